@@ -0,0 +1,120 @@
+use std::ops::{Bound, Deref, RangeBounds};
+use std::sync::Arc;
+
+// An immutable, reference-counted byte slice. Clones and slices share the
+// underlying storage.
+#[derive(Clone)]
+pub struct Buffer {
+    owner: Arc<dyn AsRef<[u8]> + Send + Sync>,
+    begin: usize,
+    end: usize,
+}
+
+impl Buffer {
+    pub fn new() -> Buffer {
+        Buffer::from_owner(Vec::new())
+    }
+
+    pub fn from_owner<T>(owner: T) -> Buffer
+    where T: AsRef<[u8]> + Send + Sync + 'static {
+        let end = owner.as_ref().len();
+        Buffer {
+            owner: Arc::new(owner),
+            begin: 0,
+            end: end,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.begin
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.begin == self.end
+    }
+
+    pub fn slice<R>(&self, range: R) -> Buffer where R: RangeBounds<usize> {
+        let begin = match range.start_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&n) => n + 1,
+            Bound::Excluded(&n) => n,
+            Bound::Unbounded => self.len(),
+        };
+        assert!(begin <= end && end <= self.len());
+        Buffer {
+            owner: self.owner.clone(),
+            begin: self.begin + begin,
+            end: self.begin + end,
+        }
+    }
+
+    pub fn advance(&mut self, count: usize) {
+        assert!(count <= self.len());
+        self.begin += count;
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len() {
+            self.end = self.begin + len;
+        }
+    }
+} // impl Buffer
+
+impl Default for Buffer {
+    fn default() -> Buffer {
+        Buffer::new()
+    }
+} // impl Default for Buffer
+
+impl Deref for Buffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &(*self.owner).as_ref()[self.begin..self.end]
+    }
+} // impl Deref for Buffer
+
+impl AsRef<[u8]> for Buffer {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+} // impl AsRef<[u8]> for Buffer
+
+impl From<Vec<u8>> for Buffer {
+    fn from(data: Vec<u8>) -> Buffer {
+        Buffer::from_owner(data)
+    }
+} // impl From<Vec<u8>> for Buffer
+
+impl From<&'static [u8]> for Buffer {
+    fn from(data: &'static [u8]) -> Buffer {
+        Buffer::from_owner(data)
+    }
+} // impl From<&'static [u8]> for Buffer
+
+impl From<String> for Buffer {
+    fn from(data: String) -> Buffer {
+        Buffer::from_owner(data.into_bytes())
+    }
+} // impl From<String> for Buffer
+
+impl PartialEq for Buffer {
+    fn eq(&self, other: &Buffer) -> bool {
+        **self == **other
+    }
+} // impl PartialEq for Buffer
+
+impl Eq for Buffer {}
+
+impl std::fmt::Debug for Buffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Buffer")
+         .field("begin", &self.begin)
+         .field("end", &self.end)
+         .finish()
+    }
+} // impl std::fmt::Debug for Buffer
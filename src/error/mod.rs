@@ -1,4 +1,6 @@
-use std::io::{Error, ErrorKind};
+use std::io::Error;
+
+use crate::UID;
 
 pub fn badf() -> Error {
     Error::from_raw_os_error(libc::EBADF)
@@ -20,6 +22,127 @@ pub fn nospc() -> Error {
     Error::from_raw_os_error(libc::ENOSPC)
 }
 
+pub fn timedout() -> Error {
+    Error::from_raw_os_error(libc::ETIMEDOUT)
+}
+
 pub fn is_again(err: &Error) -> bool {
-    matches!(err.kind(), ErrorKind::WouldBlock)
+    errno(err) == Some(libc::EAGAIN)
+}
+
+// The errno of an error, whether it is a raw OS error or an AtenError
+// wrapped in an Error.
+pub fn errno(err: &Error) -> Option<i32> {
+    err.raw_os_error().or_else(|| details(err).map(AtenError::errno))
+}
+
+pub fn details(err: &Error) -> Option<&AtenError> {
+    err.get_ref().and_then(|inner| inner.downcast_ref::<AtenError>())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    BadFd,
+    Again,
+    Invalid,
+    Protocol,
+    NoSpace,
+    TimedOut,
+    Other,
+}
+
+impl Kind {
+    pub fn of(errno: i32) -> Kind {
+        match errno {
+            libc::EBADF => Kind::BadFd,
+            libc::EAGAIN => Kind::Again,
+            libc::EINVAL => Kind::Invalid,
+            libc::EPROTO => Kind::Protocol,
+            libc::ENOSPC => Kind::NoSpace,
+            libc::ETIMEDOUT => Kind::TimedOut,
+            _ => Kind::Other,
+        }
+    }
+
+    pub fn errno(&self) -> i32 {
+        match self {
+            Kind::BadFd => libc::EBADF,
+            Kind::Again => libc::EAGAIN,
+            Kind::Invalid => libc::EINVAL,
+            Kind::Protocol => libc::EPROTO,
+            Kind::NoSpace => libc::ENOSPC,
+            Kind::TimedOut => libc::ETIMEDOUT,
+            Kind::Other => libc::EIO,
+        }
+    }
+} // impl Kind
+
+// An error that tells where it came from and why. It converts into an
+// Error of the same ErrorKind, and errno() still finds the errno.
+#[derive(Debug, Clone)]
+pub struct AtenError {
+    kind: Kind,
+    origin: Option<UID>,
+    context: String,
+    errno: i32,
+}
+
+impl AtenError {
+    pub fn new(kind: Kind, context: impl Into<String>) -> AtenError {
+        AtenError {
+            kind: kind,
+            origin: None,
+            context: context.into(),
+            errno: kind.errno(),
+        }
+    }
+
+    pub fn from_errno(errno: i32, context: impl Into<String>) -> AtenError {
+        AtenError {
+            kind: Kind::of(errno),
+            origin: None,
+            context: context.into(),
+            errno: errno,
+        }
+    }
+
+    // Name the component (typically a stream) that produced the error.
+    pub fn at(mut self, origin: UID) -> AtenError {
+        self.origin = Some(origin);
+        self
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub fn origin(&self) -> Option<UID> {
+        self.origin
+    }
+
+    pub fn context(&self) -> &str {
+        &self.context
+    }
+
+    pub fn errno(&self) -> i32 {
+        self.errno
+    }
+} // impl AtenError
+
+impl std::fmt::Display for AtenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(origin) = self.origin {
+            write!(f, "{}: ", origin)?;
+        }
+        write!(f, "{} ({})",
+               self.context, Error::from_raw_os_error(self.errno))
+    }
+} // impl std::fmt::Display for AtenError
+
+impl std::error::Error for AtenError {}
+
+impl From<AtenError> for Error {
+    fn from(err: AtenError) -> Error {
+        Error::new(Error::from_raw_os_error(err.errno).kind(), err)
+    }
 }
@@ -3,8 +3,11 @@
 #[macro_use]
 extern crate lazy_static;
 
+pub mod buffer;
 pub mod stream;
+pub mod sink;
 pub mod misc;
+pub mod task;
 
 use std::cell::{Ref, RefCell, RefMut};
 use std::collections::{BTreeMap, HashMap, LinkedList};
@@ -342,6 +345,7 @@ impl Disk {
             weak_disk: self.downgrade(),
             uid: event_uid,
             state: EventState::Idle,
+            events: 0,
             action: action,
             stack_trace: stack_trace,
         };
@@ -502,7 +506,7 @@ impl Disk {
         match body.registrations.get(&(epoll_events[0].u64 as RawFd)) {
             Some(event) => {
                 TRACE!(ATEN_DISK_POLL_EXECUTE { DISK: self, EVENT: &event });
-                event.trigger();
+                event.trigger_with(epoll_events[0].events);
             }
             None => {
                 TRACE!(ATEN_DISK_POLL_EXECUTE_SPURIOUS { DISK: self });
@@ -592,13 +596,14 @@ impl Disk {
                             &(epoll_events[i].u64 as RawFd)).map(
                             |event| { event.clone() }
                         );
+                        let events = epoll_events[i].events;
                         // body unborrowed
                         match event {
                             Some(event) => {
                                 TRACE!(ATEN_DISK_LOOP_EXECUTE {
                                     DISK: self, EVENT: event
                                 });
-                                event.trigger();
+                                event.trigger_with(events);
                             }
                             None => {
                                 TRACE!(ATEN_DISK_LOOP_SPURIOUS { DISK: self });
@@ -740,6 +745,13 @@ impl Disk {
         Ok(())
     }
 
+    fn registration_events(&self, fd: &Fd) -> u32 {
+        match self.body().registrations.get(&fd.as_raw_fd()) {
+            Some(event) => event.0.body.borrow().events,
+            None => 0,
+        }
+    }
+
     fn unregister(&self, fd: &Fd) {
         let result = self.mut_body().registrations.remove(&fd.as_raw_fd());
         assert!(result.is_some());
@@ -817,6 +829,13 @@ pub struct Registration {
 }
 
 impl Registration {
+    // The epoll event flags reported for the fd since it was registered.
+    pub fn events(&self) -> u32 {
+        self.weak_disk.upped(|disk| {
+            disk.registration_events(&self.fd)
+        }).unwrap_or(0)
+    }
+
     pub fn modify_old_school(&self, readable: bool, writable: bool)
                              -> Option<Result<()>> {
         self.weak_disk.upped(|disk| {
@@ -880,6 +899,7 @@ struct EventBody {
     weak_disk: WeakDisk,
     uid: UID,
     state: EventState,
+    events: u32,
     action: Action,
     stack_trace: Option<String>,
 }
@@ -951,6 +971,11 @@ impl Event {
         self.0.body.borrow_mut().trigger(self.downgrade());
     }
 
+    pub fn trigger_with(&self, events: u32) {
+        self.0.body.borrow_mut().events |= events;
+        self.trigger();
+    }
+
     pub fn cancel(&self) {
         self.0.body.borrow_mut().cancel();
     }
@@ -1,32 +1,72 @@
 use std::rc::{Rc, Weak};
 use std::cell::RefCell;
-use std::io::Result;
+use std::io::{Error, Result};
+use std::net::Shutdown;
+use std::os::unix::io::AsRawFd;
 
-use crate::{Disk, Link, UID, Action, Registration, Fd};
+use crate::{Disk, Link, UID, Action, Registration, Fd, error};
 use crate::{Downgradable, Upgradable, DECLARE_LINKS};
 use crate::stream::{ByteStream, ByteStreamBody, DebuggableByteStreamBody};
 use crate::stream::{ByteStreamPair, ByteStreamPairBody};
 use crate::stream::{DebuggableByteStreamPairBody};
 use crate::stream::{BasicStream, BasicStreamBody};
 use crate::stream::{base, switch, file, dry};
+use crate::sink::{self, BasicSink};
 use crate::misc::Linger;
+use crate::misc::linger::State;
 use r3::{TRACE, Traceable};
 
 #[derive(Debug)]
 pub struct DuplexBody {
     base: base::StreamBody,
     weak_self: Weak<RefCell<DuplexBody>>,
+    fd: Fd,
     ingress: ByteStream,
     egress: Option<Linger>,
     eswitch: Option<switch::Stream>,
+    esink: sink::file::Sink,
+    egress_state: State,
+    egress_errno: Option<i32>,
+    egress_callback: Action,
     registration: Option<Registration>,
 }
 
 impl DuplexBody {
     fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.base.is_closed() {
+            return Err(error::badf());
+        }
+        if let Some(errno) = self.egress_errno {
+            return Err(Error::from_raw_os_error(errno));
+        }
         self.ingress.read(buf)
     }
 
+    fn egress_done(&mut self) {
+        let result =
+            match self.egress.as_ref().map(|egress| egress.poll()) {
+                Some(State::Final(result)) => result,
+                _ => { return; }
+            };
+        match &result {
+            Ok(()) => {
+                TRACE!(ATEN_DUPLEX_EGRESS_DONE { DUPLEX: self });
+            }
+            Err(err) => {
+                TRACE!(ATEN_DUPLEX_EGRESS_FAIL {
+                    DUPLEX: self, ERR: r3::errsym(err)
+                });
+                self.egress_errno =
+                    Some(error::errno(err).unwrap_or(libc::EIO));
+                self.base.invoke_callback();
+            }
+        }
+        self.egress_state = State::Final(result);
+        self.base.get_weak_disk().upped(|disk| {
+            disk.execute(self.egress_callback.clone());
+        });
+    }
+
     fn notify(&self) {
         self.base.invoke_callback();
         if let Some(egress) = &self.egress {
@@ -48,6 +88,27 @@ impl ByteStreamBody for DuplexBody {
         self.base.unregister_callback();
     }
 
+    fn kind(&self) -> &'static str {
+        crate::stream::kind_of(std::any::type_name::<Self>())
+    }
+
+    fn wrappees(&self) -> Vec<ByteStream> {
+        vec![self.ingress.clone()]
+    }
+
+    // Closing the duplex also abandons the egress.
+    fn close(&mut self) {
+        if self.base.is_closed() {
+            return;
+        }
+        self.base.close();
+        self.ingress.close();
+        if let Some(egress) = &self.egress {
+            egress.abort();
+        }
+        self.registration = None;
+    }
+
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         if let Ok(_) = self.base.read(buf) {
             TRACE!(ATEN_DUPLEX_READ_TRIVIAL { STREAM: self, WANT: buf.len() });
@@ -87,11 +148,51 @@ impl ByteStreamPairBody for DuplexBody {
     }
 
     fn set_egress(&mut self, egress: ByteStream) {
-        if let Some(eswitch) = &self.eswitch {
-            TRACE!(ATEN_DUPLEX_SET_EGRESS { DUPLEX: self, EGRESS: egress });
-            eswitch.switch(egress);
+        let eswitch =
+            match &self.eswitch {
+                Some(eswitch) => eswitch.clone(),
+                None => { return; }
+            };
+        TRACE!(ATEN_DUPLEX_SET_EGRESS { DUPLEX: self, EGRESS: egress });
+        match self.base.get_weak_disk().upgrade() {
+            Some(disk) => {
+                // The egress may well be our own ingress, so the switch
+                // takes place only once the body is no longer borrowed.
+                let weak_eswitch = eswitch.downgrade();
+                disk.execute(Action::new(move || {
+                    weak_eswitch.upped(|eswitch| {
+                        eswitch.switch(egress.clone());
+                    });
+                }));
+            }
+            None => {
+                // No main loop is left to defer the switch to.
+                eswitch.switch(egress);
+            }
+        }
+    }
+
+    fn poll_egress(&mut self) -> State {
+        match &self.egress_state {
+            State::Busy | State::Drifting => State::Busy,
+            State::Stale => State::Stale,
+            State::Final(_) => {
+                std::mem::replace(&mut self.egress_state, State::Stale)
+            }
         }
     }
+
+    fn register_egress_callback(&mut self, callback: Action) {
+        TRACE!(ATEN_DUPLEX_REGISTER_EGRESS_CALLBACK {
+            DUPLEX: self, ACTION: &callback
+        });
+        self.egress_callback = callback;
+    }
+
+    fn unregister_egress_callback(&mut self) {
+        TRACE!(ATEN_DUPLEX_UNREGISTER_EGRESS_CALLBACK { DUPLEX: self });
+        self.egress_callback = Action::noop();
+    }
 } // impl ByteStreamPairBody for DuplexBody
 
 impl DebuggableByteStreamPairBody for DuplexBody {}
@@ -111,16 +212,22 @@ impl Duplex {
             &disk, dry::Stream::new(&disk).as_bytestream());
         let ingress = file::Stream::new(
             &disk, &fd, true).unwrap().as_bytestream();
-        let egress = Linger::new(
-            &disk, eswitch.as_bytestream(), &fd, true).unwrap();
+        let esink = sink::file::Sink::new(disk, fd, true)?;
+        let egress = Linger::with_sink(
+            &disk, eswitch.as_bytestream(), esink.as_bytesink());
         let body = Rc::new_cyclic(
             |weak_self| RefCell::new(
                 DuplexBody {
                     base: base::StreamBody::new(disk.downgrade(), uid),
                     weak_self: weak_self.clone(),
+                    fd: fd.clone(),
                     ingress: ingress.clone(),
                     egress: Some(egress.clone()),
                     eswitch: Some(eswitch),
+                    esink: esink,
+                    egress_state: State::Busy,
+                    egress_errno: None,
+                    egress_callback: Action::noop(),
                     registration: None,
                 }
             ));
@@ -129,10 +236,18 @@ impl Duplex {
             body: body,
         });
         let weak_duplex = duplex.downgrade();
+        egress.register_callback(Action::new(move || {
+            weak_duplex.upped(|duplex| {
+                duplex.0.body.borrow_mut().egress_done();
+            });
+        }));
+        let weak_duplex = duplex.downgrade();
         let notify = Action::new(move || {
             weak_duplex.upped(|duplex| { duplex.notify(); });
         });
-        match disk.register(fd, notify) {
+        let flags = libc::EPOLLIN | libc::EPOLLOUT | libc::EPOLLRDHUP |
+            libc::EPOLLET;
+        match disk.register_with_flags(fd, flags as u32, notify) {
             Ok(registration) => {
                 duplex.0.body.borrow_mut().registration =
                     Some(registration);
@@ -159,6 +274,79 @@ impl Duplex {
         self.0.body.borrow_mut().set_egress(egress);
     }
 
+    pub fn shutdown(&self, how: Shutdown) -> Result<()> {
+        let body = self.0.body.borrow();
+        let how_code = match how {
+            Shutdown::Read => libc::SHUT_RD,
+            Shutdown::Write => libc::SHUT_WR,
+            Shutdown::Both => libc::SHUT_RDWR,
+        };
+        let status = unsafe {
+            libc::shutdown(body.fd.as_raw_fd(), how_code)
+        };
+        if status < 0 {
+            let err = Error::last_os_error();
+            TRACE!(ATEN_DUPLEX_SHUTDOWN_FAIL {
+                DUPLEX: self, HOW: format!("{:?}", how),
+                ERR: r3::errsym(&err),
+            });
+            return Err(err);
+        }
+        TRACE!(ATEN_DUPLEX_SHUTDOWN {
+            DUPLEX: self, HOW: format!("{:?}", how),
+        });
+        Ok(())
+    }
+
+    // What the peer has shut down: Write means the peer half-closed its
+    // side, Both that it hung up.
+    pub fn peer_shutdown(&self) -> Option<Shutdown> {
+        let body = self.0.body.borrow();
+        let mut events =
+            match &body.registration {
+                Some(registration) => registration.events(),
+                None => 0,
+            };
+        let mut pollfd = libc::pollfd {
+            fd: body.fd.as_raw_fd(),
+            events: libc::POLLRDHUP,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut pollfd, 1, 0) } > 0 {
+            if pollfd.revents & libc::POLLHUP != 0 {
+                events |= libc::EPOLLHUP as u32;
+            }
+            if pollfd.revents & libc::POLLRDHUP != 0 {
+                events |= libc::EPOLLRDHUP as u32;
+            }
+        }
+        if events & libc::EPOLLHUP as u32 != 0 {
+            Some(Shutdown::Both)
+        } else if events & libc::EPOLLRDHUP as u32 != 0 {
+            Some(Shutdown::Write)
+        } else {
+            None
+        }
+    }
+
+    // Shut down the write side of the fd once the egress stream has
+    // been transmitted in full.
+    pub fn set_shutdown_on_egress_eof(&self, enabled: bool) {
+        self.0.body.borrow().esink.set_shutdown_on_close(enabled);
+    }
+
+    pub fn poll_egress(&self) -> State {
+        self.0.body.borrow_mut().poll_egress()
+    }
+
+    pub fn register_egress_callback(&self, callback: Action) {
+        self.0.body.borrow_mut().register_egress_callback(callback);
+    }
+
+    pub fn unregister_egress_callback(&self) {
+        self.0.body.borrow_mut().unregister_egress_callback();
+    }
+
     pub fn as_bytestream_pair(&self) -> ByteStreamPair {
         ByteStreamPair::new(self.0.uid, self.0.body.clone())
     }
@@ -1,11 +1,14 @@
 use std::rc::Rc;
 use std::cell::RefCell;
-use std::io::{Error, Result};
+use std::io::{Error, Result, IoSlice};
 use std::os::unix::io::AsRawFd;
+use std::task::Poll;
 
-use crate::{Disk, WeakDisk, Link, UID, Action, Registration, Fd};
+use crate::{Disk, WeakDisk, Link, UID, Action, Fd};
 use crate::{Downgradable, Upgradable, error, DECLARE_LINKS};
 use crate::stream::ByteStream;
+use crate::sink::{ByteSink, BasicSink, file};
+use crate::task::Trigger;
 use r3::{TRACE, Traceable};
 
 #[derive(Debug)]
@@ -22,19 +25,37 @@ impl State {
     }
 } // impl State
 
+// Moving data from fd to fd inside the kernel.
+#[derive(Debug)]
+enum Shortcut {
+    Undecided,
+    Sendfile,
+    Splice,
+    Unavailable,
+}
+
+fn file_type(fd: &Fd) -> Option<libc::mode_t> {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } < 0 {
+        return None;
+    }
+    Some(stat.st_mode & libc::S_IFMT)
+}
+
 #[derive(Debug)]
 pub struct LingerBody {
     weak_disk: WeakDisk,
     uid: UID,
     source: ByteStream,
-    dest: Fd,
+    dest: ByteSink,
     buf: Vec<u8>,
     cursor: usize,
     length: usize,
+    exhausted: bool,
+    shortcut: Shortcut,
     callback: Action,
     state: State,
     self_ref: Option<Rc<RefCell<LingerBody>>>,
-    registration: Option<Registration>,
 }
 
 impl LingerBody {
@@ -42,6 +63,105 @@ impl LingerBody {
         self.source.read(&mut self.buf)
     }
 
+    fn choose_shortcut(&self) -> Shortcut {
+        let (src, dst) =
+            match (self.source.splice_source(), self.dest.splice_dest()) {
+                (Some(src), Some(dst)) => (src, dst),
+                _ => { return Shortcut::Unavailable; }
+            };
+        match (file_type(&src.fd), file_type(&dst)) {
+            (Some(libc::S_IFREG), Some(_)) => Shortcut::Sendfile,
+            (Some(libc::S_IFIFO), Some(_)) |
+            (Some(_), Some(libc::S_IFIFO)) => Shortcut::Splice,
+            _ => Shortcut::Unavailable,
+        }
+    }
+
+    // Returns None if the source and the destination cannot be connected
+    // inside the kernel.
+    fn transfer(&mut self) -> Option<Result<usize>> {
+        const CHUNK_SIZE: u64 = 1 << 20;
+        if let Shortcut::Undecided = self.shortcut {
+            self.shortcut = self.choose_shortcut();
+            TRACE!(ATEN_LINGER_SHORTCUT {
+                LINGER: self.uid, SHORTCUT: format!("{:?}", self.shortcut),
+            });
+        }
+        if let Shortcut::Unavailable = self.shortcut {
+            return None;
+        }
+        let (src, dst) =
+            match (self.source.splice_source(), self.dest.splice_dest()) {
+                (Some(src), Some(dst)) => (src, dst),
+                _ => { return None; }
+            };
+        let chunk = src.limit.unwrap_or(CHUNK_SIZE).min(CHUNK_SIZE) as usize;
+        if chunk == 0 {
+            return Some(Ok(0));
+        }
+        let mut offset = src.offset.map(|offset| offset as libc::off_t);
+        let offset_ptr =
+            match &mut offset {
+                Some(offset) => offset as *mut libc::off_t,
+                None => std::ptr::null_mut(),
+            };
+        let count =
+            match self.shortcut {
+                Shortcut::Sendfile => unsafe {
+                    libc::sendfile(dst.as_raw_fd(), src.fd.as_raw_fd(),
+                                   offset_ptr, chunk)
+                },
+                Shortcut::Splice => unsafe {
+                    libc::splice(src.fd.as_raw_fd(), offset_ptr,
+                                 dst.as_raw_fd(), std::ptr::null_mut(),
+                                 chunk,
+                                 libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK)
+                },
+                _ => { return None; }
+            };
+        if count < 0 {
+            let err = Error::last_os_error();
+            if matches!(err.raw_os_error(),
+                        Some(libc::EINVAL) | Some(libc::ENOSYS)) {
+                TRACE!(ATEN_LINGER_SHORTCUT_UNAVAILABLE { LINGER: self.uid });
+                self.shortcut = Shortcut::Unavailable;
+                return None;
+            }
+            return Some(Err(err));
+        }
+        if count == 0 {
+            self.shortcut = Shortcut::Unavailable;
+            return Some(Ok(0));
+        }
+        self.source.consume(count as usize);
+        Some(Ok(count as usize))
+    }
+
+    // Write straight from the source's lent buffers. Returns None if the
+    // source does not lend, in which case the staging buffer is used.
+    fn write_lent(&mut self) -> Option<Result<usize>> {
+        const MAX_IOVECS: usize = 64;
+        let buffers =
+            match self.source.peek() {
+                Ok(Some(buffers)) => buffers,
+                Ok(None) => { return None; }
+                Err(err) => { return Some(Err(err)); }
+            };
+        if buffers.is_empty() {
+            return Some(Ok(0));
+        }
+        let slices: Vec<IoSlice> = buffers.iter().take(MAX_IOVECS).map(
+            |buffer| IoSlice::new(buffer)).collect();
+        match self.dest.write_vectored(&slices) {
+            Ok(count) => {
+                assert!(count > 0);
+                self.source.consume(count);
+                Some(Ok(count))
+            }
+            Err(err) => Some(Err(err)),
+        }
+    }
+
     fn done(&mut self, result: Result<()>) {
         TRACE!(ATEN_LINGER_JOCKEY_DONE { LINGER: self.uid });
         if matches!(self.state, State::Drifting) {
@@ -54,7 +174,6 @@ impl LingerBody {
     }
 
     fn consume(&mut self) -> State {
-        self.registration = None;
         self.self_ref = None;
         self.state.consume()
     }
@@ -91,6 +210,21 @@ DECLARE_LINKS!(Linger, WeakLinger, LingerBody, ATEN_LINGER_UPPED_MISS, LINGER);
 impl Linger {
     pub fn new(disk: &Disk, source: ByteStream, dest: &Fd, sync: bool)
                -> Result<Linger> {
+        match file::Sink::new(disk, dest, sync) {
+            Ok(sink) => {
+                Ok(Self::with_sink(disk, source, sink.as_bytesink()))
+            }
+            Err(err) => {
+                TRACE!(ATEN_LINGER_CREATE_FAIL {
+                    DISK: disk, ERR: r3::errsym(&err)
+                });
+                Err(err)
+            }
+        }
+    }
+
+    pub fn with_sink(disk: &Disk, source: ByteStream, dest: ByteSink)
+                     -> Linger {
         const BUF_SIZE: usize = 10000;
         let uid = UID::new();
         let body = LingerBody {
@@ -101,10 +235,11 @@ impl Linger {
             buf: vec![0; BUF_SIZE],
             cursor: 0,
             length: 0,
+            exhausted: false,
+            shortcut: Shortcut::Undecided,
             callback: Action::noop(),
             state: State::Busy,
             self_ref: None,
-            registration: None,
         };
         let self_ref = Rc::new(RefCell::new(body));
         self_ref.borrow_mut().self_ref = Some(self_ref.clone());
@@ -112,30 +247,16 @@ impl Linger {
             uid: uid,
             body: self_ref,
         });
-        if !sync {
-            let weak_linger = linger.downgrade();
-            let jockey = Action::new(move || {
-                weak_linger.upped(|linger| { linger.jockey(); });
-            });
-            match disk.register(dest, jockey) {
-                Ok(registration) => {
-                    linger.0.body.borrow_mut().registration =
-                        Some(registration);
-                }
-                Err(err) => {
-                    TRACE!(ATEN_LINGER_CREATE_FAIL {
-                        DISK: disk, ERR: r3::errsym(&err)
-                    });
-                    return Err(err);
-                }
-            }
-        }
-        TRACE!(ATEN_LINGER_CREATE { DISK: disk, LINGER: uid, SYNC: sync });
+        TRACE!(ATEN_LINGER_CREATE { DISK: disk, LINGER: uid, SINK: dest });
+        let weak_linger = linger.downgrade();
+        dest.register_callback(Action::new(move || {
+            weak_linger.upped(|linger| { linger.jockey(); });
+        }));
         let weak_linger = linger.downgrade();
         source.register_callback(Action::new(move || {
             weak_linger.upped(|linger| { linger.jockey(); });
         }));
-        Ok(linger)
+        linger
     }
 
     pub fn register_callback(&self, callback: Action) {
@@ -178,6 +299,25 @@ impl Linger {
         }
     }
 
+    // Resolves to the final result. The linger's callback is taken over
+    // for the duration.
+    pub async fn completion(&self) -> Result<()> {
+        let trigger = Trigger::new();
+        self.register_callback(trigger.action());
+        let result = std::future::poll_fn(|context| {
+            match self.poll() {
+                State::Final(result) => Poll::Ready(result),
+                State::Stale => Poll::Ready(Err(error::badf())),
+                State::Busy | State::Drifting => {
+                    trigger.park(context);
+                    Poll::Pending
+                }
+            }
+        }).await;
+        self.unregister_callback();
+        result
+    }
+
     pub fn abort(&self) -> State {
         let state = self.0.body.borrow_mut().consume();
         match &state {
@@ -207,7 +347,8 @@ impl Linger {
     }
 
     fn jockey(&self) {
-        if !matches!(self.0.body.borrow().state, State::Busy) {
+        if !matches!(self.0.body.borrow().state,
+                     State::Busy | State::Drifting) {
             TRACE!(ATEN_LINGER_JOCKEY_SPURIOUS { LINGER: self });
             return;
         }
@@ -215,29 +356,91 @@ impl Linger {
         loop {
             while body.cursor < body.length {
                 let slice = &body.buf[body.cursor..body.length];
-                let count = unsafe {
-                    libc::write(body.dest.as_raw_fd(),
-                                slice.as_ptr() as *const libc::c_void,
-                                slice.len())
-                };
-                if count < 0 {
-                    let err = Error::last_os_error();
-                    TRACE!(ATEN_LINGER_JOCKEY_WRITE_FAIL {
-                        LINGER: self, WANT: slice.len(), ERR: r3::errsym(&err),
-                    });
-                    if !error::is_again(&err) {
-                        body.done(Err(err));
-                    }
-                    return;
-                }
+                let count =
+                    match body.dest.write(slice) {
+                        Ok(count) => count,
+                        Err(err) => {
+                            TRACE!(ATEN_LINGER_JOCKEY_WRITE_FAIL {
+                                LINGER: self, WANT: slice.len(),
+                                ERR: r3::errsym(&err),
+                            });
+                            if !error::is_again(&err) {
+                                body.done(Err(err));
+                            }
+                            return;
+                        }
+                    };
                 TRACE!(ATEN_LINGER_JOCKEY_WRITE {
                     LINGER: self, WANT: slice.len(), GOT: count,
                 });
                 TRACE!(ATEN_LINGER_JOCKEY_WRITE_DUMP {
-                    LINGER: self, DATA: r3::octets(&slice[..count as usize]),
+                    LINGER: self, DATA: r3::octets(&slice[..count]),
                 });
                 assert!(count > 0);
-                body.cursor += count as usize;
+                body.cursor += count;
+            }
+            if body.exhausted {
+                match body.dest.close() {
+                    Ok(()) => {
+                        TRACE!(ATEN_LINGER_JOCKEY_CLOSE { LINGER: self });
+                        body.done(Ok(()));
+                    }
+                    Err(err) => {
+                        TRACE!(ATEN_LINGER_JOCKEY_CLOSE_FAIL {
+                            LINGER: self, ERR: r3::errsym(&err),
+                        });
+                        if !error::is_again(&err) {
+                            body.done(Err(err));
+                        }
+                    }
+                }
+                return;
+            }
+            match body.transfer() {
+                Some(Ok(0)) => {
+                    TRACE!(ATEN_LINGER_JOCKEY_TRANSFER_EOF { LINGER: self });
+                    body.exhausted = true;
+                    continue;
+                }
+                Some(Ok(count)) => {
+                    TRACE!(ATEN_LINGER_JOCKEY_TRANSFER {
+                        LINGER: self, GOT: count,
+                    });
+                    continue;
+                }
+                Some(Err(err)) => {
+                    TRACE!(ATEN_LINGER_JOCKEY_TRANSFER_FAIL {
+                        LINGER: self, ERR: r3::errsym(&err),
+                    });
+                    if !error::is_again(&err) {
+                        body.done(Err(err));
+                    }
+                    return;
+                }
+                None => {}
+            }
+            match body.write_lent() {
+                Some(Ok(0)) => {
+                    TRACE!(ATEN_LINGER_JOCKEY_WRITEV_EOF { LINGER: self });
+                    body.exhausted = true;
+                    continue;
+                }
+                Some(Ok(count)) => {
+                    TRACE!(ATEN_LINGER_JOCKEY_WRITEV {
+                        LINGER: self, GOT: count,
+                    });
+                    continue;
+                }
+                Some(Err(err)) => {
+                    TRACE!(ATEN_LINGER_JOCKEY_WRITEV_FAIL {
+                        LINGER: self, ERR: r3::errsym(&err),
+                    });
+                    if !error::is_again(&err) {
+                        body.done(Err(err));
+                    }
+                    return;
+                }
+                None => {}
             }
             match body.replenish() {
                 Ok(count) => {
@@ -245,8 +448,8 @@ impl Linger {
                         LINGER: self, GOT: count,
                     });
                     if count == 0 {
-                        body.done(Ok(()));
-                        return;
+                        body.exhausted = true;
+                        continue;
                     }
                     body.cursor = 0;
                     assert!(count <= body.buf.len());
@@ -0,0 +1,475 @@
+use std::rc::{Rc, Weak};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{Error, Result};
+use std::time::{Duration, Instant};
+
+use crate::{Disk, Link, UID, Action, Downgradable, Upgradable, error};
+use crate::DECLARE_LINKS;
+use crate::buffer::Buffer;
+use crate::stream::{ByteStream, ByteStreamBody, DebuggableByteStreamBody};
+use crate::stream::{ByteStreamPair, ByteStreamPairBody};
+use crate::stream::{DebuggableByteStreamPairBody};
+use crate::stream::{BasicStream, BasicStreamBody, base};
+use crate::misc::linger::State;
+use r3::{TRACE, Traceable};
+
+// Applies to both directions. Each egress read is at most max_chunk
+// bytes, and every such chunk is delivered to the peer as a separate
+// read.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub capacity: usize,
+    pub latency: Duration,
+    pub max_chunk: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            capacity: 65536,
+            latency: Duration::ZERO,
+            max_chunk: None,
+        }
+    }
+} // impl Default for Config
+
+#[derive(Debug)]
+struct Segment {
+    ready_at: Instant,
+    data: Option<Buffer>,       // None: end of stream
+}
+
+// One direction of the loopback.
+#[derive(Debug)]
+struct Channel {
+    segments: VecDeque<Segment>,
+    buffered: usize,
+    finished: bool,
+    reader_gone: bool,
+    reader_notify: Action,
+    writer_notify: Action,
+}
+
+impl Channel {
+    fn new() -> Channel {
+        Channel {
+            segments: VecDeque::new(),
+            buffered: 0,
+            finished: false,
+            reader_gone: false,
+            reader_notify: Action::noop(),
+            writer_notify: Action::noop(),
+        }
+    }
+} // impl Channel
+
+#[derive(Debug)]
+pub struct LoopbackBody {
+    base: base::StreamBody,
+    weak_self: Weak<RefCell<LoopbackBody>>,
+    config: Config,
+    inbound: Rc<RefCell<Channel>>,
+    outbound: Rc<RefCell<Channel>>,
+    egress: Option<ByteStream>,
+    egress_state: State,
+    egress_errno: Option<i32>,
+    egress_callback: Action,
+}
+
+impl LoopbackBody {
+    fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.base.is_closed() {
+            return Err(error::badf());
+        }
+        if let Some(errno) = self.egress_errno {
+            return Err(Error::from_raw_os_error(errno));
+        }
+        let now =
+            match self.base.get_weak_disk().upgrade() {
+                Some(disk) => disk.now(),
+                None => { return Err(error::badf()); }
+            };
+        let mut inbound = self.inbound.borrow_mut();
+        let segment =
+            match inbound.segments.front_mut() {
+                Some(segment) if segment.ready_at <= now => segment,
+                _ => { return Err(error::again()); }
+            };
+        let data =
+            match &mut segment.data {
+                Some(data) => data,
+                None => { return Ok(0); }
+            };
+        let count = buf.len().min(data.len());
+        buf[..count].copy_from_slice(&data[..count]);
+        data.advance(count);
+        if data.is_empty() {
+            inbound.segments.pop_front();
+        }
+        inbound.buffered -= count;
+        if count > 0 {
+            self.base.get_weak_disk().upped(|disk| {
+                disk.execute(inbound.writer_notify.clone());
+            });
+        }
+        Ok(count)
+    }
+
+    fn egress_done(&mut self, result: Result<()>) {
+        match &result {
+            Ok(()) => {
+                TRACE!(ATEN_LOOPBACK_EGRESS_DONE { LOOPBACK: self });
+            }
+            Err(err) => {
+                TRACE!(ATEN_LOOPBACK_EGRESS_FAIL {
+                    LOOPBACK: self, ERR: r3::errsym(err)
+                });
+                self.egress_errno =
+                    Some(error::errno(err).unwrap_or(libc::EIO));
+                self.base.invoke_callback();
+            }
+        }
+        self.egress = None;
+        self.egress_state = State::Final(result);
+        self.base.get_weak_disk().upped(|disk| {
+            disk.execute(self.egress_callback.clone());
+        });
+    }
+} // impl LoopbackBody
+
+impl ByteStreamBody for LoopbackBody {
+    fn register_callback(&mut self, callback: Action) {
+        TRACE!(ATEN_LOOPBACK_REGISTER_CALLBACK {
+            STREAM: self, ACTION: &callback
+        });
+        self.base.register_callback(callback);
+    }
+
+    fn unregister_callback(&mut self) {
+        TRACE!(ATEN_LOOPBACK_UNREGISTER_CALLBACK { STREAM: self });
+        self.base.unregister_callback();
+    }
+
+    fn kind(&self) -> &'static str {
+        crate::stream::kind_of(std::any::type_name::<Self>())
+    }
+
+    // Closing the ingress discards whatever the peer has sent and makes
+    // its further writes fail.
+    fn close(&mut self) {
+        if self.base.is_closed() {
+            return;
+        }
+        self.base.close();
+        let mut inbound = self.inbound.borrow_mut();
+        inbound.reader_gone = true;
+        inbound.segments.clear();
+        inbound.buffered = 0;
+        self.base.get_weak_disk().upped(|disk| {
+            disk.execute(inbound.writer_notify.clone());
+        });
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.base.read(buf).is_ok() {
+            TRACE!(ATEN_LOOPBACK_READ_TRIVIAL {
+                STREAM: self, WANT: buf.len()
+            });
+            return Ok(0);
+        }
+        match self.read_nontrivial(buf) {
+            Ok(count) => {
+                TRACE!(ATEN_LOOPBACK_READ {
+                    STREAM: self, WANT: buf.len(), GOT: count
+                });
+                TRACE!(ATEN_LOOPBACK_READ_DUMP {
+                    STREAM: self, DATA: r3::octets(&buf[..count])
+                });
+                Ok(count)
+            }
+            Err(err) => {
+                TRACE!(ATEN_LOOPBACK_READ_FAIL {
+                    STREAM: self, WANT: buf.len(), ERR: r3::errsym(&err)
+                });
+                Err(err)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for LoopbackBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.base.get_uid())
+    }
+} // impl std::fmt::Display for LoopbackBody
+
+impl DebuggableByteStreamBody for LoopbackBody {}
+
+impl ByteStreamPairBody for LoopbackBody {
+    fn get_ingress(&self) -> Option<ByteStream> {
+        self.weak_self.upgrade().map(
+            |s| ByteStream::new(self.base.get_uid(), s))
+    }
+
+    fn set_egress(&mut self, egress: ByteStream) {
+        if !matches!(self.egress_state, State::Busy) {
+            TRACE!(ATEN_LOOPBACK_SET_EGRESS_LATE {
+                LOOPBACK: self, EGRESS: egress
+            });
+            return;
+        }
+        TRACE!(ATEN_LOOPBACK_SET_EGRESS { LOOPBACK: self, EGRESS: egress });
+        // The egress may well be our own ingress, so the callbacks are
+        // swapped only once the body is no longer borrowed.
+        let previous = self.egress.replace(egress);
+        let weak_body = self.weak_self.clone();
+        let uid = self.base.get_uid();
+        self.base.get_weak_disk().upped(|disk| {
+            let previous = previous.clone();
+            let weak_body = weak_body.clone();
+            disk.execute(Action::new(move || {
+                if let Some(previous) = &previous {
+                    previous.unregister_callback();
+                }
+                if let Some(body) = weak_body.upgrade() {
+                    Loopback(Link { uid: uid, body: body }).attach();
+                }
+            }));
+        });
+    }
+
+    fn poll_egress(&mut self) -> State {
+        match &self.egress_state {
+            State::Busy | State::Drifting => State::Busy,
+            State::Stale => State::Stale,
+            State::Final(_) => {
+                std::mem::replace(&mut self.egress_state, State::Stale)
+            }
+        }
+    }
+
+    fn register_egress_callback(&mut self, callback: Action) {
+        TRACE!(ATEN_LOOPBACK_REGISTER_EGRESS_CALLBACK {
+            LOOPBACK: self, ACTION: &callback
+        });
+        self.egress_callback = callback;
+    }
+
+    fn unregister_egress_callback(&mut self) {
+        TRACE!(ATEN_LOOPBACK_UNREGISTER_EGRESS_CALLBACK { LOOPBACK: self });
+        self.egress_callback = Action::noop();
+    }
+} // impl ByteStreamPairBody for LoopbackBody
+
+impl DebuggableByteStreamPairBody for LoopbackBody {}
+
+impl Drop for LoopbackBody {
+    fn drop(&mut self) {
+        TRACE!(ATEN_LOOPBACK_DROP { LOOPBACK: self });
+        self.inbound.borrow_mut().reader_gone = true;
+        let mut outbound = self.outbound.borrow_mut();
+        if !outbound.finished {
+            outbound.finished = true;
+            outbound.segments.push_back(Segment {
+                ready_at: Instant::now(),
+                data: None,
+            });
+        }
+        let notifications = [
+            self.inbound.borrow().writer_notify.clone(),
+            outbound.reader_notify.clone(),
+        ];
+        self.base.get_weak_disk().upped(|disk| {
+            for notification in &notifications {
+                disk.execute(notification.clone());
+            }
+        });
+    }
+}
+
+DECLARE_LINKS!(Loopback, WeakLoopback, LoopbackBody,
+               ATEN_LOOPBACK_UPPED_MISS, LOOPBACK);
+
+impl Loopback {
+    // Two ends connected to each other: whatever is set as the egress of
+    // one comes out of the ingress of the other.
+    pub fn pair(disk: &Disk, config: Config) -> (Loopback, Loopback) {
+        let forward = Rc::new(RefCell::new(Channel::new()));
+        let backward = Rc::new(RefCell::new(Channel::new()));
+        let first = Self::new(disk, &config, &backward, &forward);
+        let second = Self::new(disk, &config, &forward, &backward);
+        Self::connect(&forward, &first, &second);
+        Self::connect(&backward, &second, &first);
+        TRACE!(ATEN_LOOPBACK_CREATE_PAIR {
+            DISK: disk, FIRST: first, SECOND: second,
+            CONFIG: format!("{:?}", config),
+        });
+        (first, second)
+    }
+
+    fn new(disk: &Disk,
+           config: &Config,
+           inbound: &Rc<RefCell<Channel>>,
+           outbound: &Rc<RefCell<Channel>>) -> Loopback {
+        let uid = UID::new();
+        let body = Rc::new_cyclic(
+            |weak_self| RefCell::new(
+                LoopbackBody {
+                    base: base::StreamBody::new(disk.downgrade(), uid),
+                    weak_self: weak_self.clone(),
+                    config: config.clone(),
+                    inbound: inbound.clone(),
+                    outbound: outbound.clone(),
+                    egress: None,
+                    egress_state: State::Busy,
+                    egress_errno: None,
+                    egress_callback: Action::noop(),
+                }
+            ));
+        Loopback(Link {
+            uid: uid,
+            body: body,
+        })
+    }
+
+    fn connect(channel: &Rc<RefCell<Channel>>,
+               writer: &Loopback,
+               reader: &Loopback) {
+        let mut channel = channel.borrow_mut();
+        let weak_writer = writer.downgrade();
+        channel.writer_notify = Action::new(move || {
+            weak_writer.upped(|writer| { writer.pump(); });
+        });
+        let weak_reader = reader.downgrade();
+        channel.reader_notify = Action::new(move || {
+            weak_reader.upped(|reader| { reader.invoke_callback(); });
+        });
+    }
+
+    fn attach(&self) {
+        let egress =
+            match &self.0.body.borrow().egress {
+                Some(egress) => egress.clone(),
+                None => { return; }
+            };
+        let weak_loopback = self.downgrade();
+        egress.register_callback(Action::new(move || {
+            weak_loopback.upped(|loopback| { loopback.pump(); });
+        }));
+    }
+
+    // Move data from the egress stream to the peer as long as there is
+    // room for it.
+    fn pump(&self) {
+        loop {
+            let (disk, egress, outbound, config) = {
+                let body = self.0.body.borrow();
+                let egress =
+                    match (&body.egress_state, &body.egress) {
+                        (State::Busy, Some(egress)) => egress.clone(),
+                        _ => { return; }
+                    };
+                let disk =
+                    match body.base.get_weak_disk().upgrade() {
+                        Some(disk) => disk,
+                        None => { return; }
+                    };
+                (disk, egress, body.outbound.clone(), body.config.clone())
+            };
+            if outbound.borrow().reader_gone {
+                TRACE!(ATEN_LOOPBACK_PUMP_PEER_GONE { LOOPBACK: self });
+                self.0.body.borrow_mut().egress_done(
+                    Err(Error::from_raw_os_error(libc::EPIPE)));
+                return;
+            }
+            let room =
+                config.capacity.saturating_sub(outbound.borrow().buffered);
+            if room == 0 {
+                TRACE!(ATEN_LOOPBACK_PUMP_FULL { LOOPBACK: self });
+                return;
+            }
+            let want = config.max_chunk.unwrap_or(room).min(room);
+            let mut buf = vec![0u8; want];
+            let result = egress.read(&mut buf);
+            let ready_at = disk.now() + config.latency;
+            match result {
+                Ok(0) => {
+                    TRACE!(ATEN_LOOPBACK_PUMP_EOF { LOOPBACK: self });
+                    Self::deliver(&disk, &outbound, ready_at, None);
+                    self.0.body.borrow_mut().egress_done(Ok(()));
+                    return;
+                }
+                Ok(count) => {
+                    TRACE!(ATEN_LOOPBACK_PUMP { LOOPBACK: self, GOT: count });
+                    buf.truncate(count);
+                    Self::deliver(&disk, &outbound, ready_at,
+                                  Some(Buffer::from(buf)));
+                }
+                Err(err) if error::is_again(&err) => {
+                    return;
+                }
+                Err(err) => {
+                    TRACE!(ATEN_LOOPBACK_PUMP_FAIL {
+                        LOOPBACK: self, ERR: r3::errsym(&err)
+                    });
+                    Self::deliver(&disk, &outbound, ready_at, None);
+                    self.0.body.borrow_mut().egress_done(Err(err));
+                    return;
+                }
+            }
+        }
+    }
+
+    fn deliver(disk: &Disk,
+               outbound: &Rc<RefCell<Channel>>,
+               ready_at: Instant,
+               data: Option<Buffer>) {
+        let mut channel = outbound.borrow_mut();
+        match &data {
+            Some(data) => { channel.buffered += data.len(); }
+            None => { channel.finished = true; }
+        }
+        channel.segments.push_back(Segment {
+            ready_at: ready_at,
+            data: data,
+        });
+        if ready_at <= disk.now() {
+            disk.execute(channel.reader_notify.clone());
+        } else {
+            disk.schedule(ready_at, channel.reader_notify.clone());
+        }
+    }
+
+    pub fn get_ingress(&self) -> Option<ByteStream> {
+        self.0.body.borrow().get_ingress()
+    }
+
+    pub fn set_egress(&self, egress: ByteStream) {
+        self.0.body.borrow_mut().set_egress(egress);
+    }
+
+    pub fn poll_egress(&self) -> State {
+        self.0.body.borrow_mut().poll_egress()
+    }
+
+    pub fn register_egress_callback(&self, callback: Action) {
+        self.0.body.borrow_mut().register_egress_callback(callback);
+    }
+
+    pub fn unregister_egress_callback(&self) {
+        self.0.body.borrow_mut().unregister_egress_callback();
+    }
+
+    pub fn as_bytestream_pair(&self) -> ByteStreamPair {
+        ByteStreamPair::new(self.0.uid, self.0.body.clone())
+    }
+} // impl Loopback
+
+impl BasicStreamBody for LoopbackBody {
+    fn get_base(&self) -> &base::StreamBody { &self.base }
+}
+
+impl BasicStream<WeakLoopback, LoopbackBody> for Loopback {
+    fn get_link(&self) -> &Link<LoopbackBody> { &self.0 }
+}
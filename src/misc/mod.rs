@@ -7,12 +7,17 @@ pub mod linger;
 pub use linger::{Linger, WeakLinger};
 pub mod duplex;
 pub use duplex::{Duplex, WeakDuplex};
+pub mod loopback;
+pub use loopback::{Loopback, WeakLoopback};
+pub mod ratelimiter;
+pub use ratelimiter::{RateLimiter, WeakRateLimiter};
 pub mod tcp_connect;
 pub use tcp_connect::{TcpProgress, WeakTcpProgress};
 pub mod unix_connect;
 pub use unix_connect::{UnixProgress, WeakUnixProgress};
 pub mod resolver;
 pub use resolver::{Resolver, WeakResolver};
+pub mod signal;
 
 pub fn pipe(disk: &Disk) -> Result<(ByteStream, Fd)> {
     let mut pair = [0i32, 0i32];
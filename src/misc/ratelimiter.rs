@@ -0,0 +1,318 @@
+use std::rc::{Rc, Weak};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::Result;
+use std::time::{Duration, Instant};
+
+use crate::{Disk, WeakDisk, Link, UID, Action, Timer};
+use crate::{Downgradable, Upgradable, error, DECLARE_LINKS};
+use r3::{TRACE, Traceable};
+
+// How long a woken consumer has to draw before the next one in line gets
+// its turn, unless refilling its amount takes longer.
+const MIN_TURN: Duration = Duration::from_millis(10);
+
+#[derive(Debug)]
+struct Waiter {
+    uid: UID,
+    amount: f64,
+    wake: Action,
+}
+
+#[derive(Debug)]
+struct RateLimiterBody {
+    weak_disk: WeakDisk,
+    uid: UID,
+    parent: Option<RateLimiter>,
+    byterate: f64,
+    max_burst: f64,
+    quota: f64,
+    prev_time: Instant,
+    waiters: VecDeque<Waiter>,
+    wake_timer: Option<Timer>,
+    turn_timer: Option<Timer>,
+    woken: bool,
+    weak_self: Weak<RefCell<RateLimiterBody>>,
+}
+
+impl RateLimiterBody {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.prev_time);
+        self.quota += elapsed.as_secs_f64() * self.byterate;
+        if self.quota > self.max_burst {
+            self.quota = self.max_burst;
+        }
+        self.prev_time = now;
+    }
+
+    fn enqueue(&mut self, uid: UID, amount: f64, wake: &Action) {
+        if self.waiters.iter().any(|waiter| waiter.uid == uid) {
+            return;
+        }
+        TRACE!(ATEN_RATELIMITER_WAIT {
+            LIMITER: self.uid, WAITER: uid, AMOUNT: amount,
+        });
+        self.waiters.push_back(Waiter {
+            uid: uid,
+            amount: amount,
+            wake: wake.clone(),
+        });
+        if self.waiters.len() == 1 {
+            self.kick();
+        } else if self.woken && self.turn_timer.is_none() {
+            self.end_turn_later();
+        }
+    }
+
+    fn forget(&mut self, uid: UID) {
+        let was_first =
+            matches!(self.waiters.front(), Some(waiter) if waiter.uid == uid);
+        self.waiters.retain(|waiter| waiter.uid != uid);
+        if let Some(parent) = &self.parent {
+            if self.waiters.is_empty() {
+                parent.forget(self.uid);
+            } else if was_first {
+                parent.0.body.borrow_mut().wait_again(self.uid);
+            }
+        }
+        if was_first {
+            self.kick();
+        }
+    }
+
+    // A child limiter at the head of the line that still has consumers
+    // of its own waiting keeps its place and waits for its amount again.
+    fn wait_again(&mut self, uid: UID) {
+        if matches!(self.waiters.front(), Some(waiter) if waiter.uid == uid) {
+            self.kick();
+        }
+    }
+
+    // Take amount out of this bucket and those of the ancestors.
+    fn draw(&mut self, amount: f64) {
+        self.quota -= amount;
+        if let Some(parent) = &self.parent {
+            parent.0.body.borrow_mut().draw(amount);
+        }
+    }
+
+    // Wake up the first waiter once the bucket holds enough for it.
+    fn kick(&mut self) {
+        if let Some(timer) = self.wake_timer.take() {
+            timer.cancel();
+        }
+        if let Some(timer) = self.turn_timer.take() {
+            timer.cancel();
+        }
+        self.woken = false;
+        let disk =
+            match self.weak_disk.upgrade() {
+                Some(disk) => disk,
+                None => { return; }
+            };
+        let amount =
+            match self.waiters.front() {
+                Some(waiter) => waiter.amount,
+                None => { return; }
+            };
+        let now = disk.now();
+        self.refill(now);
+        if self.quota >= amount {
+            let waiter = self.waiters.front().unwrap();
+            TRACE!(ATEN_RATELIMITER_WAKE {
+                LIMITER: self.uid, WAITER: waiter.uid,
+            });
+            disk.execute(waiter.wake.clone());
+            self.woken = true;
+            if self.waiters.len() > 1 {
+                self.end_turn_later();
+            }
+            return;
+        }
+        let delay = (amount - self.quota) / self.byterate;
+        let weak_self = self.weak_self.clone();
+        self.wake_timer = Some(disk.schedule(
+            now + Duration::from_secs_f64(delay),
+            Action::new(move || {
+                if let Some(body) = weak_self.upgrade() {
+                    let mut body = body.borrow_mut();
+                    body.wake_timer = None;
+                    body.kick();
+                }
+            })));
+    }
+
+    // A woken waiter that does not draw in time (say, because its own
+    // reader is held up) must not hold up the rest of the line, so it is
+    // moved to the back.
+    fn end_turn_later(&mut self) {
+        let disk =
+            match self.weak_disk.upgrade() {
+                Some(disk) => disk,
+                None => { return; }
+            };
+        let amount = self.waiters.front().map_or(0.0, |waiter| waiter.amount);
+        let refill = Duration::from_secs_f64(amount / self.byterate);
+        let turn = MIN_TURN.max(refill);
+        let weak_self = self.weak_self.clone();
+        self.turn_timer = Some(disk.schedule(
+            disk.now() + turn,
+            Action::new(move || {
+                if let Some(body) = weak_self.upgrade() {
+                    let mut body = body.borrow_mut();
+                    body.turn_timer = None;
+                    body.end_turn();
+                }
+            })));
+    }
+
+    fn end_turn(&mut self) {
+        if let Some(waiter) = self.waiters.pop_front() {
+            TRACE!(ATEN_RATELIMITER_TURN_OVER {
+                LIMITER: self.uid, WAITER: waiter.uid,
+            });
+            self.waiters.push_back(waiter);
+        }
+        self.kick();
+    }
+
+    fn wake_action(&self) -> Action {
+        let weak_self = self.weak_self.clone();
+        Action::new(move || {
+            if let Some(body) = weak_self.upgrade() {
+                body.borrow_mut().kick();
+            }
+        })
+    }
+} // impl RateLimiterBody
+
+impl Drop for RateLimiterBody {
+    fn drop(&mut self) {
+        TRACE!(ATEN_RATELIMITER_DROP { LIMITER: self.uid });
+        if let Some(timer) = self.wake_timer.take() {
+            timer.cancel();
+        }
+        if let Some(timer) = self.turn_timer.take() {
+            timer.cancel();
+        }
+    }
+} // impl Drop for RateLimiterBody
+
+// A token bucket shared by any number of consumers (typically pacer
+// streams). Consumers that cannot be served wait in line and are woken
+// up in order. A limiter may have a parent, in which case whatever is
+// drawn from the limiter is drawn from the parent as well.
+DECLARE_LINKS!(RateLimiter, WeakRateLimiter, RateLimiterBody,
+               ATEN_RATELIMITER_UPPED_MISS, LIMITER);
+
+impl RateLimiter {
+    pub fn new(disk: &Disk, byterate: f64, max_burst: usize)
+               -> Result<RateLimiter> {
+        Self::make(disk, None, byterate, max_burst)
+    }
+
+    // A limiter nested inside parent: its consumers are held to both
+    // limits, and the parent serves it in turn with its other consumers.
+    pub fn with_parent(disk: &Disk, parent: &RateLimiter, byterate: f64,
+                       max_burst: usize) -> Result<RateLimiter> {
+        Self::make(disk, Some(parent.clone()), byterate, max_burst)
+    }
+
+    fn make(disk: &Disk, parent: Option<RateLimiter>, byterate: f64,
+            max_burst: usize) -> Result<RateLimiter> {
+        if byterate <= 0.0 || max_burst < 1 {
+            return Err(error::inval());
+        }
+        let uid = UID::new();
+        TRACE!(ATEN_RATELIMITER_CREATE {
+            DISK: disk, LIMITER: uid, PARENT: r3::option(&parent),
+            RATE: byterate, MAX_BURST: max_burst,
+        });
+        let body = Rc::new_cyclic(
+            |weak_self| RefCell::new(RateLimiterBody {
+                weak_disk: disk.downgrade(),
+                uid: uid,
+                parent: parent,
+                byterate: byterate,
+                max_burst: max_burst as f64,
+                quota: 0.0,
+                prev_time: disk.now(),
+                waiters: VecDeque::new(),
+                wake_timer: None,
+                turn_timer: None,
+                woken: false,
+                weak_self: weak_self.clone(),
+            }));
+        Ok(RateLimiter(Link {
+            uid: uid,
+            body: body,
+        }))
+    }
+
+    // Change the rate on the fly. What has accrued so far accrued at the
+    // old rate.
+    pub fn set_rate(&self, byterate: f64) -> Result<()> {
+        if byterate <= 0.0 {
+            return Err(error::inval());
+        }
+        TRACE!(ATEN_RATELIMITER_SET_RATE { LIMITER: self, RATE: byterate });
+        let mut body = self.0.body.borrow_mut();
+        if let Some(disk) = body.weak_disk.upgrade() {
+            body.refill(disk.now());
+        }
+        body.byterate = byterate;
+        body.kick();
+        Ok(())
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.0.body.borrow().byterate
+    }
+
+    // How many bytes the consumer identified by uid may draw right now:
+    // at least amount (capped at the burst size), or zero. In the latter
+    // case, the consumer is put in line, and wake is executed when it
+    // is its turn. Whatever is drawn must be reported with consume().
+    pub fn available(&self, uid: UID, amount: f64, wake: &Action) -> f64 {
+        let mut body = self.0.body.borrow_mut();
+        let amount = amount.min(body.max_burst);
+        if let Some(waiter) = body.waiters.front() {
+            if waiter.uid != uid {
+                body.enqueue(uid, amount, wake);
+                return 0.0;
+            }
+        }
+        if let Some(disk) = body.weak_disk.upgrade() {
+            body.refill(disk.now());
+        }
+        if body.quota < amount {
+            body.enqueue(uid, amount, wake);
+            return 0.0;
+        }
+        let mut available = body.quota;
+        if let Some(parent) = body.parent.clone() {
+            let wake_self = body.wake_action();
+            available =
+                available.min(parent.available(body.uid, amount, &wake_self));
+            if available < amount {
+                // The parent wakes us up, and we wake up the consumer.
+                body.enqueue(uid, amount, wake);
+                return 0.0;
+            }
+        }
+        available
+    }
+
+    // Draw count bytes on behalf of the consumer, which then stops
+    // waiting. The next consumer in line gets its turn.
+    pub fn consume(&self, uid: UID, count: usize) {
+        let mut body = self.0.body.borrow_mut();
+        body.draw(count as f64);
+        body.forget(uid);
+    }
+
+    // Give up the consumer's place in line.
+    pub fn forget(&self, uid: UID) {
+        self.0.body.borrow_mut().forget(uid);
+    }
+} // impl RateLimiter
@@ -2,12 +2,14 @@ use std::io::Result;
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::task::Poll;
 use std::thread::JoinHandle;
 
 use crate::{Disk, WeakDisk, UID, Action, Link, Downgradable, Upgradable, error};
 use crate::DECLARE_LINKS;
 use crate::stream::ByteStream;
 use crate::misc::pipe;
+use crate::task::Trigger;
 use r3::{TRACE, TRACE_ENABLED, Traceable};
 
 #[derive(Debug)]
@@ -62,6 +64,22 @@ impl Resolver {
         }
     }
 
+    pub async fn resolve(disk: &Disk, name: String)
+                         -> Result<std::vec::IntoIter<SocketAddr>> {
+        let trigger = Trigger::new();
+        let resolver = Self::new(disk, name)?;
+        resolver.register_callback(trigger.action());
+        std::future::poll_fn(|context| {
+            match resolver.poll() {
+                Err(err) if error::is_again(&err) => {
+                    trigger.park(context);
+                    Poll::Pending
+                }
+                result => Poll::Ready(result),
+            }
+        }).await
+    }
+
     pub fn register_callback(&self, callback: Action) {
         TRACE!(ATEN_RESOLVER_REGISTER_CALLBACK {
             RESOLVER: self, CALLBACK: &callback
@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+use std::io::{Error, Result};
+use std::os::unix::io::AsRawFd;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use crate::{Disk, Action, UID, Registration, Fd, error};
+use r3::{TRACE, Traceable};
+
+// Signals are delivered to the main loop through a self-pipe per
+// watch. The signal handler may only touch these atomics.
+const MAX_WATCHES: usize = 64;
+
+static WATCH_SIGNOS: [AtomicI32; MAX_WATCHES] =
+    [const { AtomicI32::new(0) }; MAX_WATCHES];
+static WATCH_FDS: [AtomicI32; MAX_WATCHES] =
+    [const { AtomicI32::new(-1) }; MAX_WATCHES];
+
+// The number of watches per signal and the disposition the signal had
+// before the first of them.
+static INSTALLED: Mutex<BTreeMap<libc::c_int, (usize, libc::sigaction)>> =
+    Mutex::new(BTreeMap::new());
+
+extern "C" fn handler(signo: libc::c_int) {
+    let saved_errno = unsafe { *libc::__errno_location() };
+    for slot in 0..MAX_WATCHES {
+        if WATCH_SIGNOS[slot].load(Ordering::SeqCst) != signo {
+            continue;
+        }
+        let fd = WATCH_FDS[slot].load(Ordering::SeqCst);
+        if fd >= 0 {
+            let byte = 0u8;
+            unsafe {
+                libc::write(fd, &byte as *const u8 as *const libc::c_void, 1)
+            };
+        }
+    }
+    unsafe { *libc::__errno_location() = saved_errno };
+}
+
+// The handler replaces whatever disposition the signal had before
+// until the last watch of the signal is gone.
+fn install(signo: libc::c_int) -> Result<()> {
+    let mut installed = INSTALLED.lock().unwrap();
+    if let Some((count, _)) = installed.get_mut(&signo) {
+        *count += 1;
+        return Ok(());
+    }
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = handler as extern "C" fn(libc::c_int) as usize;
+    action.sa_flags = libc::SA_RESTART;
+    unsafe { libc::sigemptyset(&mut action.sa_mask) };
+    let mut previous: libc::sigaction = unsafe { std::mem::zeroed() };
+    if unsafe { libc::sigaction(signo, &action, &mut previous) } < 0 {
+        return Err(Error::last_os_error());
+    }
+    installed.insert(signo, (1, previous));
+    Ok(())
+}
+
+fn uninstall(signo: libc::c_int) {
+    let mut installed = INSTALLED.lock().unwrap();
+    if let Some((count, previous)) = installed.get_mut(&signo) {
+        *count -= 1;
+        if *count == 0 {
+            unsafe {
+                libc::sigaction(signo, &*previous, std::ptr::null_mut())
+            };
+            installed.remove(&signo);
+        }
+    }
+}
+
+fn drain(fd: &Fd) {
+    let mut buffer = [0u8; 64];
+    loop {
+        let count = unsafe {
+            libc::read(fd.as_raw_fd(),
+                       buffer.as_mut_ptr() as *mut libc::c_void,
+                       buffer.len())
+        };
+        if count <= 0 {
+            break
+        }
+    }
+}
+
+// Executes the action in the main loop whenever the signal is received.
+// Repeated signals may be coalesced. The disposition the signal had
+// before is restored when the last watch of the signal is dropped.
+#[derive(Debug)]
+pub struct Watch {
+    uid: UID,
+    signo: i32,
+    slot: usize,
+    write_fd: Fd,
+    registration: Registration,
+}
+
+impl Watch {
+    pub fn new(disk: &Disk, signo: i32, action: Action) -> Result<Watch> {
+        let uid = UID::new();
+        if signo <= 0 || signo >= 64 {
+            return Err(error::inval());
+        }
+        let mut pair = [0i32, 0i32];
+        let status = unsafe {
+            libc::pipe2(&mut pair[0], libc::O_NONBLOCK | libc::O_CLOEXEC)
+        };
+        if status < 0 {
+            let err = Error::last_os_error();
+            TRACE!(ATEN_SIGNAL_WATCH_CREATE_FAIL {
+                DISK: disk, SIGNO: signo, ERR: r3::errsym(&err),
+            });
+            return Err(err);
+        }
+        let read_fd = Fd::new(pair[0]);
+        let write_fd = Fd::new(pair[1]);
+        let drain_fd = read_fd.clone();
+        let notify = Action::new(move || {
+            drain(&drain_fd);
+            action.perform();
+        });
+        let registration = disk.register(&read_fd, notify)?;
+        let slot =
+            match (0..MAX_WATCHES).find(|slot| {
+                WATCH_SIGNOS[*slot].compare_exchange(
+                    0, -1, Ordering::SeqCst, Ordering::SeqCst).is_ok()
+            }) {
+                Some(slot) => slot,
+                None => {
+                    TRACE!(ATEN_SIGNAL_WATCH_CREATE_EXHAUSTED {
+                        DISK: disk, SIGNO: signo,
+                    });
+                    return Err(Error::from_raw_os_error(libc::EMFILE));
+                }
+            };
+        WATCH_FDS[slot].store(write_fd.as_raw_fd(), Ordering::SeqCst);
+        WATCH_SIGNOS[slot].store(signo, Ordering::SeqCst);
+        if let Err(err) = install(signo) {
+            TRACE!(ATEN_SIGNAL_WATCH_CREATE_FAIL {
+                DISK: disk, SIGNO: signo, ERR: r3::errsym(&err),
+            });
+            Self::release(slot);
+            return Err(err);
+        }
+        TRACE!(ATEN_SIGNAL_WATCH_CREATE {
+            DISK: disk, WATCH: uid, SIGNO: signo, SLOT: slot,
+        });
+        Ok(Watch {
+            uid: uid,
+            signo: signo,
+            slot: slot,
+            write_fd: write_fd,
+            registration: registration,
+        })
+    }
+
+    fn release(slot: usize) {
+        WATCH_SIGNOS[slot].store(-1, Ordering::SeqCst);
+        WATCH_FDS[slot].store(-1, Ordering::SeqCst);
+        WATCH_SIGNOS[slot].store(0, Ordering::SeqCst);
+    }
+} // impl Watch
+
+impl std::fmt::Display for Watch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.uid)
+    }
+} // impl std::fmt::Display for Watch
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        TRACE!(ATEN_SIGNAL_WATCH_DROP { WATCH: self });
+        Self::release(self.slot);
+        uninstall(self.signo);
+    }
+} // impl Drop for Watch
@@ -3,11 +3,13 @@ use std::cell::RefCell;
 use std::io::{Error, Result};
 use std::net::SocketAddr;
 use std::os::unix::io::{AsRawFd};
+use std::task::Poll;
 
 use crate::{Disk, WeakDisk, Link, UID, Action, Fd, Registration};
 use crate::{Downgradable, Upgradable, nonblock, error, DECLARE_LINKS};
 use crate::misc::duplex::Duplex;
 use crate::stream::ByteStreamPair;
+use crate::task::Trigger;
 use r3::{TRACE, Traceable};
 
 #[derive(Debug)]
@@ -213,6 +215,21 @@ impl TcpProgress {
     pub fn take(&self) -> Result<ByteStreamPair> {
         self.0.body.borrow_mut().take()
     }
+
+    pub async fn connect(disk: &Disk, address: &SocketAddr)
+                         -> Result<ByteStreamPair> {
+        let trigger = Trigger::new();
+        let progress = Self::new(disk, address, trigger.action())?;
+        std::future::poll_fn(|context| {
+            match progress.take() {
+                Err(err) if error::is_again(&err) => {
+                    trigger.park(context);
+                    Poll::Pending
+                }
+                result => Poll::Ready(result),
+            }
+        }).await
+    }
 } // impl TcpProgress
 
 fn try_connect(socket: &Fd, address: &SocketAddr) -> Result<()> {
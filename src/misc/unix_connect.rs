@@ -2,10 +2,12 @@ use std::rc::Rc;
 use std::cell::RefCell;
 use std::io::{Error, Result};
 use std::os::unix::io::{AsRawFd};
+use std::task::Poll;
 
 use crate::{Disk, WeakDisk, Link, UID, Action, Fd, Registration};
 use crate::{Downgradable, Upgradable, nonblock, error, DECLARE_LINKS};
 use crate::stream::ByteStreamPair;
+use crate::task::Trigger;
 use crate::misc::duplex::Duplex;
 use r3::{TRACE, Traceable};
 
@@ -212,6 +214,21 @@ impl UnixProgress {
     pub fn take(&self) -> Result<ByteStreamPair> {
         self.0.body.borrow_mut().take()
     }
+
+    pub async fn connect(disk: &Disk, address: &std::path::Path)
+                         -> Result<ByteStreamPair> {
+        let trigger = Trigger::new();
+        let progress = Self::new(disk, address, trigger.action())?;
+        std::future::poll_fn(|context| {
+            match progress.take() {
+                Err(err) if error::is_again(&err) => {
+                    trigger.park(context);
+                    Poll::Pending
+                }
+                result => Poll::Ready(result),
+            }
+        }).await
+    }
 } // impl UnixProgress
 
 fn try_connect(socket: &Fd, address: &std::path::Path) -> Result<()> {
@@ -0,0 +1,141 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::io::{Result, Error, IoSlice};
+use std::os::unix::io::AsRawFd;
+
+use crate::{Disk, Link, Action, UID, Registration, Fd};
+use crate::{Downgradable, Upgradable, error};
+use crate::sink::{BasicSink, SinkHooks};
+use crate::stream::base;
+use r3::{TRACE, Traceable};
+
+DECLARE_SINK!(
+    Sink, WeakSink, SinkBody,
+    ATEN_FILESINK_DROP,
+    ATEN_FILESINK_UPPED_MISS,
+    ATEN_FILESINK_REGISTER_CALLBACK,
+    ATEN_FILESINK_UNREGISTER_CALLBACK,
+    ATEN_FILESINK_WRITE,
+    ATEN_FILESINK_WRITE_DUMP,
+    ATEN_FILESINK_WRITE_FAIL,
+    ATEN_FILESINK_CLOSE,
+    ATEN_FILESINK_CLOSE_FAIL,
+    SinkHooks);
+
+#[derive(Debug)]
+pub struct SinkBody {
+    base: base::StreamBody,
+    fd: Option<Fd>,
+    registration: Option<Registration>,
+    shutdown_on_close: bool,
+}
+
+impl SinkBody {
+    fn fd(&self) -> Result<&Fd> {
+        self.fd.as_ref().ok_or_else(error::badf)
+    }
+
+    fn write_nontrivial(&mut self, buf: &[u8]) -> Result<usize> {
+        let count = unsafe {
+            libc::write(self.fd()?.as_raw_fd(),
+                        buf.as_ptr() as *const libc::c_void, buf.len())
+        };
+        if count < 0 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(count as usize)
+        }
+    }
+
+    fn flush_nontrivial(&mut self) -> Result<()> {
+        self.fd()?;
+        Ok(())
+    }
+
+    fn close_nontrivial(&mut self) -> Result<()> {
+        if self.shutdown_on_close {
+            if let Some(fd) = &self.fd {
+                let status = unsafe {
+                    libc::shutdown(fd.as_raw_fd(), libc::SHUT_WR)
+                };
+                if status < 0 {
+                    return Err(Error::last_os_error());
+                }
+            }
+        }
+        self.registration = None;
+        self.fd = None;
+        Ok(())
+    }
+}
+
+impl SinkHooks for SinkBody {
+    fn write_vectored_nontrivial(&mut self, bufs: &[IoSlice])
+                                 -> Result<usize> {
+        let count = unsafe {
+            libc::writev(self.fd()?.as_raw_fd(),
+                         bufs.as_ptr() as *const libc::iovec,
+                         bufs.len().min(libc::UIO_MAXIOV as usize)
+                         as libc::c_int)
+        };
+        if count < 0 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(count as usize)
+        }
+    }
+
+    fn splice_dest_nontrivial(&self) -> Option<Fd> {
+        self.fd.as_ref().map(|fd| fd.clone())
+    }
+} // impl SinkHooks for SinkBody
+
+impl Sink {
+    pub fn new(disk: &Disk, fd: &Fd, sync: bool) -> Result<Sink> {
+        let uid = UID::new();
+        let body = SinkBody {
+            base: base::StreamBody::new(disk.downgrade(), uid),
+            fd: Some(fd.clone()),
+            registration: None,
+            shutdown_on_close: false,
+        };
+        let sink = Sink(Link {
+            uid: uid,
+            body: Rc::new(RefCell::new(body)),
+        });
+        if !sync {
+            let weak_sink = sink.downgrade();
+            let notify = Action::new(move || {
+                weak_sink.upped(|sink| { sink.invoke_callback(); });
+            });
+            match disk.register(fd, notify) {
+                Ok(registration) => {
+                    sink.0.body.borrow_mut().registration =
+                        Some(registration);
+                }
+                Err(err) => {
+                    TRACE!(ATEN_FILESINK_CREATE_FAIL {
+                        DISK: disk, ERR: r3::errsym(&err)
+                    });
+                    return Err(err);
+                }
+            }
+        }
+        TRACE!(ATEN_FILESINK_CREATE {
+            DISK: disk, SINK: uid, FD: fd, SYNC: sync,
+        });
+        Ok(sink)
+    }
+
+    // Shut down the write side of a socket when the sink is closed.
+    pub fn set_shutdown_on_close(&self, enabled: bool) {
+        TRACE!(ATEN_FILESINK_SET_SHUTDOWN_ON_CLOSE {
+            SINK: self, ENABLED: enabled,
+        });
+        self.0.body.borrow_mut().shutdown_on_close = enabled;
+    }
+
+    pub fn get_fd(&self) -> Option<Fd> {
+        self.0.body.borrow().fd.as_ref().map(|fd| fd.clone())
+    }
+} // impl Sink
@@ -0,0 +1,337 @@
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::io::{Result, Write, IoSlice};
+use std::rc::Rc;
+
+use crate::{Link, UID, Action, Fd, Downgradable, Upgradable, DECLARE_LINKS};
+
+DECLARE_LINKS!(ByteSink, WeakByteSink, dyn DebuggableByteSinkBody,
+               ATEN_BYTESINK_UPPED_MISS, SINK);
+
+impl ByteSink {
+    pub fn new(uid: UID, body: Rc<RefCell<dyn DebuggableByteSinkBody>>)
+               -> ByteSink {
+        ByteSink(Link {
+            uid: uid,
+            body: body,
+        })
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize> {
+        self.0.body.borrow_mut().write(buf)
+    }
+
+    pub fn write_vectored(&self, bufs: &[IoSlice]) -> Result<usize> {
+        self.0.body.borrow_mut().write_vectored(bufs)
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.0.body.borrow_mut().flush()
+    }
+
+    pub fn close(&self) -> Result<()> {
+        self.0.body.borrow_mut().close()
+    }
+
+    pub fn splice_dest(&self) -> Option<Fd> {
+        self.0.body.borrow().splice_dest()
+    }
+
+    pub fn register_callback(&self, callback: Action) {
+        self.0.body.borrow_mut().register_callback(callback);
+    }
+
+    pub fn unregister_callback(&self) {
+        self.0.body.borrow_mut().unregister_callback();
+    }
+} // impl ByteSink
+
+// Writes, flushes and closes never block. EAGAIN means the operation
+// should be retried once the callback has been invoked. Closing
+// implies flushing; once closed, the sink rejects writes with EBADF.
+pub trait ByteSinkBody {
+    fn write(&mut self, buf: &[u8]) -> Result<usize>;
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> Result<usize>;
+    fn flush(&mut self) -> Result<()>;
+    fn close(&mut self) -> Result<()>;
+    fn register_callback(&mut self, callback: Action);
+    fn unregister_callback(&mut self);
+
+    // The file descriptor the sink writes to directly, if any.
+    fn splice_dest(&self) -> Option<Fd> {
+        None
+    }
+}
+
+pub trait DebuggableByteSinkBody: ByteSinkBody + std::fmt::Debug {}
+
+impl Write for ByteSink {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.0.body.borrow_mut().write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> Result<usize> {
+        self.0.body.borrow_mut().write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.0.body.borrow_mut().flush()
+    }
+}
+
+#[macro_export]
+macro_rules! DECLARE_SINK {
+    ($Sink:ident,
+     $WeakSink:ident,
+     $SinkBody:ident,
+     $ATEN_SINK_DROP:ident,
+     $ATEN_SINK_UPPED_MISS:ident,
+     $ATEN_SINK_REGISTER_CALLBACK:ident,
+     $ATEN_SINK_UNREGISTER_CALLBACK:ident,
+     $ATEN_SINK_WRITE:ident,
+     $ATEN_SINK_WRITE_DUMP:ident,
+     $ATEN_SINK_WRITE_FAIL:ident,
+     $ATEN_SINK_CLOSE:ident,
+     $ATEN_SINK_CLOSE_FAIL:ident
+     $(, $SinkHooks:ident)?) => {
+        $crate::DECLARE_SINK_DROP!($SinkBody,
+                                   $ATEN_SINK_DROP);
+        $crate::DECLARE_SINK_NO_DROP!($Sink, $WeakSink, $SinkBody,
+                                      $ATEN_SINK_UPPED_MISS,
+                                      $ATEN_SINK_REGISTER_CALLBACK,
+                                      $ATEN_SINK_UNREGISTER_CALLBACK,
+                                      $ATEN_SINK_WRITE,
+                                      $ATEN_SINK_WRITE_DUMP,
+                                      $ATEN_SINK_WRITE_FAIL,
+                                      $ATEN_SINK_CLOSE,
+                                      $ATEN_SINK_CLOSE_FAIL
+                                      $(, $SinkHooks)?);
+    }
+}
+
+#[macro_export]
+macro_rules! DECLARE_SINK_DROP {
+    ($SinkBody:ident,
+     $ATEN_SINK_DROP:ident) => {
+        impl Drop for $SinkBody {
+            fn drop(&mut self) {
+                TRACE!($ATEN_SINK_DROP { SINK: self });
+            }
+        }
+    }
+}
+
+// A sink body that implements SinkHooks itself says so with a trailing
+// SinkHooks argument; the others get the defaults.
+#[macro_export]
+macro_rules! DECLARE_SINK_NO_DROP {
+    ($Sink:ident,
+     $WeakSink:ident,
+     $SinkBody:ident,
+     $ATEN_SINK_UPPED_MISS:ident,
+     $ATEN_SINK_REGISTER_CALLBACK:ident,
+     $ATEN_SINK_UNREGISTER_CALLBACK:ident,
+     $ATEN_SINK_WRITE:ident,
+     $ATEN_SINK_WRITE_DUMP:ident,
+     $ATEN_SINK_WRITE_FAIL:ident,
+     $ATEN_SINK_CLOSE:ident,
+     $ATEN_SINK_CLOSE_FAIL:ident) => {
+        $crate::DECLARE_SINK_NO_DROP!($Sink, $WeakSink, $SinkBody,
+                                      $ATEN_SINK_UPPED_MISS,
+                                      $ATEN_SINK_REGISTER_CALLBACK,
+                                      $ATEN_SINK_UNREGISTER_CALLBACK,
+                                      $ATEN_SINK_WRITE,
+                                      $ATEN_SINK_WRITE_DUMP,
+                                      $ATEN_SINK_WRITE_FAIL,
+                                      $ATEN_SINK_CLOSE,
+                                      $ATEN_SINK_CLOSE_FAIL,
+                                      SinkHooks);
+
+        impl $crate::sink::SinkHooks for $SinkBody {}
+    };
+    ($Sink:ident,
+     $WeakSink:ident,
+     $SinkBody:ident,
+     $ATEN_SINK_UPPED_MISS:ident,
+     $ATEN_SINK_REGISTER_CALLBACK:ident,
+     $ATEN_SINK_UNREGISTER_CALLBACK:ident,
+     $ATEN_SINK_WRITE:ident,
+     $ATEN_SINK_WRITE_DUMP:ident,
+     $ATEN_SINK_WRITE_FAIL:ident,
+     $ATEN_SINK_CLOSE:ident,
+     $ATEN_SINK_CLOSE_FAIL:ident,
+     SinkHooks) => {
+        $crate::DECLARE_LINKS!($Sink, $WeakSink, $SinkBody,
+                               $ATEN_SINK_UPPED_MISS, SINKD);
+
+        impl $crate::sink::ByteSinkBody for $SinkBody {
+            fn register_callback(&mut self, callback: $crate::Action) {
+                TRACE!($ATEN_SINK_REGISTER_CALLBACK {
+                    SINKD: self, ACTION: &callback
+                });
+                $crate::stream::ByteStreamBody::register_callback(
+                    &mut self.base, callback);
+            }
+
+            fn unregister_callback(&mut self) {
+                TRACE!($ATEN_SINK_UNREGISTER_CALLBACK { SINKD: self });
+                $crate::stream::ByteStreamBody::unregister_callback(
+                    &mut self.base);
+            }
+
+            fn write(&mut self, buf: &[u8]) -> Result<usize> {
+                match self.write_nontrivial(buf) {
+                    Ok(count) => {
+                        TRACE!($ATEN_SINK_WRITE {
+                            SINKD: self, WANT: buf.len(), GOT: count
+                        });
+                        TRACE!($ATEN_SINK_WRITE_DUMP {
+                            SINKD: self, DATA: r3::octets(&buf[..count])
+                        });
+                        Ok(count)
+                    }
+                    Err(err) => {
+                        TRACE!($ATEN_SINK_WRITE_FAIL {
+                            SINKD: self, WANT: buf.len(), ERR: r3::errsym(&err)
+                        });
+                        Err(err)
+                    }
+                }
+            }
+
+            fn write_vectored(&mut self, bufs: &[std::io::IoSlice])
+                              -> Result<usize> {
+                let want: usize = bufs.iter().map(|buf| buf.len()).sum();
+                match $crate::sink::SinkHooks::write_vectored_nontrivial(
+                    self, bufs) {
+                    Ok(count) => {
+                        TRACE!($ATEN_SINK_WRITE {
+                            SINKD: self, WANT: want, GOT: count
+                        });
+                        Ok(count)
+                    }
+                    Err(err) => {
+                        TRACE!($ATEN_SINK_WRITE_FAIL {
+                            SINKD: self, WANT: want, ERR: r3::errsym(&err)
+                        });
+                        Err(err)
+                    }
+                }
+            }
+
+            fn flush(&mut self) -> Result<()> {
+                self.flush_nontrivial()
+            }
+
+            fn splice_dest(&self) -> Option<$crate::Fd> {
+                $crate::sink::SinkHooks::splice_dest_nontrivial(self)
+            }
+
+            fn close(&mut self) -> Result<()> {
+                match self.close_nontrivial() {
+                    Ok(()) => {
+                        TRACE!($ATEN_SINK_CLOSE { SINKD: self });
+                        Ok(())
+                    }
+                    Err(err) => {
+                        TRACE!($ATEN_SINK_CLOSE_FAIL {
+                            SINKD: self, ERR: r3::errsym(&err)
+                        });
+                        Err(err)
+                    }
+                }
+            }
+        }
+
+        impl std::fmt::Display for $SinkBody {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "{}", self.base)
+            }
+        }
+
+        impl $crate::sink::DebuggableByteSinkBody for $SinkBody {}
+
+        impl From<$Sink> for $crate::sink::ByteSink {
+            fn from(sink: $Sink) -> $crate::sink::ByteSink {
+                sink.as_bytesink()
+            }
+        }
+
+        impl $crate::sink::BasicSinkBody for $SinkBody {
+            fn get_base(&self) -> &$crate::stream::base::StreamBody {
+                &self.base
+            }
+
+            fn write_nontrivial(&mut self, buf: &[u8]) -> Result<usize> {
+                $SinkBody::write_nontrivial(self, buf)
+            }
+        }
+
+        impl $crate::sink::BasicSink<$WeakSink, $SinkBody> for $Sink {
+            fn get_link(&self) -> &$crate::Link<$SinkBody> { &self.0 }
+        }
+    };
+}
+
+pub trait BasicSinkBody {
+    fn get_base(&self) -> &crate::stream::base::StreamBody;
+    fn write_nontrivial(&mut self, buf: &[u8]) -> Result<usize>;
+}
+
+// Optional parts of a sink body. By default, a sink writes one buffer at
+// a time and has no file descriptor to splice into.
+pub trait SinkHooks: BasicSinkBody {
+    fn write_vectored_nontrivial(&mut self, bufs: &[IoSlice])
+                                 -> Result<usize> {
+        match bufs.iter().find(|buf| !buf.is_empty()) {
+            Some(buf) => self.write_nontrivial(buf),
+            None => Ok(0),
+        }
+    }
+
+    fn splice_dest_nontrivial(&self) -> Option<Fd> {
+        None
+    }
+}
+
+pub trait BasicSink<W, B>: Downgradable<W> + Sized where
+    W: Upgradable<Self> + 'static,
+    B: BasicSinkBody + DebuggableByteSinkBody + 'static,
+{
+    fn get_link(&self) -> &Link<B>;
+
+    fn invoke_callback(&self) {
+        self.get_link().body.borrow().get_base().invoke_callback();
+    }
+
+    fn as_bytesink(&self) -> ByteSink {
+        let link = self.get_link();
+        ByteSink::new(link.uid, link.body.clone())
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        self.get_link().body.borrow_mut().write(buf)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.get_link().body.borrow_mut().flush()
+    }
+
+    fn close(&self) -> Result<()> {
+        self.get_link().body.borrow_mut().close()
+    }
+
+    fn register_callback(&self, callback: Action) {
+        self.get_link().body.borrow_mut().register_callback(callback);
+    }
+
+    fn unregister_callback(&self) {
+        self.get_link().body.borrow_mut().unregister_callback();
+    }
+}
+
+pub mod file;
+pub mod rotating;
+pub mod transform;
+pub mod vec;
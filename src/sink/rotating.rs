@@ -0,0 +1,399 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::ffi::{CString, OsString};
+use std::io::{Result, Error, IoSlice};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::{Disk, Link, Action, UID, Timer, Fd};
+use crate::{Downgradable, Upgradable, error};
+use crate::misc::signal;
+use crate::sink::{BasicSink, SinkHooks};
+use crate::stream::base;
+use r3::{TRACE, Traceable};
+
+DECLARE_SINK!(
+    Sink, WeakSink, SinkBody,
+    ATEN_ROTATINGSINK_DROP,
+    ATEN_ROTATINGSINK_UPPED_MISS,
+    ATEN_ROTATINGSINK_REGISTER_CALLBACK,
+    ATEN_ROTATINGSINK_UNREGISTER_CALLBACK,
+    ATEN_ROTATINGSINK_WRITE,
+    ATEN_ROTATINGSINK_WRITE_DUMP,
+    ATEN_ROTATINGSINK_WRITE_FAIL,
+    ATEN_ROTATINGSINK_CLOSE,
+    ATEN_ROTATINGSINK_CLOSE_FAIL,
+    SinkHooks);
+
+// On rotation, path is renamed to path.1, path.1 to path.2 and so on;
+// at most keep old files are retained.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub max_size: Option<u64>,
+    pub max_age: Option<Duration>,
+    pub keep: usize,
+    pub fsync_interval: Option<Duration>,
+    pub reopen_on_sighup: bool,
+    pub mode: libc::mode_t,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            max_size: None,
+            max_age: None,
+            keep: 5,
+            fsync_interval: None,
+            reopen_on_sighup: false,
+            mode: 0o644,
+        }
+    }
+} // impl Default for Config
+
+fn c_path(path: &Path) -> Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| error::inval())
+}
+
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+fn rename(from: &Path, to: &Path) -> Result<()> {
+    match std::fs::rename(from, to) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn open_append(path: &Path, mode: libc::mode_t) -> Result<(Fd, u64)> {
+    let c_path = c_path(path)?;
+    let raw_fd = unsafe {
+        libc::open(c_path.as_ptr(),
+                   libc::O_WRONLY | libc::O_CREAT | libc::O_APPEND |
+                   libc::O_CLOEXEC,
+                   mode as libc::c_uint)
+    };
+    if raw_fd < 0 {
+        return Err(Error::last_os_error());
+    }
+    let fd = Fd::new(raw_fd);
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok((fd, stat.st_size as u64))
+}
+
+#[derive(Debug)]
+pub struct SinkBody {
+    base: base::StreamBody,
+    path: PathBuf,
+    config: Config,
+    fd: Option<Fd>,
+    size: u64,
+    dirty: bool,
+    rotation_timer: Option<Timer>,
+    fsync_timer: Option<Timer>,
+    sighup_watch: Option<signal::Watch>,
+    rotation_callback: Action,
+    rotation_error: Option<Error>,
+}
+
+impl SinkBody {
+    fn fd(&self) -> Result<&Fd> {
+        self.fd.as_ref().ok_or_else(error::badf)
+    }
+
+    fn wrote(&mut self, count: isize) -> Result<usize> {
+        if count < 0 {
+            return Err(Error::last_os_error());
+        }
+        self.size += count as u64;
+        self.dirty = true;
+        if let Some(max_size) = self.config.max_size {
+            if self.size >= max_size {
+                // The data has been written; a failure to rotate is
+                // reported separately and retried at the next write.
+                let result = self.rotate();
+                self.report(result);
+            }
+        }
+        Ok(count as usize)
+    }
+
+    fn write_nontrivial(&mut self, buf: &[u8]) -> Result<usize> {
+        let count = unsafe {
+            libc::write(self.fd()?.as_raw_fd(),
+                        buf.as_ptr() as *const libc::c_void, buf.len())
+        };
+        self.wrote(count)
+    }
+
+    fn flush_nontrivial(&mut self) -> Result<()> {
+        self.fd()?;
+        Ok(())
+    }
+
+    fn close_nontrivial(&mut self) -> Result<()> {
+        if self.fd.is_none() {
+            return Ok(());
+        }
+        if self.config.fsync_interval.is_some() {
+            self.sync()?;
+        }
+        if let Some(timer) = self.rotation_timer.take() {
+            timer.cancel();
+        }
+        if let Some(timer) = self.fsync_timer.take() {
+            timer.cancel();
+        }
+        self.sighup_watch = None;
+        self.fd = None;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if unsafe { libc::fdatasync(self.fd()?.as_raw_fd()) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        self.fd()?;
+        if let Err(err) = self.shift() {
+            TRACE!(ATEN_ROTATINGSINK_ROTATE_FAIL {
+                SINK: self, ERR: r3::errsym(&err),
+            });
+            return Err(err);
+        }
+        TRACE!(ATEN_ROTATINGSINK_ROTATE { SINK: self, SIZE: self.size });
+        self.reopen()
+    }
+
+    fn shift(&mut self) -> Result<()> {
+        if self.config.keep == 0 {
+            return match std::fs::remove_file(&self.path) {
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    Ok(())
+                }
+                result => result,
+            };
+        }
+        for n in (1..self.config.keep).rev() {
+            rename(&numbered(&self.path, n), &numbered(&self.path, n + 1))?;
+        }
+        rename(&self.path, &numbered(&self.path, 1))
+    }
+
+    // Until the file has been reopened successfully, writes keep going
+    // to the old one.
+    fn reopen(&mut self) -> Result<()> {
+        self.fd()?;
+        if self.config.fsync_interval.is_some() {
+            self.sync()?;
+        }
+        match open_append(&self.path, self.config.mode) {
+            Ok((fd, size)) => {
+                TRACE!(ATEN_ROTATINGSINK_REOPEN {
+                    SINK: self, FD: fd, SIZE: size,
+                });
+                self.fd = Some(fd);
+                self.size = size;
+                self.dirty = false;
+                self.announce();
+                Ok(())
+            }
+            Err(err) => {
+                TRACE!(ATEN_ROTATINGSINK_REOPEN_FAIL {
+                    SINK: self, ERR: r3::errsym(&err),
+                });
+                Err(err)
+            }
+        }
+    }
+
+    fn announce(&self) {
+        self.base.get_weak_disk().upped(|disk| {
+            disk.execute(self.rotation_callback.clone());
+        });
+    }
+
+    // Failures of rotations and reopenings nobody asked for are kept
+    // until taken and announced through the rotation callback.
+    fn report(&mut self, result: Result<()>) {
+        if let Err(err) = result {
+            TRACE!(ATEN_ROTATINGSINK_REPORT_FAIL {
+                SINK: self, ERR: r3::errsym(&err),
+            });
+            if self.rotation_error.is_none() {
+                self.rotation_error = Some(err);
+                self.announce();
+            }
+        }
+    }
+}
+
+impl SinkHooks for SinkBody {
+    fn write_vectored_nontrivial(&mut self, bufs: &[IoSlice])
+                                 -> Result<usize> {
+        let count = unsafe {
+            libc::writev(self.fd()?.as_raw_fd(),
+                         bufs.as_ptr() as *const libc::iovec,
+                         bufs.len().min(libc::UIO_MAXIOV as usize)
+                         as libc::c_int)
+        };
+        self.wrote(count)
+    }
+} // impl SinkHooks for SinkBody
+
+impl Sink {
+    // The file is opened in append mode and created if necessary.
+    pub fn open(disk: &Disk, path: &Path, config: Config) -> Result<Sink> {
+        let uid = UID::new();
+        let (fd, size) =
+            match open_append(path, config.mode) {
+                Ok(result) => result,
+                Err(err) => {
+                    TRACE!(ATEN_ROTATINGSINK_CREATE_FAIL {
+                        DISK: disk, PATH: path.to_string_lossy(),
+                        ERR: r3::errsym(&err),
+                    });
+                    return Err(err);
+                }
+            };
+        TRACE!(ATEN_ROTATINGSINK_CREATE {
+            DISK: disk, SINK: uid, PATH: path.to_string_lossy(), FD: fd,
+            SIZE: size, CONFIG: format!("{:?}", config),
+        });
+        let reopen_on_sighup = config.reopen_on_sighup;
+        let body = SinkBody {
+            base: base::StreamBody::new(disk.downgrade(), uid),
+            path: path.to_path_buf(),
+            config: config,
+            fd: Some(fd),
+            size: size,
+            dirty: false,
+            rotation_timer: None,
+            fsync_timer: None,
+            sighup_watch: None,
+            rotation_callback: Action::noop(),
+            rotation_error: None,
+        };
+        let sink = Sink(Link {
+            uid: uid,
+            body: Rc::new(RefCell::new(body)),
+        });
+        if reopen_on_sighup {
+            let weak_sink = sink.downgrade();
+            let watch = signal::Watch::new(
+                disk, libc::SIGHUP, Action::new(move || {
+                    weak_sink.upped(|sink| {
+                        let mut body = sink.0.body.borrow_mut();
+                        let result = body.reopen();
+                        body.report(result);
+                    });
+                }))?;
+            sink.0.body.borrow_mut().sighup_watch = Some(watch);
+        }
+        sink.schedule_rotation(disk);
+        sink.schedule_fsync(disk);
+        Ok(sink)
+    }
+
+    fn schedule_rotation(&self, disk: &Disk) {
+        let body = self.0.body.borrow();
+        let max_age =
+            match (&body.fd, body.config.max_age) {
+                (Some(_), Some(max_age)) => max_age,
+                _ => { return; }
+            };
+        drop(body);
+        let weak_sink = self.downgrade();
+        let weak_disk = disk.downgrade();
+        let timer = disk.schedule(
+            disk.now() + max_age,
+            Action::new(move || {
+                weak_sink.upped(|sink| {
+                    let mut body = sink.0.body.borrow_mut();
+                    if body.size > 0 {
+                        let result = body.rotate();
+                        body.report(result);
+                    }
+                    drop(body);
+                    weak_disk.upped(|disk| { sink.schedule_rotation(disk); });
+                });
+            }));
+        self.0.body.borrow_mut().rotation_timer = Some(timer);
+    }
+
+    fn schedule_fsync(&self, disk: &Disk) {
+        let body = self.0.body.borrow();
+        let interval =
+            match (&body.fd, body.config.fsync_interval) {
+                (Some(_), Some(interval)) => interval,
+                _ => { return; }
+            };
+        drop(body);
+        let weak_sink = self.downgrade();
+        let weak_disk = disk.downgrade();
+        let timer = disk.schedule(
+            disk.now() + interval,
+            Action::new(move || {
+                weak_sink.upped(|sink| {
+                    let mut body = sink.0.body.borrow_mut();
+                    if let Err(err) = body.sync() {
+                        TRACE!(ATEN_ROTATINGSINK_FSYNC_FAIL {
+                            SINK: sink, ERR: r3::errsym(&err),
+                        });
+                    }
+                    drop(body);
+                    weak_disk.upped(|disk| { sink.schedule_fsync(disk); });
+                });
+            }));
+        self.0.body.borrow_mut().fsync_timer = Some(timer);
+    }
+
+    // Rotate right away regardless of size and age.
+    pub fn rotate(&self) -> Result<()> {
+        self.0.body.borrow_mut().rotate()
+    }
+
+    // Reopen the file by its path, typically after it has been renamed
+    // by an external log rotator.
+    pub fn reopen(&self) -> Result<()> {
+        self.0.body.borrow_mut().reopen()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.0.body.borrow().size
+    }
+
+    // The rotation callback is invoked after every rotation and
+    // reopening, and also when one that happened on its own has failed.
+    pub fn register_rotation_callback(&self, callback: Action) {
+        TRACE!(ATEN_ROTATINGSINK_REGISTER_ROTATION_CALLBACK {
+            SINK: self, ACTION: &callback,
+        });
+        self.0.body.borrow_mut().rotation_callback = callback;
+    }
+
+    pub fn unregister_rotation_callback(&self) {
+        TRACE!(ATEN_ROTATINGSINK_UNREGISTER_ROTATION_CALLBACK { SINK: self });
+        self.0.body.borrow_mut().rotation_callback = Action::noop();
+    }
+
+    // The first failure of an automatic rotation or reopening since the
+    // previous call.
+    pub fn take_rotation_error(&self) -> Option<Error> {
+        self.0.body.borrow_mut().rotation_error.take()
+    }
+} // impl Sink
@@ -0,0 +1,230 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::io::{Error, Result};
+
+use crate::{Disk, Link, UID, Action, Downgradable, Upgradable, error};
+use crate::buffer::Buffer;
+use crate::misc::linger::{Linger, State};
+use crate::sink::{ByteSink, BasicSink};
+use crate::stream::{ByteStream, BasicStream, StreamHooks, base};
+use r3::{TRACE, Traceable};
+
+crate::DECLARE_STREAM!(
+    Feed, WeakFeed, FeedBody,
+    ATEN_TRANSFORMFEED_DROP,
+    ATEN_TRANSFORMFEED_UPPED_MISS,
+    ATEN_TRANSFORMFEED_REGISTER_CALLBACK,
+    ATEN_TRANSFORMFEED_UNREGISTER_CALLBACK,
+    ATEN_TRANSFORMFEED_READ_TRIVIAL,
+    ATEN_TRANSFORMFEED_READ,
+    ATEN_TRANSFORMFEED_READ_DUMP,
+    ATEN_TRANSFORMFEED_READ_FAIL,
+    StreamHooks);
+
+DECLARE_SINK_NO_DROP!(
+    Sink, WeakSink, SinkBody,
+    ATEN_TRANSFORMSINK_UPPED_MISS,
+    ATEN_TRANSFORMSINK_REGISTER_CALLBACK,
+    ATEN_TRANSFORMSINK_UNREGISTER_CALLBACK,
+    ATEN_TRANSFORMSINK_WRITE,
+    ATEN_TRANSFORMSINK_WRITE_DUMP,
+    ATEN_TRANSFORMSINK_WRITE_FAIL,
+    ATEN_TRANSFORMSINK_CLOSE,
+    ATEN_TRANSFORMSINK_CLOSE_FAIL);
+
+// The feed hands what has been written to the sink over to the
+// transformation one write at a time.
+#[derive(Debug)]
+pub struct FeedBody {
+    base: base::StreamBody,
+    pending: Buffer,
+    finished: bool,
+    drained: Action,
+}
+
+impl FeedBody {
+    fn consumed(&mut self, count: usize) {
+        self.pending.advance(count);
+        if self.pending.is_empty() {
+            self.base.get_weak_disk().upped(|disk| {
+                disk.execute(self.drained.clone());
+            });
+        }
+    }
+
+    fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.pending.is_empty() {
+            return if self.finished { Ok(0) } else { Err(error::again()) };
+        }
+        let count = buf.len().min(self.pending.len());
+        buf[..count].copy_from_slice(&self.pending[..count]);
+        self.consumed(count);
+        Ok(count)
+    }
+}
+
+impl StreamHooks for FeedBody {
+    fn peek_nontrivial(&mut self) -> Result<Option<Vec<Buffer>>> {
+        if self.pending.is_empty() {
+            if self.finished {
+                return Ok(Some(Vec::new()));
+            }
+            return Err(error::again());
+        }
+        Ok(Some(vec![self.pending.clone()]))
+    }
+
+    fn consume_nontrivial(&mut self, count: usize) {
+        if count > 0 {
+            self.consumed(count);
+        }
+    }
+} // impl StreamHooks for FeedBody
+
+impl Feed {
+    fn new(disk: &Disk, drained: Action) -> Feed {
+        let uid = UID::new();
+        TRACE!(ATEN_TRANSFORMFEED_CREATE { DISK: disk, STREAM: uid });
+        let body = FeedBody {
+            base: base::StreamBody::new(disk.downgrade(), uid),
+            pending: Buffer::new(),
+            finished: false,
+            drained: drained,
+        };
+        Feed(Link {
+            uid: uid,
+            body: Rc::new(RefCell::new(body)),
+        })
+    }
+
+    fn is_busy(&self) -> bool {
+        !self.0.body.borrow().pending.is_empty()
+    }
+
+    fn supply(&self, data: Buffer) {
+        self.0.body.borrow_mut().pending = data;
+        self.invoke_callback();
+    }
+
+    fn finish(&self) {
+        let mut body = self.0.body.borrow_mut();
+        if !body.finished {
+            body.finished = true;
+            body.base.invoke_callback();
+        }
+    }
+} // impl Feed
+
+#[derive(Debug)]
+enum Outcome {
+    Open,
+    Closing,
+    Closed,
+    Failed(i32),
+}
+
+#[derive(Debug)]
+pub struct SinkBody {
+    base: base::StreamBody,
+    feed: Feed,
+    linger: Linger,
+    outcome: Outcome,
+}
+
+impl SinkBody {
+    fn write_nontrivial(&mut self, buf: &[u8]) -> Result<usize> {
+        if !matches!(self.outcome, Outcome::Open) {
+            return Err(error::badf());
+        }
+        if self.feed.is_busy() {
+            return Err(error::again());
+        }
+        self.feed.supply(Buffer::from(buf.to_vec()));
+        Ok(buf.len())
+    }
+
+    fn flush_nontrivial(&mut self) -> Result<()> {
+        if !matches!(self.outcome, Outcome::Open) {
+            return Err(error::badf());
+        }
+        if self.feed.is_busy() {
+            return Err(error::again());
+        }
+        Ok(())
+    }
+
+    fn close_nontrivial(&mut self) -> Result<()> {
+        match self.outcome {
+            Outcome::Open => {
+                self.feed.finish();
+                self.outcome = Outcome::Closing;
+            }
+            Outcome::Closing => {}
+            Outcome::Closed => {
+                return Ok(());
+            }
+            Outcome::Failed(errno) => {
+                return Err(Error::from_raw_os_error(errno));
+            }
+        }
+        match self.linger.poll() {
+            State::Final(Ok(())) => {
+                self.outcome = Outcome::Closed;
+                Ok(())
+            }
+            State::Final(Err(err)) => {
+                let errno = error::errno(&err).unwrap_or(libc::EIO);
+                self.outcome = Outcome::Failed(errno);
+                Err(err)
+            }
+            _ => {
+                Err(error::again())
+            }
+        }
+    }
+}
+
+// The linger keeps itself alive until its result is claimed, and it
+// would wait forever for a feed nobody writes to any more.
+impl Drop for SinkBody {
+    fn drop(&mut self) {
+        TRACE!(ATEN_TRANSFORMSINK_DROP { SINK: self });
+        self.linger.abort();
+    }
+} // impl Drop for SinkBody
+
+impl Sink {
+    // The transformation is applied to the stream of data written to
+    // the sink, and its output is pumped into the downstream sink.
+    pub fn new<F>(disk: &Disk, downstream: ByteSink, transform: F) -> Sink
+    where F: FnOnce(ByteStream) -> ByteStream {
+        let uid = UID::new();
+        let sink = Rc::new_cyclic(|weak_body| {
+            let weak_body: std::rc::Weak<RefCell<SinkBody>> =
+                weak_body.clone();
+            let notify = Action::new(move || {
+                if let Some(body) = weak_body.upgrade() {
+                    body.borrow().base.invoke_callback();
+                }
+            });
+            let feed = Feed::new(disk, notify.clone());
+            let transformed = transform(feed.as_bytestream());
+            TRACE!(ATEN_TRANSFORMSINK_CREATE {
+                DISK: disk, SINK: uid, FEED: feed, TRANSFORMED: transformed,
+                DOWNSTREAM: downstream,
+            });
+            let linger = Linger::with_sink(disk, transformed, downstream);
+            linger.register_callback(notify);
+            RefCell::new(SinkBody {
+                base: base::StreamBody::new(disk.downgrade(), uid),
+                feed: feed,
+                linger: linger,
+                outcome: Outcome::Open,
+            })
+        });
+        Sink(Link {
+            uid: uid,
+            body: sink,
+        })
+    }
+} // impl Sink
@@ -0,0 +1,100 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::io::Result;
+
+use crate::{Disk, Link, UID, Downgradable, error};
+use crate::sink::BasicSink;
+use crate::stream::base;
+use r3::{TRACE, Traceable};
+
+DECLARE_SINK!(
+    Sink, WeakSink, SinkBody,
+    ATEN_VECSINK_DROP,
+    ATEN_VECSINK_UPPED_MISS,
+    ATEN_VECSINK_REGISTER_CALLBACK,
+    ATEN_VECSINK_UNREGISTER_CALLBACK,
+    ATEN_VECSINK_WRITE,
+    ATEN_VECSINK_WRITE_DUMP,
+    ATEN_VECSINK_WRITE_FAIL,
+    ATEN_VECSINK_CLOSE,
+    ATEN_VECSINK_CLOSE_FAIL);
+
+#[derive(Debug)]
+pub struct SinkBody {
+    base: base::StreamBody,
+    contents: Vec<u8>,
+    limit: Option<usize>,
+    closed: bool,
+}
+
+impl SinkBody {
+    fn write_nontrivial(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.closed {
+            return Err(error::badf());
+        }
+        let count =
+            match self.limit {
+                Some(limit) => {
+                    let room = limit.saturating_sub(self.contents.len());
+                    if room == 0 && !buf.is_empty() {
+                        return Err(error::nospc());
+                    }
+                    buf.len().min(room)
+                }
+                None => buf.len(),
+            };
+        self.contents.extend_from_slice(&buf[..count]);
+        Ok(count)
+    }
+
+    fn flush_nontrivial(&mut self) -> Result<()> {
+        if self.closed {
+            return Err(error::badf());
+        }
+        Ok(())
+    }
+
+    fn close_nontrivial(&mut self) -> Result<()> {
+        self.closed = true;
+        Ok(())
+    }
+}
+
+impl Sink {
+    pub fn new(disk: &Disk, limit: Option<usize>) -> Sink {
+        let uid = UID::new();
+        TRACE!(ATEN_VECSINK_CREATE {
+            DISK: disk, SINK: uid, LIMIT: r3::option(&limit),
+        });
+        let body = SinkBody {
+            base: base::StreamBody::new(disk.downgrade(), uid),
+            contents: Vec::new(),
+            limit: limit,
+            closed: false,
+        };
+        Sink(Link {
+            uid: uid,
+            body: Rc::new(RefCell::new(body)),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.body.borrow().contents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.body.borrow().closed
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.body.borrow().contents.clone()
+    }
+
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.0.body.borrow_mut().contents)
+    }
+} // impl Sink
@@ -3,7 +3,7 @@ use std::cell::RefCell;
 use std::io::Result;
 
 use crate::{Disk, Link, UID, Downgradable};
-use crate::stream::{ByteStream, BasicStream};
+use crate::stream::{ByteStream, BasicStream, StreamHooks};
 use crate::stream::base;
 use r3::{TRACE, Traceable};
 
@@ -16,7 +16,8 @@ DECLARE_STREAM!(
     ATEN_AVIDSTREAM_READ_TRIVIAL,
     ATEN_AVIDSTREAM_READ,
     ATEN_AVIDSTREAM_READ_DUMP,
-    ATEN_AVIDSTREAM_READ_FAIL);
+    ATEN_AVIDSTREAM_READ_FAIL,
+    StreamHooks);
 
 #[derive(Debug)]
 pub struct StreamBody {
@@ -47,6 +48,12 @@ impl StreamBody {
     }
 }
 
+impl StreamHooks for StreamBody {
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        vec![self.wrappee.clone()]
+    }
+} // impl StreamHooks for StreamBody
+
 impl Stream {
     pub fn new(disk: &Disk, wrappee: ByteStream) -> Stream {
         let uid = UID::new();
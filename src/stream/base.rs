@@ -9,6 +9,7 @@ pub struct StreamBody {
     weak_disk: WeakDisk,
     uid: UID,
     callback: Action,
+    closed: bool,
 }
 
 impl std::fmt::Display for StreamBody {
@@ -23,6 +24,7 @@ impl StreamBody {
             weak_disk: weak_disk,
             uid: uid,
             callback: Action::noop(),
+            closed: false,
         }
     }
 
@@ -40,6 +42,19 @@ impl StreamBody {
             disk.execute(self.callback.clone());
         });
     }
+
+    // A closed stream wakes up its reader one last time and fails every
+    // read after that with EBADF.
+    pub fn close(&mut self) {
+        TRACE!(ATEN_BASESTREAM_CLOSE { STREAM: self.uid });
+        self.closed = true;
+        self.invoke_callback();
+        self.callback = Action::noop();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
 } // impl StreamBody
 
 impl ByteStreamBody for StreamBody {
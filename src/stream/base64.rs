@@ -0,0 +1,390 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::io::{Error, Result};
+
+use crate::{Disk, Link, UID, Downgradable};
+use crate::error::{AtenError, Kind};
+use crate::stream::{ByteStream, BasicStream, StreamHooks, base};
+use r3::{TRACE, Traceable};
+
+DECLARE_STREAM!(
+    Encoder, WeakEncoder, EncoderBody,
+    ATEN_BASE64ENCODER_DROP,
+    ATEN_BASE64ENCODER_UPPED_MISS,
+    ATEN_BASE64ENCODER_REGISTER_CALLBACK,
+    ATEN_BASE64ENCODER_UNREGISTER_CALLBACK,
+    ATEN_BASE64ENCODER_READ_TRIVIAL,
+    ATEN_BASE64ENCODER_READ,
+    ATEN_BASE64ENCODER_READ_DUMP,
+    ATEN_BASE64ENCODER_READ_FAIL,
+    StreamHooks);
+
+DECLARE_STREAM!(
+    Decoder, WeakDecoder, DecoderBody,
+    ATEN_BASE64DECODER_DROP,
+    ATEN_BASE64DECODER_UPPED_MISS,
+    ATEN_BASE64DECODER_REGISTER_CALLBACK,
+    ATEN_BASE64DECODER_UNREGISTER_CALLBACK,
+    ATEN_BASE64DECODER_READ_TRIVIAL,
+    ATEN_BASE64DECODER_READ,
+    ATEN_BASE64DECODER_READ_DUMP,
+    ATEN_BASE64DECODER_READ_FAIL,
+    StreamHooks);
+
+#[derive(Debug, Clone, Copy)]
+pub enum Alphabet {
+    Standard,                   // RFC 4648 section 4
+    UrlSafe,                    // RFC 4648 section 5
+}
+
+impl Alphabet {
+    fn symbols(&self) -> &'static [u8; 64] {
+        match self {
+            Alphabet::Standard =>
+                b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/",
+            Alphabet::UrlSafe =>
+                b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_",
+        }
+    }
+
+    fn value(&self, symbol: u8) -> Option<u8> {
+        match symbol {
+            b'A'..=b'Z' => Some(symbol - b'A'),
+            b'a'..=b'z' => Some(symbol - b'a' + 26),
+            b'0'..=b'9' => Some(symbol - b'0' + 52),
+            b'+' if matches!(self, Alphabet::Standard) => Some(62),
+            b'/' if matches!(self, Alphabet::Standard) => Some(63),
+            b'-' if matches!(self, Alphabet::UrlSafe) => Some(62),
+            b'_' if matches!(self, Alphabet::UrlSafe) => Some(63),
+            _ => None,
+        }
+    }
+} // impl Alphabet
+
+impl std::fmt::Display for Alphabet {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+} // impl std::fmt::Display for Alphabet
+
+const BUF_SIZE: usize = 2000;
+
+#[derive(Debug)]
+pub struct EncoderBody {
+    base: base::StreamBody,
+    wrappee: ByteStream,
+    alphabet: Alphabet,
+    padding: bool,
+    line_length: Option<usize>,
+    column: usize,
+    input: [u8; BUF_SIZE],
+    low: usize,
+    high: usize,
+    output: Vec<u8>,
+    cursor: usize,
+    exhausted: bool,
+}
+
+impl EncoderBody {
+    fn emit(&mut self, symbol: u8) {
+        if let Some(line_length) = self.line_length {
+            if self.column == line_length {
+                self.output.extend_from_slice(b"\r\n");
+                self.column = 0;
+            }
+        }
+        self.output.push(symbol);
+        self.column += 1;
+    }
+
+    fn encode_group(&mut self, group: &[u8]) {
+        let symbols = self.alphabet.symbols();
+        let mut bits = 0u32;
+        for (i, byte) in group.iter().enumerate() {
+            bits |= (*byte as u32) << (16 - 8 * i);
+        }
+        for i in 0..=group.len() {
+            self.emit(symbols[(bits >> (18 - 6 * i) & 0x3f) as usize]);
+        }
+        if self.padding {
+            for _ in group.len()..3 {
+                self.emit(b'=');
+            }
+        }
+    }
+
+    fn replenish(&mut self) -> Result<()> {
+        let leftover = self.high - self.low;
+        self.input.copy_within(self.low..self.high, 0);
+        self.low = 0;
+        self.high = leftover;
+        self.output.clear();
+        self.cursor = 0;
+        match self.wrappee.read(&mut self.input[leftover..]) {
+            Ok(0) => {
+                if leftover > 0 {
+                    let group = [self.input[0], self.input[1]];
+                    self.encode_group(&group[..leftover]);
+                }
+                self.high = 0;
+                self.exhausted = true;
+                Ok(())
+            }
+            Ok(count) => {
+                self.high += count;
+                while self.high - self.low >= 3 {
+                    let group = [
+                        self.input[self.low],
+                        self.input[self.low + 1],
+                        self.input[self.low + 2],
+                    ];
+                    self.low += 3;
+                    self.encode_group(&group);
+                }
+                Ok(())
+            }
+            Err(err) => {
+                Err(err)
+            }
+        }
+    }
+
+    fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if self.cursor < self.output.len() {
+                let count = buf.len().min(self.output.len() - self.cursor);
+                buf[..count].copy_from_slice(
+                    &self.output[self.cursor..self.cursor + count]);
+                self.cursor += count;
+                return Ok(count);
+            }
+            if self.exhausted {
+                return Ok(0);
+            }
+            self.replenish()?;
+        }
+    }
+}
+
+impl StreamHooks for EncoderBody {
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        vec![self.wrappee.clone()]
+    }
+} // impl StreamHooks for EncoderBody
+
+impl Encoder {
+    pub fn new(disk: &Disk,
+               wrappee: ByteStream,
+               alphabet: Alphabet,
+               padding: bool,
+               line_length: Option<usize>) -> Result<Encoder> {
+        if matches!(line_length, Some(0)) {
+            return Err(AtenError::new(Kind::Invalid, "zero line length")
+                       .into());
+        }
+        let uid = UID::new();
+        TRACE!(ATEN_BASE64ENCODER_CREATE {
+            DISK: disk, STREAM: uid, WRAPPEE: wrappee, ALPHABET: alphabet,
+            PADDING: padding, LINE_LENGTH: r3::option(&line_length),
+        });
+        let body = Rc::new(RefCell::new(EncoderBody {
+            base: base::StreamBody::new(disk.downgrade(), uid),
+            wrappee: wrappee.clone(),
+            alphabet: alphabet,
+            padding: padding,
+            line_length: line_length,
+            column: 0,
+            input: [0; BUF_SIZE],
+            low: 0,
+            high: 0,
+            output: Vec::new(),
+            cursor: 0,
+            exhausted: false,
+        }));
+        let stream = Encoder(Link {
+            uid: uid,
+            body: body.clone(),
+        });
+        stream.register_wrappee_callback(&wrappee);
+        Ok(stream)
+    }
+} // impl Encoder
+
+#[derive(Debug)]
+enum State {
+    Decoding,
+    Padding(usize),
+    Exhausted,
+    Errored,
+}
+
+#[derive(Debug)]
+pub struct DecoderBody {
+    base: base::StreamBody,
+    wrappee: ByteStream,
+    alphabet: Alphabet,
+    state: State,
+    input: [u8; BUF_SIZE],
+    group: [u8; 4],
+    group_len: usize,
+    output: Vec<u8>,
+    cursor: usize,
+    failure: Option<Error>,
+}
+
+impl DecoderBody {
+    fn malformed(&self, context: &str) -> Error {
+        AtenError::new(Kind::Protocol, context)
+            .at(self.base.get_uid()).into()
+    }
+
+    fn flush_group(&mut self) {
+        let mut bits = 0u32;
+        for i in 0..self.group_len {
+            bits |= (self.group[i] as u32) << (18 - 6 * i);
+        }
+        for i in 0..self.group_len.saturating_sub(1) {
+            self.output.push((bits >> (16 - 8 * i)) as u8);
+        }
+        self.group_len = 0;
+    }
+
+    fn feed(&mut self, symbol: u8) -> Result<()> {
+        if matches!(symbol, b' ' | b'\t' | b'\r' | b'\n') {
+            return Ok(());
+        }
+        match self.state {
+            State::Decoding => {
+                if symbol == b'=' {
+                    if self.group_len < 2 {
+                        return Err(self.malformed("misplaced padding"));
+                    }
+                    self.state = State::Padding(1);
+                    return Ok(());
+                }
+                match self.alphabet.value(symbol) {
+                    Some(value) => {
+                        self.group[self.group_len] = value;
+                        self.group_len += 1;
+                        if self.group_len == 4 {
+                            self.flush_group();
+                        }
+                        Ok(())
+                    }
+                    None => {
+                        Err(self.malformed("invalid symbol"))
+                    }
+                }
+            }
+            State::Padding(count) => {
+                if symbol != b'=' || self.group_len + count >= 4 {
+                    return Err(self.malformed("data after padding"));
+                }
+                self.state = State::Padding(count + 1);
+                Ok(())
+            }
+            State::Exhausted | State::Errored => {
+                unreachable!();
+            }
+        }
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if let State::Padding(count) = self.state {
+            if self.group_len + count != 4 {
+                return Err(self.malformed("incomplete padding"));
+            }
+        }
+        if self.group_len == 1 {
+            return Err(self.malformed("input ends in a group"));
+        }
+        self.flush_group();
+        self.state = State::Exhausted;
+        Ok(())
+    }
+
+    fn decode(&mut self, count: usize) -> Result<()> {
+        if count == 0 {
+            return self.finish();
+        }
+        for i in 0..count {
+            let symbol = self.input[i];
+            self.feed(symbol)?;
+        }
+        Ok(())
+    }
+
+    fn replenish(&mut self) -> Result<()> {
+        self.output.clear();
+        self.cursor = 0;
+        let count = self.wrappee.read(&mut self.input)?;
+        if let Err(err) = self.decode(count) {
+            TRACE!(ATEN_BASE64DECODER_MALFORMED { STREAM: self });
+            // The groups decoded before the error are delivered first.
+            self.failure = Some(err);
+            self.state = State::Errored;
+        }
+        Ok(())
+    }
+
+    fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if self.cursor < self.output.len() {
+                let count = buf.len().min(self.output.len() - self.cursor);
+                buf[..count].copy_from_slice(
+                    &self.output[self.cursor..self.cursor + count]);
+                self.cursor += count;
+                return Ok(count);
+            }
+            match self.state {
+                State::Decoding | State::Padding(_) => {}
+                State::Exhausted => {
+                    return Ok(0);
+                }
+                State::Errored => {
+                    return Err(self.failure.take().unwrap_or_else(
+                        || self.malformed("input was malformed")));
+                }
+            }
+            self.replenish()?;
+        }
+    }
+}
+
+impl StreamHooks for DecoderBody {
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        vec![self.wrappee.clone()]
+    }
+
+    fn get_summary(&self) -> String {
+        format!("{:?}", self.state)
+    }
+} // impl StreamHooks for DecoderBody
+
+impl Decoder {
+    pub fn new(disk: &Disk, wrappee: ByteStream, alphabet: Alphabet)
+               -> Decoder {
+        let uid = UID::new();
+        TRACE!(ATEN_BASE64DECODER_CREATE {
+            DISK: disk, STREAM: uid, WRAPPEE: wrappee, ALPHABET: alphabet,
+        });
+        let body = Rc::new(RefCell::new(DecoderBody {
+            base: base::StreamBody::new(disk.downgrade(), uid),
+            wrappee: wrappee.clone(),
+            alphabet: alphabet,
+            state: State::Decoding,
+            input: [0; BUF_SIZE],
+            group: [0; 4],
+            group_len: 0,
+            output: Vec::new(),
+            cursor: 0,
+            failure: None,
+        }));
+        let stream = Decoder(Link {
+            uid: uid,
+            body: body.clone(),
+        });
+        stream.register_wrappee_callback(&wrappee);
+        stream
+    }
+} // impl Decoder
@@ -3,7 +3,8 @@ use std::cell::RefCell;
 use std::io::Result;
 
 use crate::{Disk, Link, UID, Downgradable};
-use crate::stream::{BasicStream, base};
+use crate::buffer::Buffer;
+use crate::stream::{BasicStream, StreamHooks, base};
 use r3::{TRACE, Traceable};
 
 DECLARE_STREAM!(
@@ -15,39 +16,61 @@ DECLARE_STREAM!(
     ATEN_BLOBSTREAM_READ_TRIVIAL,
     ATEN_BLOBSTREAM_READ,
     ATEN_BLOBSTREAM_READ_DUMP,
-    ATEN_BLOBSTREAM_READ_FAIL);
+    ATEN_BLOBSTREAM_READ_FAIL,
+    StreamHooks);
 
 #[derive(Debug)]
 pub struct StreamBody {
     base: base::StreamBody,
-    blob: Vec<u8>,
-    cursor: usize,
+    blob: Buffer,
 }
 
 impl StreamBody {
     fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let count = buf.len().min(self.blob.len() - self.cursor);
-        for slot in &mut buf[..count].iter_mut() {
-            *slot = self.blob[self.cursor];
-            self.cursor += 1;
-        }
+        let count = buf.len().min(self.blob.len());
+        buf[..count].copy_from_slice(&self.blob[..count]);
+        self.blob.advance(count);
         Ok(count)
     }
 }
 
+impl StreamHooks for StreamBody {
+    fn peek_nontrivial(&mut self) -> Result<Option<Vec<Buffer>>> {
+        if self.blob.is_empty() {
+            return Ok(Some(Vec::new()));
+        }
+        Ok(Some(vec![self.blob.clone()]))
+    }
+
+    fn consume_nontrivial(&mut self, count: usize) {
+        self.blob.advance(count);
+    }
+
+    fn get_summary(&self) -> String {
+        format!("remaining {}", self.blob.len())
+    }
+
+    fn close_nontrivial(&mut self) {
+        self.blob = Buffer::new();
+    }
+} // impl StreamHooks for StreamBody
+
 impl Stream {
     pub fn new(disk: &Disk, blob: Vec<u8>) -> Stream {
+        Self::from_buffer(disk, Buffer::from(blob))
+    }
+
+    pub fn from_buffer(disk: &Disk, blob: Buffer) -> Stream {
         let uid = UID::new();
         TRACE!(ATEN_BLOBSTREAM_CREATE {
             DISK: disk, STREAM: uid, BLOB_LEN: blob.len()
         });
         TRACE!(ATEN_BLOBSTREAM_CREATE_DUMP {
-            STREAM: uid, DATA: r3::octets(&blob)
+            STREAM: uid, DATA: r3::octets(&blob[..])
         });
         let body = StreamBody {
             base: base::StreamBody::new(disk.downgrade(), uid),
             blob: blob,
-            cursor: 0,
         };
         Stream(Link {
             uid: uid,
@@ -0,0 +1,201 @@
+use std::rc::{Rc, Weak};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{Error, Result};
+use std::time::Duration;
+
+use crate::{Disk, Link, Action, UID, Timer, Downgradable, Upgradable, error};
+use crate::stream::{ByteStream, BasicStream, StreamHooks, base};
+use r3::{TRACE, Traceable};
+
+DECLARE_STREAM_NO_DROP!(
+    Stream, WeakStream, StreamBody,
+    ATEN_CHAOSSTREAM_UPPED_MISS,
+    ATEN_CHAOSSTREAM_REGISTER_CALLBACK,
+    ATEN_CHAOSSTREAM_UNREGISTER_CALLBACK,
+    ATEN_CHAOSSTREAM_READ_TRIVIAL,
+    ATEN_CHAOSSTREAM_READ,
+    ATEN_CHAOSSTREAM_READ_DUMP,
+    ATEN_CHAOSSTREAM_READ_FAIL,
+    StreamHooks);
+
+// Probabilities are per read. Each fault is an (offset, errno) pair: the
+// read reaching the offset fails with the errno, once.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub seed: u64,
+    pub short_read_probability: f64,
+    pub again_probability: f64,
+    pub delay_probability: f64,
+    pub max_delay: Duration,
+    pub faults: Vec<(u64, i32)>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            seed: 0,
+            short_read_probability: 0.0,
+            again_probability: 0.0,
+            delay_probability: 0.0,
+            max_delay: Duration::from_millis(10),
+            faults: Vec::new(),
+        }
+    }
+} // impl Default for Config
+
+// xorshift64*, seeded through splitmix64 so that any seed will do.
+#[derive(Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        let mut z = seed.wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        Rng((z ^ (z >> 31)) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 &&
+            ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+
+    // Uniform in 1..=n.
+    fn count(&mut self, n: usize) -> usize {
+        1 + (self.next() % n as u64) as usize
+    }
+} // impl Rng
+
+#[derive(Debug)]
+pub struct StreamBody {
+    base: base::StreamBody,
+    wrappee: ByteStream,
+    config: Config,
+    rng: Rng,
+    faults: VecDeque<(u64, i32)>,
+    position: u64,
+    delay_timer: Option<Timer>,
+    weak_self: Weak<RefCell<Self>>,
+}
+
+impl StreamBody {
+    fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.delay_timer.is_some() {
+            return Err(error::again());
+        }
+        if self.rng.chance(self.config.again_probability) {
+            TRACE!(ATEN_CHAOSSTREAM_INJECT_AGAIN { STREAM: self });
+            self.base.invoke_callback();
+            return Err(error::again());
+        }
+        if self.rng.chance(self.config.delay_probability) {
+            self.delay();
+            return Err(error::again());
+        }
+        let mut want = buf.len();
+        if let Some(&(offset, errno)) = self.faults.front() {
+            if offset <= self.position {
+                TRACE!(ATEN_CHAOSSTREAM_INJECT_FAULT {
+                    STREAM: self, OFFSET: self.position, ERRNO: errno,
+                });
+                self.faults.pop_front();
+                return Err(Error::from_raw_os_error(errno));
+            }
+            want = want.min((offset - self.position) as usize);
+        }
+        if self.rng.chance(self.config.short_read_probability) {
+            want = self.rng.count(want);
+            TRACE!(ATEN_CHAOSSTREAM_INJECT_SHORT_READ {
+                STREAM: self, WANT: buf.len(), LIMIT: want,
+            });
+        }
+        let count = self.wrappee.read(&mut buf[..want])?;
+        self.position += count as u64;
+        Ok(count)
+    }
+
+    fn delay(&mut self) {
+        let disk =
+            match self.base.get_weak_disk().upgrade() {
+                Some(disk) => disk,
+                None => { return; }
+            };
+        let max_nanos = self.config.max_delay.as_nanos().max(1) as u64;
+        let delay = Duration::from_nanos(self.rng.next() % max_nanos);
+        TRACE!(ATEN_CHAOSSTREAM_INJECT_DELAY {
+            STREAM: self, DELAY: delay.as_secs_f64(),
+        });
+        let weak_self = self.weak_self.clone();
+        self.delay_timer = Some(disk.schedule(
+            disk.now() + delay,
+            Action::new(move || {
+                if let Some(body) = weak_self.upgrade() {
+                    let mut body = body.borrow_mut();
+                    body.delay_timer = None;
+                    body.base.invoke_callback();
+                }
+            })));
+    }
+} // impl StreamBody
+
+impl StreamHooks for StreamBody {
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        vec![self.wrappee.clone()]
+    }
+
+    fn get_summary(&self) -> String {
+        format!("position {}", self.position)
+    }
+
+    fn close_nontrivial(&mut self) {
+        if let Some(timer) = self.delay_timer.take() {
+            timer.cancel();
+        }
+    }
+} // impl StreamHooks for StreamBody
+
+impl Drop for StreamBody {
+    fn drop(&mut self) {
+        TRACE!(ATEN_CHAOSSTREAM_DROP { STREAM: self });
+        if let Some(timer) = self.delay_timer.take() {
+            timer.cancel();
+        }
+    }
+} // impl Drop for StreamBody
+
+impl Stream {
+    pub fn new(disk: &Disk, wrappee: ByteStream, config: Config) -> Stream {
+        let uid = UID::new();
+        TRACE!(ATEN_CHAOSSTREAM_CREATE {
+            DISK: disk, STREAM: uid, WRAPPEE: wrappee,
+            CONFIG: format!("{:?}", config),
+        });
+        let mut faults = config.faults.clone();
+        faults.sort();
+        let body = Rc::new_cyclic(
+            |weak_self| RefCell::new(StreamBody {
+                base: base::StreamBody::new(disk.downgrade(), uid),
+                wrappee: wrappee.clone(),
+                rng: Rng::new(config.seed),
+                config: config,
+                faults: faults.into(),
+                position: 0,
+                delay_timer: None,
+                weak_self: weak_self.clone(),
+            }));
+        let stream = Stream(Link {
+            uid: uid,
+            body: body,
+        });
+        stream.register_wrappee_callback(&wrappee);
+        stream
+    }
+} // impl Stream
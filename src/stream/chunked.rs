@@ -0,0 +1,418 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::io::{Error, Result};
+
+use crate::{Disk, Link, UID, Downgradable, Upgradable, error};
+use crate::error::{AtenError, Kind};
+use crate::stream::{ByteStream, BasicStream, StreamHooks, base, queue, blob};
+use r3::{TRACE, Traceable};
+
+DECLARE_STREAM!(
+    Encoder, WeakEncoder, EncoderBody,
+    ATEN_CHUNKEDENCODER_DROP,
+    ATEN_CHUNKEDENCODER_UPPED_MISS,
+    ATEN_CHUNKEDENCODER_REGISTER_CALLBACK,
+    ATEN_CHUNKEDENCODER_UNREGISTER_CALLBACK,
+    ATEN_CHUNKEDENCODER_READ_TRIVIAL,
+    ATEN_CHUNKEDENCODER_READ,
+    ATEN_CHUNKEDENCODER_READ_DUMP,
+    ATEN_CHUNKEDENCODER_READ_FAIL,
+    StreamHooks);
+
+DECLARE_STREAM!(
+    Decoder, WeakDecoder, DecoderBody,
+    ATEN_CHUNKEDDECODER_DROP,
+    ATEN_CHUNKEDDECODER_UPPED_MISS,
+    ATEN_CHUNKEDDECODER_REGISTER_CALLBACK,
+    ATEN_CHUNKEDDECODER_UNREGISTER_CALLBACK,
+    ATEN_CHUNKEDDECODER_READ_TRIVIAL,
+    ATEN_CHUNKEDDECODER_READ,
+    ATEN_CHUNKEDDECODER_READ_DUMP,
+    ATEN_CHUNKEDDECODER_READ_FAIL,
+    StreamHooks);
+
+pub type Trailers = Vec<(String, String)>;
+
+const BUF_SIZE: usize = 2000;
+const MAX_LINE: usize = 8192;
+const MAX_TRAILERS: usize = 65536;
+
+fn is_valid_trailer(name: &str, value: &str) -> bool {
+    !name.is_empty() &&
+        name.bytes().all(|b| b.is_ascii_graphic() && b != b':') &&
+        value.bytes().all(|b| b != b'\r' && b != b'\n')
+}
+
+#[derive(Debug)]
+pub struct EncoderBody {
+    base: base::StreamBody,
+    wrappee: ByteStream,
+    trailers: Trailers,
+    input: [u8; BUF_SIZE],
+    framing: Vec<u8>,
+    cursor: usize,
+    exhausted: bool,
+}
+
+impl EncoderBody {
+    fn frame_chunk(&mut self, count: usize) {
+        self.framing.clear();
+        self.cursor = 0;
+        self.framing.extend_from_slice(format!("{:x}\r\n", count).as_bytes());
+        self.framing.extend_from_slice(&self.input[..count]);
+        self.framing.extend_from_slice(b"\r\n");
+    }
+
+    fn frame_last_chunk(&mut self) {
+        self.framing.clear();
+        self.cursor = 0;
+        self.framing.extend_from_slice(b"0\r\n");
+        for (name, value) in &self.trailers {
+            self.framing.extend_from_slice(
+                format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        self.framing.extend_from_slice(b"\r\n");
+        self.exhausted = true;
+    }
+
+    fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if self.cursor < self.framing.len() {
+                let count = buf.len().min(self.framing.len() - self.cursor);
+                buf[..count].copy_from_slice(
+                    &self.framing[self.cursor..self.cursor + count]);
+                self.cursor += count;
+                return Ok(count);
+            }
+            if self.exhausted {
+                return Ok(0);
+            }
+            match self.wrappee.read(&mut self.input)? {
+                0 => {
+                    self.frame_last_chunk();
+                }
+                count => {
+                    self.frame_chunk(count);
+                }
+            }
+        }
+    }
+}
+
+impl StreamHooks for EncoderBody {
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        vec![self.wrappee.clone()]
+    }
+} // impl StreamHooks for EncoderBody
+
+impl Encoder {
+    pub fn new(disk: &Disk, wrappee: ByteStream) -> Encoder {
+        let uid = UID::new();
+        TRACE!(ATEN_CHUNKEDENCODER_CREATE {
+            DISK: disk, STREAM: uid, WRAPPEE: wrappee,
+        });
+        let body = Rc::new(RefCell::new(EncoderBody {
+            base: base::StreamBody::new(disk.downgrade(), uid),
+            wrappee: wrappee.clone(),
+            trailers: Vec::new(),
+            input: [0; BUF_SIZE],
+            framing: Vec::new(),
+            cursor: 0,
+            exhausted: false,
+        }));
+        let stream = Encoder(Link {
+            uid: uid,
+            body: body.clone(),
+        });
+        stream.register_wrappee_callback(&wrappee);
+        stream
+    }
+
+    pub fn set_trailers(&self, trailers: Trailers) -> Result<()> {
+        let mut body = self.0.body.borrow_mut();
+        if body.exhausted ||
+            !trailers.iter().all(
+                |(name, value)| is_valid_trailer(name, value)) {
+            TRACE!(ATEN_CHUNKEDENCODER_SET_TRAILERS_FAIL { STREAM: self });
+            return Err(error::inval());
+        }
+        TRACE!(ATEN_CHUNKEDENCODER_SET_TRAILERS {
+            STREAM: self, COUNT: trailers.len(),
+        });
+        body.trailers = trailers;
+        Ok(())
+    }
+} // impl Encoder
+
+#[derive(Debug)]
+enum State {
+    Size,
+    Data(u64),
+    DataEnd,
+    Trailer,
+    Terminated(ByteStream),
+    Errored,
+}
+
+#[derive(Debug)]
+pub struct DecoderBody {
+    base: base::StreamBody,
+    wrappee: ByteStream,
+    state: State,
+    input: [u8; BUF_SIZE],
+    low: usize,
+    high: usize,
+    line: Vec<u8>,
+    trailers: Trailers,
+    trailer_size: usize,
+}
+
+fn parse_size(line: &[u8]) -> Option<u64> {
+    let digits = match line.iter().position(|b| *b == b';') {
+        Some(semicolon) => &line[..semicolon],
+        None => line,
+    };
+    let digits = std::str::from_utf8(digits).ok()?.trim_matches(
+        |c| c == ' ' || c == '\t');
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u64::from_str_radix(digits, 16).ok()
+}
+
+fn parse_trailer(line: &[u8]) -> Option<(String, String)> {
+    let line = std::str::from_utf8(line).ok()?;
+    let (name, value) = line.split_once(':')?;
+    let value = value.trim_matches(|c| c == ' ' || c == '\t');
+    if !is_valid_trailer(name, value) {
+        return None;
+    }
+    Some((name.to_string(), value.to_string()))
+}
+
+impl DecoderBody {
+    fn malformed(&mut self, context: &str) -> Error {
+        TRACE!(ATEN_CHUNKEDDECODER_MALFORMED {
+            STREAM: self, CONTEXT: context,
+        });
+        self.state = State::Errored;
+        AtenError::new(Kind::Protocol, context)
+            .at(self.base.get_uid()).into()
+    }
+
+    fn take_line(&mut self) -> Result<Option<Vec<u8>>> {
+        let pending = &self.input[self.low..self.high];
+        match pending.iter().position(|b| *b == b'\n') {
+            Some(lf) => {
+                self.line.extend_from_slice(&pending[..lf]);
+                self.low += lf + 1;
+                if self.line.last() == Some(&b'\r') {
+                    self.line.pop();
+                }
+                Ok(Some(std::mem::take(&mut self.line)))
+            }
+            None => {
+                self.line.extend_from_slice(pending);
+                self.low = self.high;
+                if self.line.len() > MAX_LINE {
+                    return Err(self.malformed(
+                        &format!("line exceeds {} bytes", MAX_LINE)));
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    fn terminate(&mut self) -> Result<()> {
+        if self.low >= self.high {
+            self.state = State::Terminated(self.wrappee.clone());
+            return Ok(());
+        }
+        match self.base.get_weak_disk().upgrade() {
+            Some(disk) => {
+                let q = queue::Stream::new(&disk, None);
+                q.enqueue(
+                    blob::Stream::new(
+                        &disk, self.input[self.low..self.high].to_vec())
+                        .as_bytestream());
+                q.enqueue(self.wrappee.clone());
+                q.terminate();
+                self.low = self.high;
+                self.state = State::Terminated(q.as_bytestream());
+                Ok(())
+            }
+            None => {
+                Err(error::badf())
+            }
+        }
+    }
+
+    fn parse(&mut self) -> Result<()> {
+        let line =
+            match self.take_line()? {
+                Some(line) => line,
+                None => { return Ok(()); }
+            };
+        match self.state {
+            State::Size => {
+                match parse_size(&line) {
+                    Some(0) => {
+                        self.state = State::Trailer;
+                    }
+                    Some(size) => {
+                        self.state = State::Data(size);
+                    }
+                    None => {
+                        return Err(self.malformed("bad chunk size"));
+                    }
+                }
+            }
+            State::DataEnd => {
+                if !line.is_empty() {
+                    return Err(self.malformed("no line end after chunk"));
+                }
+                self.state = State::Size;
+            }
+            State::Trailer => {
+                if line.is_empty() {
+                    TRACE!(ATEN_CHUNKEDDECODER_TERMINATED { STREAM: self });
+                    return self.terminate();
+                }
+                self.trailer_size += line.len();
+                if self.trailer_size > MAX_TRAILERS {
+                    return Err(self.malformed(
+                        &format!("trailers exceed {} bytes", MAX_TRAILERS)));
+                }
+                match parse_trailer(&line) {
+                    Some(trailer) => {
+                        self.trailers.push(trailer);
+                    }
+                    None => {
+                        return Err(self.malformed("bad trailer"));
+                    }
+                }
+            }
+            _ => {
+                unreachable!();
+            }
+        }
+        Ok(())
+    }
+
+    fn read_data(&mut self, buf: &mut [u8], remaining: u64) -> Result<usize> {
+        let want = (buf.len() as u64).min(remaining) as usize;
+        let count =
+            if self.low < self.high {
+                let count = want.min(self.high - self.low);
+                buf[..count].copy_from_slice(
+                    &self.input[self.low..self.low + count]);
+                self.low += count;
+                count
+            } else {
+                match self.wrappee.read(&mut buf[..want])? {
+                    0 => {
+                        return Err(self.malformed("input ends in chunk"));
+                    }
+                    count => count,
+                }
+            };
+        let remaining = remaining - count as u64;
+        self.state =
+            if remaining == 0 {
+                State::DataEnd
+            } else {
+                State::Data(remaining)
+            };
+        Ok(count)
+    }
+
+    fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            match self.state {
+                State::Data(remaining) => {
+                    return self.read_data(buf, remaining);
+                }
+                State::Terminated(_) => {
+                    return Ok(0);
+                }
+                State::Errored => {
+                    return Err(error::proto());
+                }
+                State::Size | State::DataEnd | State::Trailer => {}
+            }
+            if self.low >= self.high {
+                match self.wrappee.read(&mut self.input)? {
+                    0 => {
+                        return Err(self.malformed("input ends in framing"));
+                    }
+                    count => {
+                        self.low = 0;
+                        self.high = count;
+                    }
+                }
+            }
+            self.parse()?;
+        }
+    }
+}
+
+impl StreamHooks for DecoderBody {
+    // Once terminated, the wrappee belongs to the remainder.
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        match self.state {
+            State::Terminated(_) => Vec::new(),
+            _ => vec![self.wrappee.clone()],
+        }
+    }
+
+    fn get_summary(&self) -> String {
+        match &self.state {
+            State::Terminated(_) => "Terminated".to_string(),
+            state => format!("{:?}", state),
+        }
+    }
+} // impl StreamHooks for DecoderBody
+
+impl Decoder {
+    pub fn new(disk: &Disk, wrappee: ByteStream) -> Decoder {
+        let uid = UID::new();
+        TRACE!(ATEN_CHUNKEDDECODER_CREATE {
+            DISK: disk, STREAM: uid, WRAPPEE: wrappee,
+        });
+        let body = Rc::new(RefCell::new(DecoderBody {
+            base: base::StreamBody::new(disk.downgrade(), uid),
+            wrappee: wrappee.clone(),
+            state: State::Size,
+            input: [0; BUF_SIZE],
+            low: 0,
+            high: 0,
+            line: Vec::new(),
+            trailers: Vec::new(),
+            trailer_size: 0,
+        }));
+        let stream = Decoder(Link {
+            uid: uid,
+            body: body.clone(),
+        });
+        stream.register_wrappee_callback(&wrappee);
+        stream
+    }
+
+    pub fn trailers(&self) -> Option<Trailers> {
+        let body = self.0.body.borrow();
+        if let State::Terminated(_) = &body.state {
+            Some(body.trailers.clone())
+        } else {
+            None
+        }
+    }
+
+    pub fn remainder(&self) -> Option<ByteStream> {
+        let body = self.0.body.borrow();
+        if let State::Terminated(stream) = &body.state {
+            Some(stream.clone())
+        } else {
+            None
+        }
+    }
+} // impl Decoder
@@ -0,0 +1,545 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::io::{Error, Result};
+
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus, StreamResult};
+use miniz_oxide::deflate::core::{CompressorOxide};
+use miniz_oxide::deflate::core::create_comp_flags_from_zip_params;
+use miniz_oxide::inflate::stream::InflateState;
+
+use crate::{Disk, Link, UID, Downgradable};
+use crate::error::{AtenError, Kind};
+use crate::stream::{ByteStream, BasicStream, StreamHooks, base};
+use r3::{TRACE, Traceable};
+
+DECLARE_STREAM!(
+    Encoder, WeakEncoder, EncoderBody,
+    ATEN_DEFLATEENCODER_DROP,
+    ATEN_DEFLATEENCODER_UPPED_MISS,
+    ATEN_DEFLATEENCODER_REGISTER_CALLBACK,
+    ATEN_DEFLATEENCODER_UNREGISTER_CALLBACK,
+    ATEN_DEFLATEENCODER_READ_TRIVIAL,
+    ATEN_DEFLATEENCODER_READ,
+    ATEN_DEFLATEENCODER_READ_DUMP,
+    ATEN_DEFLATEENCODER_READ_FAIL,
+    StreamHooks);
+
+DECLARE_STREAM!(
+    Decoder, WeakDecoder, DecoderBody,
+    ATEN_DEFLATEDECODER_DROP,
+    ATEN_DEFLATEDECODER_UPPED_MISS,
+    ATEN_DEFLATEDECODER_REGISTER_CALLBACK,
+    ATEN_DEFLATEDECODER_UNREGISTER_CALLBACK,
+    ATEN_DEFLATEDECODER_READ_TRIVIAL,
+    ATEN_DEFLATEDECODER_READ,
+    ATEN_DEFLATEDECODER_READ_DUMP,
+    ATEN_DEFLATEDECODER_READ_FAIL,
+    StreamHooks);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Raw,                        // RFC 1951
+    Zlib,                       // RFC 1950
+    Gzip,                       // RFC 1952
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+} // impl std::fmt::Display for Format
+
+pub const MAX_LEVEL: u8 = 10;
+
+const BUF_SIZE: usize = 4000;
+const MAX_GZIP_HEADER: usize = 65536;
+
+const GZIP_FHCRC: u8 = 0x02;
+const GZIP_FEXTRA: u8 = 0x04;
+const GZIP_FNAME: u8 = 0x08;
+const GZIP_FCOMMENT: u8 = 0x10;
+
+const CRC_TABLE: [u32; 256] = make_crc_table();
+
+const fn make_crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut c = !crc;
+    for byte in data {
+        c = CRC_TABLE[((c ^ *byte as u32) & 0xff) as usize] ^ (c >> 8);
+    }
+    !c
+}
+
+fn failure(err: MZError) -> AtenError {
+    let kind =
+        match err {
+            MZError::Param => Kind::Invalid,
+            _ => Kind::Protocol,
+        };
+    AtenError::new(kind, format!("{:?}", err))
+}
+
+#[derive(Debug)]
+enum EncoderState {
+    Header,
+    Body,
+    Trailer,
+    Done,
+}
+
+pub struct EncoderBody {
+    base: base::StreamBody,
+    wrappee: ByteStream,
+    format: Format,
+    level: u8,
+    state: EncoderState,
+    compressor: Box<CompressorOxide>,
+    input: [u8; BUF_SIZE],
+    low: usize,
+    high: usize,
+    input_exhausted: bool,
+    framing: Vec<u8>,
+    cursor: usize,
+    crc: u32,
+    size: u32,
+}
+
+impl EncoderBody {
+    fn gzip_header(&self) -> Vec<u8> {
+        let xfl = match self.level {
+            0 | 1 => 4,
+            9 | 10 => 2,
+            _ => 0,
+        };
+        vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, xfl, 3]
+    }
+
+    fn gzip_trailer(&self) -> Vec<u8> {
+        let mut trailer = self.crc.to_le_bytes().to_vec();
+        trailer.extend_from_slice(&self.size.to_le_bytes());
+        trailer
+    }
+
+    fn compress(&mut self, buf: &mut [u8]) -> Result<Option<usize>> {
+        let flush =
+            if self.input_exhausted { MZFlush::Finish } else { MZFlush::None };
+        let StreamResult { bytes_consumed, bytes_written, status } =
+            miniz_oxide::deflate::stream::deflate(
+                &mut self.compressor, &self.input[self.low..self.high], buf,
+                flush);
+        let consumed = &self.input[self.low..self.low + bytes_consumed];
+        self.crc = crc32(self.crc, consumed);
+        self.size = self.size.wrapping_add(bytes_consumed as u32);
+        self.low += bytes_consumed;
+        match status {
+            Ok(MZStatus::StreamEnd) => {
+                self.state = EncoderState::Trailer;
+            }
+            Ok(_) | Err(MZError::Buf) => {}
+            Err(err) => {
+                return Err(failure(err).at(self.base.get_uid()).into());
+            }
+        }
+        if bytes_written > 0 {
+            return Ok(Some(bytes_written));
+        }
+        Ok(None)
+    }
+
+    fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if self.cursor < self.framing.len() {
+                let count = buf.len().min(self.framing.len() - self.cursor);
+                buf[..count].copy_from_slice(
+                    &self.framing[self.cursor..self.cursor + count]);
+                self.cursor += count;
+                return Ok(count);
+            }
+            match self.state {
+                EncoderState::Header => {
+                    if self.format == Format::Gzip {
+                        self.framing = self.gzip_header();
+                        self.cursor = 0;
+                    }
+                    self.state = EncoderState::Body;
+                }
+                EncoderState::Body => {
+                    if let Some(count) = self.compress(buf)? {
+                        return Ok(count);
+                    }
+                    if matches!(self.state, EncoderState::Body) &&
+                        self.low >= self.high {
+                        match self.wrappee.read(&mut self.input)? {
+                            0 => {
+                                self.input_exhausted = true;
+                            }
+                            count => {
+                                self.low = 0;
+                                self.high = count;
+                            }
+                        }
+                    }
+                }
+                EncoderState::Trailer => {
+                    if self.format == Format::Gzip {
+                        self.framing = self.gzip_trailer();
+                        self.cursor = 0;
+                    }
+                    self.state = EncoderState::Done;
+                }
+                EncoderState::Done => {
+                    return Ok(0);
+                }
+            }
+        }
+    }
+}
+
+impl StreamHooks for EncoderBody {
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        vec![self.wrappee.clone()]
+    }
+
+    fn get_summary(&self) -> String {
+        format!("{:?}", self.state)
+    }
+} // impl StreamHooks for EncoderBody
+
+impl std::fmt::Debug for EncoderBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("deflate::Encoder")
+         .field("base", &self.base)
+         .field("wrappee", &self.wrappee)
+         .field("format", &self.format)
+         .field("level", &self.level)
+         .field("state", &self.state)
+         .field("low", &self.low)
+         .field("high", &self.high)
+         .field("input_exhausted", &self.input_exhausted)
+         .field("size", &self.size)
+         .finish()
+    }
+} // impl std::fmt::Debug for EncoderBody
+
+impl Encoder {
+    pub fn new(disk: &Disk, wrappee: ByteStream, format: Format, level: u8)
+               -> Result<Encoder> {
+        if level > MAX_LEVEL {
+            return Err(AtenError::new(Kind::Invalid, "level out of range")
+                       .into());
+        }
+        let window_bits = match format {
+            Format::Zlib => 15,
+            Format::Raw | Format::Gzip => -15,
+        };
+        let flags = create_comp_flags_from_zip_params(
+            level as i32, window_bits, 0);
+        let uid = UID::new();
+        TRACE!(ATEN_DEFLATEENCODER_CREATE {
+            DISK: disk, STREAM: uid, WRAPPEE: wrappee, FORMAT: format,
+            LEVEL: level,
+        });
+        let body = Rc::new(RefCell::new(EncoderBody {
+            base: base::StreamBody::new(disk.downgrade(), uid),
+            wrappee: wrappee.clone(),
+            format: format,
+            level: level,
+            state: EncoderState::Header,
+            compressor: Box::new(CompressorOxide::new(flags)),
+            input: [0; BUF_SIZE],
+            low: 0,
+            high: 0,
+            input_exhausted: false,
+            framing: Vec::new(),
+            cursor: 0,
+            crc: 0,
+            size: 0,
+        }));
+        let stream = Encoder(Link {
+            uid: uid,
+            body: body.clone(),
+        });
+        stream.register_wrappee_callback(&wrappee);
+        Ok(stream)
+    }
+} // impl Encoder
+
+#[derive(Debug)]
+enum DecoderState {
+    Header,
+    Body,
+    Trailer,
+    Done,
+    Errored,
+}
+
+pub struct DecoderBody {
+    base: base::StreamBody,
+    wrappee: ByteStream,
+    format: Format,
+    state: DecoderState,
+    inflater: Box<InflateState>,
+    input: [u8; BUF_SIZE],
+    low: usize,
+    high: usize,
+    framing: Vec<u8>,
+    members: usize,
+    crc: u32,
+    size: u32,
+}
+
+fn skip_field(header: &[u8], pos: usize) -> Option<usize> {
+    header[pos.min(header.len())..].iter().position(|b| *b == 0)
+        .map(|offset| pos + offset + 1)
+}
+
+fn gzip_header_length(header: &[u8])
+                      -> std::result::Result<Option<usize>, AtenError> {
+    if header.len() < 10 {
+        return Ok(None);
+    }
+    if header[0] != 0x1f || header[1] != 0x8b || header[2] != 8 ||
+        header[3] & 0xe0 != 0 {
+        return Err(AtenError::new(Kind::Protocol, "bad gzip header"));
+    }
+    let flags = header[3];
+    let mut pos = 10;
+    if flags & GZIP_FEXTRA != 0 {
+        if header.len() < pos + 2 {
+            return Ok(None);
+        }
+        pos += 2 + u16::from_le_bytes([header[pos], header[pos + 1]]) as usize;
+    }
+    if flags & GZIP_FNAME != 0 {
+        match skip_field(header, pos) {
+            Some(next) => { pos = next; }
+            None => { return Ok(None); }
+        }
+    }
+    if flags & GZIP_FCOMMENT != 0 {
+        match skip_field(header, pos) {
+            Some(next) => { pos = next; }
+            None => { return Ok(None); }
+        }
+    }
+    if flags & GZIP_FHCRC != 0 {
+        pos += 2;
+    }
+    if header.len() < pos {
+        return Ok(None);
+    }
+    Ok(Some(pos))
+}
+
+impl DecoderBody {
+    fn malformed(&mut self, err: AtenError) -> Error {
+        TRACE!(ATEN_DEFLATEDECODER_MALFORMED {
+            STREAM: self, CONTEXT: err.context()
+        });
+        self.state = DecoderState::Errored;
+        err.at(self.base.get_uid()).into()
+    }
+
+    fn parse_header(&mut self) -> Result<()> {
+        let previous = self.framing.len();
+        self.framing.extend_from_slice(&self.input[self.low..self.high]);
+        match gzip_header_length(&self.framing) {
+            Err(err) => {
+                Err(self.malformed(err))
+            }
+            Ok(Some(length)) => {
+                self.low += length - previous;
+                self.framing.clear();
+                self.state = DecoderState::Body;
+                Ok(())
+            }
+            Ok(None) => {
+                if self.framing.len() > MAX_GZIP_HEADER {
+                    return Err(self.malformed(AtenError::new(
+                        Kind::Protocol, "gzip header too long")));
+                }
+                self.low = self.high;
+                Ok(())
+            }
+        }
+    }
+
+    fn parse_trailer(&mut self) -> Result<()> {
+        let count = (8 - self.framing.len()).min(self.high - self.low);
+        self.framing.extend_from_slice(&self.input[self.low..self.low + count]);
+        self.low += count;
+        if self.framing.len() < 8 {
+            return Ok(());
+        }
+        let crc = u32::from_le_bytes(self.framing[..4].try_into().unwrap());
+        let size = u32::from_le_bytes(self.framing[4..].try_into().unwrap());
+        if crc != self.crc || size != self.size {
+            return Err(self.malformed(AtenError::new(
+                Kind::Protocol, "gzip trailer mismatch")));
+        }
+        self.framing.clear();
+        self.members += 1;
+        self.crc = 0;
+        self.size = 0;
+        self.inflater.reset(DataFormat::Raw);
+        self.state = DecoderState::Header;
+        Ok(())
+    }
+
+    fn decompress(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let StreamResult { bytes_consumed, bytes_written, status } =
+            miniz_oxide::inflate::stream::inflate(
+                &mut self.inflater, &self.input[self.low..self.high], buf,
+                MZFlush::None);
+        self.low += bytes_consumed;
+        self.crc = crc32(self.crc, &buf[..bytes_written]);
+        self.size = self.size.wrapping_add(bytes_written as u32);
+        match status {
+            Ok(MZStatus::StreamEnd) => {
+                self.state =
+                    if self.format == Format::Gzip {
+                        DecoderState::Trailer
+                    } else {
+                        DecoderState::Done
+                    };
+            }
+            Ok(_) | Err(MZError::Buf) => {}
+            Err(err) => {
+                return Err(self.malformed(failure(err)));
+            }
+        }
+        Ok(bytes_written)
+    }
+
+    fn replenish(&mut self) -> Result<()> {
+        let count = self.wrappee.read(&mut self.input)?;
+        self.low = 0;
+        self.high = count;
+        if count > 0 {
+            return Ok(());
+        }
+        match self.state {
+            DecoderState::Header
+                if self.members > 0 && self.framing.is_empty() => {
+                    self.state = DecoderState::Done;
+                    Ok(())
+                }
+            _ => {
+                Err(self.malformed(AtenError::new(
+                    Kind::Protocol, "input ends in a member")))
+            }
+        }
+    }
+
+    fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            match self.state {
+                DecoderState::Header => {
+                    if self.low < self.high {
+                        self.parse_header()?;
+                        continue;
+                    }
+                }
+                DecoderState::Body => {
+                    let count = self.decompress(buf)?;
+                    if count > 0 {
+                        return Ok(count);
+                    }
+                    if !matches!(self.state, DecoderState::Body) ||
+                        self.low < self.high {
+                        continue;
+                    }
+                }
+                DecoderState::Trailer => {
+                    if self.low < self.high {
+                        self.parse_trailer()?;
+                        continue;
+                    }
+                }
+                DecoderState::Done => {
+                    return Ok(0);
+                }
+                DecoderState::Errored => {
+                    return Err(AtenError::new(
+                        Kind::Protocol, "input was malformed")
+                               .at(self.base.get_uid()).into());
+                }
+            }
+            self.replenish()?;
+        }
+    }
+}
+
+impl StreamHooks for DecoderBody {
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        vec![self.wrappee.clone()]
+    }
+
+    fn get_summary(&self) -> String {
+        format!("{:?}", self.state)
+    }
+} // impl StreamHooks for DecoderBody
+
+impl std::fmt::Debug for DecoderBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("deflate::Decoder")
+         .field("base", &self.base)
+         .field("wrappee", &self.wrappee)
+         .field("format", &self.format)
+         .field("state", &self.state)
+         .field("low", &self.low)
+         .field("high", &self.high)
+         .field("members", &self.members)
+         .field("size", &self.size)
+         .finish()
+    }
+} // impl std::fmt::Debug for DecoderBody
+
+impl Decoder {
+    pub fn new(disk: &Disk, wrappee: ByteStream, format: Format) -> Decoder {
+        let uid = UID::new();
+        TRACE!(ATEN_DEFLATEDECODER_CREATE {
+            DISK: disk, STREAM: uid, WRAPPEE: wrappee, FORMAT: format,
+        });
+        let (data_format, state) = match format {
+            Format::Raw => (DataFormat::Raw, DecoderState::Body),
+            Format::Zlib => (DataFormat::Zlib, DecoderState::Body),
+            Format::Gzip => (DataFormat::Raw, DecoderState::Header),
+        };
+        let body = Rc::new(RefCell::new(DecoderBody {
+            base: base::StreamBody::new(disk.downgrade(), uid),
+            wrappee: wrappee.clone(),
+            format: format,
+            state: state,
+            inflater: InflateState::new_boxed(data_format),
+            input: [0; BUF_SIZE],
+            low: 0,
+            high: 0,
+            framing: Vec::new(),
+            members: 0,
+            crc: 0,
+            size: 0,
+        }));
+        let stream = Decoder(Link {
+            uid: uid,
+            body: body.clone(),
+        });
+        stream.register_wrappee_callback(&wrappee);
+        stream
+    }
+} // impl Decoder
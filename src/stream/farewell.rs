@@ -3,7 +3,7 @@ use std::cell::RefCell;
 use std::io::Result;
 
 use crate::{Disk, Link, UID, Action, Downgradable, Upgradable};
-use crate::stream::{ByteStream, BasicStream, base};
+use crate::stream::{ByteStream, BasicStream, StreamHooks, base};
 use r3::{TRACE, Traceable};
 
 DECLARE_STREAM_NO_DROP!(
@@ -14,7 +14,8 @@ DECLARE_STREAM_NO_DROP!(
     ATEN_FAREWELLSTREAM_READ_TRIVIAL,
     ATEN_FAREWELLSTREAM_READ,
     ATEN_FAREWELLSTREAM_READ_DUMP,
-    ATEN_FAREWELLSTREAM_READ_FAIL);
+    ATEN_FAREWELLSTREAM_READ_FAIL,
+    StreamHooks);
 
 #[derive(Debug)]
 pub struct StreamBody {
@@ -29,6 +30,12 @@ impl StreamBody {
     }
 } // impl StreamBody
 
+impl StreamHooks for StreamBody {
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        vec![self.wrappee.clone()]
+    }
+} // impl StreamHooks for StreamBody
+
 impl Drop for StreamBody {
     fn drop(&mut self) {
         TRACE!(ATEN_FAREWELLSTREAM_DROP { STREAM: self });
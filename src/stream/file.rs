@@ -1,10 +1,13 @@
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::io::{Result, Error};
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::io::AsRawFd;
+use std::path::Path;
 
 use crate::{Disk, Link, Action, UID, Registration, Fd, Downgradable, Upgradable};
-use crate::stream::{BasicStream, base};
+use crate::{error};
+use crate::stream::{BasicStream, StreamHooks, SpliceSource, base};
 use r3::{TRACE, Traceable};
 
 DECLARE_STREAM!(
@@ -16,36 +19,173 @@ DECLARE_STREAM!(
     ATEN_FILESTREAM_READ_TRIVIAL,
     ATEN_FILESTREAM_READ,
     ATEN_FILESTREAM_READ_DUMP,
-    ATEN_FILESTREAM_READ_FAIL);
+    ATEN_FILESTREAM_READ_FAIL,
+    StreamHooks);
+
+#[derive(Debug, Clone, Copy)]
+pub enum Advice {
+    Normal,
+    Sequential,
+    Random,
+    NoReuse,
+    WillNeed,
+    DontNeed,
+}
+
+impl std::fmt::Display for Advice {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+} // impl std::fmt::Display for Advice
 
 #[derive(Debug)]
 pub struct StreamBody {
     base: base::StreamBody,
     fd: Fd,
     registration: Option<Registration>,
+    position: Option<u64>,      // Some: read with pread(2)
+    remaining: Option<u64>,
 }
 
 impl StreamBody {
     fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let count = unsafe {
-            libc::read(self.fd.as_raw_fd(),
-                       buf.as_mut_ptr() as *mut libc::c_void, buf.len())
-        };
+        let want =
+            match self.remaining {
+                Some(remaining) => (buf.len() as u64).min(remaining) as usize,
+                None => buf.len(),
+            };
+        if want == 0 && !buf.is_empty() {
+            return Ok(0);
+        }
+        let count =
+            match self.position {
+                Some(position) => unsafe {
+                    libc::pread(self.fd.as_raw_fd(),
+                                buf.as_mut_ptr() as *mut libc::c_void, want,
+                                position as libc::off_t)
+                },
+                None => unsafe {
+                    libc::read(self.fd.as_raw_fd(),
+                               buf.as_mut_ptr() as *mut libc::c_void, want)
+                },
+            };
         if count < 0 {
-            Err(Error::last_os_error())
-        } else {
-            Ok(count as usize)
+            return Err(Error::last_os_error());
+        }
+        self.consume_nontrivial(count as usize);
+        Ok(count as usize)
+    }
+}
+
+impl StreamHooks for StreamBody {
+    fn consume_nontrivial(&mut self, count: usize) {
+        if let Some(position) = &mut self.position {
+            *position += count as u64;
+        }
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= count as u64;
         }
     }
+
+    fn splice_source_nontrivial(&self) -> Option<SpliceSource> {
+        Some(SpliceSource {
+            fd: self.fd.clone(),
+            offset: self.position,
+            limit: self.remaining,
+        })
+    }
+
+    fn get_summary(&self) -> String {
+        let mut summary = format!("fd {}", self.fd.as_raw_fd());
+        if let Some(position) = self.position {
+            summary.push_str(&format!(", position {}", position));
+        }
+        if let Some(remaining) = self.remaining {
+            summary.push_str(&format!(", remaining {}", remaining));
+        }
+        summary
+    }
+
+    fn close_nontrivial(&mut self) {
+        self.registration = None;
+    }
+} // impl StreamHooks for StreamBody
+
+fn is_regular(fd: &Fd) -> Result<bool> {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(stat.st_mode & libc::S_IFMT == libc::S_IFREG)
+}
+
+fn block(fd: &Fd) -> Result<()> {
+    let status = unsafe {
+        libc::fcntl(fd.as_raw_fd(), libc::F_GETFL, 0)
+    };
+    if status < 0 {
+        return Err(Error::last_os_error());
+    }
+    let status = unsafe {
+        libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, status & !libc::O_NONBLOCK)
+    };
+    if status < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
 }
 
 impl Stream {
     pub fn new(disk: &Disk, fd: &Fd, sync: bool) -> Result<Stream> {
+        Self::make(disk, fd, sync, None, None)
+    }
+
+    // Regular files are read with pread(2) starting at offset; other
+    // files can only be read from where they are and must be given a
+    // zero offset. Opening does not block even if the file is a FIFO
+    // without a writer.
+    pub fn open(disk: &Disk, path: &Path, offset: u64, len: Option<u64>)
+                -> Result<Stream> {
+        let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| error::inval())?;
+        let raw_fd = unsafe {
+            libc::open(c_path.as_ptr(),
+                       libc::O_RDONLY | libc::O_NONBLOCK | libc::O_CLOEXEC)
+        };
+        if raw_fd < 0 {
+            let err = Error::last_os_error();
+            TRACE!(ATEN_FILESTREAM_OPEN_FAIL {
+                DISK: disk, PATH: path.to_string_lossy(),
+                ERR: r3::errsym(&err),
+            });
+            return Err(err);
+        }
+        let fd = Fd::new(raw_fd);
+        if is_regular(&fd)? {
+            block(&fd)?;
+            Self::make(disk, &fd, true, Some(offset), len)
+        } else if offset != 0 {
+            TRACE!(ATEN_FILESTREAM_OPEN_UNSEEKABLE {
+                DISK: disk, PATH: path.to_string_lossy(), OFFSET: offset,
+            });
+            Err(Error::from_raw_os_error(libc::ESPIPE))
+        } else {
+            Self::make(disk, &fd, false, None, len)
+        }
+    }
+
+    fn make(disk: &Disk,
+            fd: &Fd,
+            sync: bool,
+            position: Option<u64>,
+            remaining: Option<u64>) -> Result<Stream> {
         let uid = UID::new();
         let body = StreamBody {
             base: base::StreamBody::new(disk.downgrade(), uid),
             fd: fd.clone(),
             registration: None,
+            position: position,
+            remaining: remaining,
         };
         let stream = Stream(Link {
             uid: uid,
@@ -61,6 +201,13 @@ impl Stream {
                     stream.0.body.borrow_mut().registration =
                         Some(registration);
                 }
+                Err(err) if err.raw_os_error() == Some(libc::EPERM) => {
+                    // epoll does not support regular files, which are
+                    // always ready anyway.
+                    TRACE!(ATEN_FILESTREAM_CREATE_SYNC_FALLBACK {
+                        DISK: disk, STREAM: uid,
+                    });
+                }
                 Err(err) => {
                     TRACE!(ATEN_FILESTREAM_CREATE_FAIL {
                         DISK: disk, ERR: r3::errsym(&err)
@@ -69,10 +216,45 @@ impl Stream {
                 }
             }
         }
-        TRACE!(ATEN_FILESTREAM_CREATE { DISK: disk, STREAM: uid, SYNC: sync });
+        TRACE!(ATEN_FILESTREAM_CREATE {
+            DISK: disk, STREAM: uid, SYNC: sync,
+            POSITION: r3::option(&position),
+            REMAINING: r3::option(&remaining),
+        });
         Ok(stream)
     }
 
+    pub fn position(&self) -> Option<u64> {
+        self.0.body.borrow().position
+    }
+
+    pub fn advise(&self, advice: Advice) -> Result<()> {
+        let body = self.0.body.borrow();
+        let code = match advice {
+            Advice::Normal => libc::POSIX_FADV_NORMAL,
+            Advice::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+            Advice::Random => libc::POSIX_FADV_RANDOM,
+            Advice::NoReuse => libc::POSIX_FADV_NOREUSE,
+            Advice::WillNeed => libc::POSIX_FADV_WILLNEED,
+            Advice::DontNeed => libc::POSIX_FADV_DONTNEED,
+        };
+        let status = unsafe {
+            libc::posix_fadvise(body.fd.as_raw_fd(),
+                                body.position.unwrap_or(0) as libc::off_t,
+                                body.remaining.unwrap_or(0) as libc::off_t,
+                                code)
+        };
+        if status != 0 {
+            let err = Error::from_raw_os_error(status);
+            TRACE!(ATEN_FILESTREAM_ADVISE_FAIL {
+                STREAM: self, ADVICE: advice, ERR: r3::errsym(&err),
+            });
+            return Err(err);
+        }
+        TRACE!(ATEN_FILESTREAM_ADVISE { STREAM: self, ADVICE: advice });
+        Ok(())
+    }
+
     fn notify(&self) {
         self.0.body.borrow().base.invoke_callback();
     }
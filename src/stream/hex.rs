@@ -0,0 +1,240 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::io::{Error, Result};
+
+use crate::{Disk, Link, UID, Downgradable};
+use crate::error::{AtenError, Kind};
+use crate::stream::{ByteStream, BasicStream, StreamHooks, base};
+use r3::{TRACE, Traceable};
+
+DECLARE_STREAM!(
+    Encoder, WeakEncoder, EncoderBody,
+    ATEN_HEXENCODER_DROP,
+    ATEN_HEXENCODER_UPPED_MISS,
+    ATEN_HEXENCODER_REGISTER_CALLBACK,
+    ATEN_HEXENCODER_UNREGISTER_CALLBACK,
+    ATEN_HEXENCODER_READ_TRIVIAL,
+    ATEN_HEXENCODER_READ,
+    ATEN_HEXENCODER_READ_DUMP,
+    ATEN_HEXENCODER_READ_FAIL,
+    StreamHooks);
+
+DECLARE_STREAM!(
+    Decoder, WeakDecoder, DecoderBody,
+    ATEN_HEXDECODER_DROP,
+    ATEN_HEXDECODER_UPPED_MISS,
+    ATEN_HEXDECODER_REGISTER_CALLBACK,
+    ATEN_HEXDECODER_UNREGISTER_CALLBACK,
+    ATEN_HEXDECODER_READ_TRIVIAL,
+    ATEN_HEXDECODER_READ,
+    ATEN_HEXDECODER_READ_DUMP,
+    ATEN_HEXDECODER_READ_FAIL,
+    StreamHooks);
+
+const BUF_SIZE: usize = 2000;
+
+#[derive(Debug)]
+pub struct EncoderBody {
+    base: base::StreamBody,
+    wrappee: ByteStream,
+    digits: &'static [u8; 16],
+    input: [u8; BUF_SIZE],
+    low: usize,
+    high: usize,
+    pending: Option<u8>,
+}
+
+impl EncoderBody {
+    fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut wi = 0;
+        if let Some(digit) = self.pending.take() {
+            buf[wi] = digit;
+            wi += 1;
+        }
+        loop {
+            if wi == buf.len() {
+                return Ok(wi);
+            }
+            if self.low >= self.high {
+                if wi > 0 {
+                    return Ok(wi);
+                }
+                let count = self.wrappee.read(&mut self.input)?;
+                if count == 0 {
+                    return Ok(0);
+                }
+                self.low = 0;
+                self.high = count;
+            }
+            let byte = self.input[self.low];
+            self.low += 1;
+            buf[wi] = self.digits[(byte >> 4) as usize];
+            wi += 1;
+            let low_digit = self.digits[(byte & 0xf) as usize];
+            if wi == buf.len() {
+                self.pending = Some(low_digit);
+                return Ok(wi);
+            }
+            buf[wi] = low_digit;
+            wi += 1;
+        }
+    }
+}
+
+impl StreamHooks for EncoderBody {
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        vec![self.wrappee.clone()]
+    }
+} // impl StreamHooks for EncoderBody
+
+impl Encoder {
+    pub fn new(disk: &Disk, wrappee: ByteStream, uppercase: bool) -> Encoder {
+        let uid = UID::new();
+        TRACE!(ATEN_HEXENCODER_CREATE {
+            DISK: disk, STREAM: uid, WRAPPEE: wrappee, UPPERCASE: uppercase,
+        });
+        let body = Rc::new(RefCell::new(EncoderBody {
+            base: base::StreamBody::new(disk.downgrade(), uid),
+            wrappee: wrappee.clone(),
+            digits: if uppercase {
+                b"0123456789ABCDEF"
+            } else {
+                b"0123456789abcdef"
+            },
+            input: [0; BUF_SIZE],
+            low: 0,
+            high: 0,
+            pending: None,
+        }));
+        let stream = Encoder(Link {
+            uid: uid,
+            body: body.clone(),
+        });
+        stream.register_wrappee_callback(&wrappee);
+        stream
+    }
+} // impl Encoder
+
+#[derive(Debug)]
+enum State {
+    Decoding,
+    Exhausted,
+    Errored,
+}
+
+#[derive(Debug)]
+pub struct DecoderBody {
+    base: base::StreamBody,
+    wrappee: ByteStream,
+    state: State,
+    high_nibble: Option<u8>,
+    failure: Option<Error>,
+}
+
+fn nibble(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+impl DecoderBody {
+    fn malformed(&self, context: &str) -> Error {
+        AtenError::new(Kind::Protocol, context)
+            .at(self.base.get_uid()).into()
+    }
+
+    // Whitespace is skipped. The bytes decoded before an invalid digit
+    // are returned and the failure is left for the next read.
+    fn decode(&mut self, buf: &mut [u8], count: usize) -> usize {
+        let mut wi = 0;
+        for ri in 0..count {
+            if matches!(buf[ri], b' ' | b'\t' | b'\r' | b'\n') {
+                continue;
+            }
+            let value =
+                match nibble(buf[ri]) {
+                    Some(value) => value,
+                    None => {
+                        TRACE!(ATEN_HEXDECODER_MALFORMED { STREAM: self });
+                        self.failure = Some(self.malformed("invalid digit"));
+                        self.state = State::Errored;
+                        break;
+                    }
+                };
+            match self.high_nibble.take() {
+                Some(high) => {
+                    buf[wi] = high << 4 | value;
+                    wi += 1;
+                }
+                None => {
+                    self.high_nibble = Some(value);
+                }
+            }
+        }
+        wi
+    }
+
+    fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            match self.state {
+                State::Decoding => {}
+                State::Exhausted => {
+                    return Ok(0);
+                }
+                State::Errored => {
+                    return Err(self.failure.take().unwrap_or_else(
+                        || self.malformed("input was malformed")));
+                }
+            }
+            let count = self.wrappee.read(buf)?;
+            if count == 0 {
+                if self.high_nibble.is_some() {
+                    TRACE!(ATEN_HEXDECODER_TRUNCATED { STREAM: self });
+                    self.state = State::Errored;
+                    return Err(self.malformed("input ends in a byte"));
+                }
+                self.state = State::Exhausted;
+                return Ok(0);
+            }
+            let n = self.decode(buf, count);
+            if n > 0 {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+impl StreamHooks for DecoderBody {
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        vec![self.wrappee.clone()]
+    }
+
+    fn get_summary(&self) -> String {
+        format!("{:?}", self.state)
+    }
+} // impl StreamHooks for DecoderBody
+
+impl Decoder {
+    pub fn new(disk: &Disk, wrappee: ByteStream) -> Decoder {
+        let uid = UID::new();
+        TRACE!(ATEN_HEXDECODER_CREATE {
+            DISK: disk, STREAM: uid, WRAPPEE: wrappee,
+        });
+        let body = Rc::new(RefCell::new(DecoderBody {
+            base: base::StreamBody::new(disk.downgrade(), uid),
+            wrappee: wrappee.clone(),
+            state: State::Decoding,
+            high_nibble: None,
+            failure: None,
+        }));
+        let stream = Decoder(Link {
+            uid: uid,
+            body: body.clone(),
+        });
+        stream.register_wrappee_callback(&wrappee);
+        stream
+    }
+} // impl Decoder
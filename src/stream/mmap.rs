@@ -0,0 +1,207 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::io::{Error, Result};
+use std::os::unix::io::{AsRawFd, IntoRawFd};
+use std::path::Path;
+
+use crate::{Disk, Link, UID, Fd, Downgradable, error};
+use crate::buffer::Buffer;
+use crate::stream::{BasicStream, StreamHooks, base};
+use r3::{TRACE, Traceable};
+
+DECLARE_STREAM!(
+    Stream, WeakStream, StreamBody,
+    ATEN_MMAPSTREAM_DROP,
+    ATEN_MMAPSTREAM_UPPED_MISS,
+    ATEN_MMAPSTREAM_REGISTER_CALLBACK,
+    ATEN_MMAPSTREAM_UNREGISTER_CALLBACK,
+    ATEN_MMAPSTREAM_READ_TRIVIAL,
+    ATEN_MMAPSTREAM_READ,
+    ATEN_MMAPSTREAM_READ_DUMP,
+    ATEN_MMAPSTREAM_READ_FAIL,
+    StreamHooks);
+
+// A read-only shared mapping. Note that truncating the file underneath
+// the mapping causes SIGBUS on access.
+struct Mapping {
+    addr: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is never written to and is unmapped only when the last
+// reference goes away.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    fn new(fd: &Fd, offset: u64, len: usize) -> Result<Mapping> {
+        let addr = unsafe {
+            libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ,
+                       libc::MAP_SHARED, fd.as_raw_fd(),
+                       offset as libc::off_t)
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        Ok(Mapping {
+            addr: addr,
+            len: len,
+        })
+    }
+} // impl Mapping
+
+impl AsRef<[u8]> for Mapping {
+    fn as_ref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.addr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.addr, self.len) };
+    }
+}
+
+#[derive(Debug)]
+pub struct StreamBody {
+    base: base::StreamBody,
+    view: Buffer,
+}
+
+impl StreamBody {
+    fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let count = buf.len().min(self.view.len());
+        buf[..count].copy_from_slice(&self.view[..count]);
+        self.view.advance(count);
+        Ok(count)
+    }
+}
+
+impl StreamHooks for StreamBody {
+    fn peek_nontrivial(&mut self) -> Result<Option<Vec<Buffer>>> {
+        if self.view.is_empty() {
+            return Ok(Some(Vec::new()));
+        }
+        Ok(Some(vec![self.view.clone()]))
+    }
+
+    fn consume_nontrivial(&mut self, count: usize) {
+        self.view.advance(count);
+    }
+
+    fn get_summary(&self) -> String {
+        format!("remaining {}", self.view.len())
+    }
+
+    fn close_nontrivial(&mut self) {
+        self.view = Buffer::new();
+    }
+} // impl StreamHooks for StreamBody
+
+fn file_size(fd: &Fd) -> Result<u64> {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } < 0 {
+        return Err(Error::last_os_error());
+    }
+    if stat.st_mode & libc::S_IFMT != libc::S_IFREG {
+        return Err(error::inval());
+    }
+    Ok(stat.st_size as u64)
+}
+
+impl Stream {
+    // Map the part of a regular file starting at offset. The range is
+    // cut short at the end of the file.
+    pub fn new(disk: &Disk, fd: &Fd, offset: u64, len: Option<u64>)
+               -> Result<Stream> {
+        let uid = UID::new();
+        let view =
+            match Self::map(fd, offset, len) {
+                Ok(view) => view,
+                Err(err) => {
+                    TRACE!(ATEN_MMAPSTREAM_CREATE_FAIL {
+                        DISK: disk, FD: fd, OFFSET: offset,
+                        ERR: r3::errsym(&err),
+                    });
+                    return Err(err);
+                }
+            };
+        TRACE!(ATEN_MMAPSTREAM_CREATE {
+            DISK: disk, STREAM: uid, FD: fd, OFFSET: offset,
+            LEN: view.len(),
+        });
+        let body = StreamBody {
+            base: base::StreamBody::new(disk.downgrade(), uid),
+            view: view,
+        };
+        Ok(Stream(Link {
+            uid: uid,
+            body: Rc::new(RefCell::new(body)),
+        }))
+    }
+
+    pub fn open(disk: &Disk, path: &Path, offset: u64, len: Option<u64>)
+                -> Result<Stream> {
+        let file =
+            match std::fs::File::open(path) {
+                Ok(file) => file,
+                Err(err) => {
+                    TRACE!(ATEN_MMAPSTREAM_OPEN_FAIL {
+                        DISK: disk, PATH: path.to_string_lossy(),
+                        ERR: r3::errsym(&err),
+                    });
+                    return Err(err);
+                }
+            };
+        Self::new(disk, &Fd::new(file.into_raw_fd()), offset, len)
+    }
+
+    fn map(fd: &Fd, offset: u64, len: Option<u64>) -> Result<Buffer> {
+        let size = file_size(fd)?;
+        if offset > size {
+            return Err(error::inval());
+        }
+        let mut end = size;
+        if let Some(len) = len {
+            end = end.min(offset.saturating_add(len));
+        }
+        if end == offset {
+            return Ok(Buffer::new());
+        }
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        let page_offset = offset % page_size;
+        let mapping = Mapping::new(
+            fd, offset - page_offset, (end - offset + page_offset) as usize)?;
+        Ok(Buffer::from_owner(mapping).slice(page_offset as usize..))
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.0.body.borrow().view.len()
+    }
+
+    // Tell the kernel the rest of the mapping is going to be read
+    // sequentially.
+    pub fn advise_sequential(&self) -> Result<()> {
+        let body = self.0.body.borrow();
+        if body.view.is_empty() {
+            return Ok(());
+        }
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let addr = body.view.as_ptr() as usize;
+        let page_offset = addr % page_size;
+        let status = unsafe {
+            libc::madvise((addr - page_offset) as *mut libc::c_void,
+                          body.view.len() + page_offset,
+                          libc::MADV_SEQUENTIAL)
+        };
+        if status < 0 {
+            let err = Error::last_os_error();
+            TRACE!(ATEN_MMAPSTREAM_ADVISE_FAIL {
+                STREAM: self, ERR: r3::errsym(&err),
+            });
+            return Err(err);
+        }
+        TRACE!(ATEN_MMAPSTREAM_ADVISE { STREAM: self });
+        Ok(())
+    }
+} // impl Stream
@@ -1,10 +1,16 @@
 #![allow(dead_code)]
 
 use std::cell::RefCell;
-use std::io::{Result, Read};
+use std::collections::BTreeSet;
+use std::io::{Result, Read, IoSliceMut};
 use std::rc::Rc;
+use std::task::Poll;
 
-use crate::{Link, UID, Action, Downgradable, Upgradable, DECLARE_LINKS};
+use crate::{Link, UID, Action, Fd, Downgradable, Upgradable, DECLARE_LINKS};
+use crate::error;
+use crate::task::Trigger;
+use crate::buffer::Buffer;
+use crate::misc::linger;
 use r3::{TRACE, Traceable};
 
 DECLARE_LINKS!(ByteStream, WeakByteStream, dyn DebuggableByteStreamBody,
@@ -23,6 +29,22 @@ impl ByteStream {
         self.0.body.borrow_mut().read(buf)
     }
 
+    pub fn read_vectored(&self, bufs: &mut [IoSliceMut]) -> Result<usize> {
+        self.0.body.borrow_mut().read_vectored(bufs)
+    }
+
+    pub fn peek(&self) -> Result<Option<Vec<Buffer>>> {
+        self.0.body.borrow_mut().peek()
+    }
+
+    pub fn consume(&self, count: usize) {
+        self.0.body.borrow_mut().consume(count);
+    }
+
+    pub fn splice_source(&self) -> Option<SpliceSource> {
+        self.0.body.borrow().splice_source()
+    }
+
     pub fn register_callback(&self, callback: crate::Action) {
         self.0.body.borrow_mut().register_callback(callback);
     }
@@ -30,12 +52,201 @@ impl ByteStream {
     pub fn unregister_callback(&self) {
         self.0.body.borrow_mut().unregister_callback();
     }
+
+    // Wait until some data or the end of the stream is available. The
+    // stream's callback is taken over for the duration and left
+    // unregistered afterwards, even if the future is dropped midway.
+    pub async fn read_async(&self, buf: &mut [u8]) -> Result<usize> {
+        let trigger = Trigger::new();
+        self.register_callback(trigger.action());
+        let _registration = CallbackRegistration(self);
+        std::future::poll_fn(|context| {
+            match self.read(buf) {
+                Err(err) if error::is_again(&err) => {
+                    trigger.park(context);
+                    Poll::Pending
+                }
+                result => Poll::Ready(result),
+            }
+        }).await
+    }
+
+    pub fn kind(&self) -> &'static str {
+        self.0.body.borrow().kind()
+    }
+
+    pub fn wrappees(&self) -> Vec<ByteStream> {
+        self.0.body.borrow().wrappees()
+    }
+
+    pub fn summary(&self) -> String {
+        self.0.body.borrow().summary()
+    }
+
+    pub fn close(&self) {
+        self.0.body.borrow_mut().close();
+    }
+
+    // Render the stream and everything it reads from as an indented
+    // tree, one stream per line. Streams borrowed at the time (such as
+    // the one being read) are shown as busy.
+    pub fn describe(&self) -> String {
+        let mut output = String::new();
+        self.describe_node(0, &mut BTreeSet::new(), &mut output);
+        output
+    }
+
+    fn describe_node(&self, depth: usize, seen: &mut BTreeSet<UID>,
+                     output: &mut String) {
+        output.push_str(&"  ".repeat(depth));
+        output.push_str(&self.label());
+        if !seen.insert(self.0.uid) {
+            output.push_str(" (see above)\n");
+            return;
+        }
+        output.push('\n');
+        if let Some((_, _, wrappees)) = self.inspect() {
+            for wrappee in wrappees {
+                wrappee.describe_node(depth + 1, seen, output);
+            }
+        }
+    }
+
+    // Render the same as a Graphviz digraph with edges pointing from
+    // wrappers to wrappees.
+    pub fn describe_dot(&self) -> String {
+        let mut output = String::from("digraph {\n");
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![self.clone()];
+        while let Some(stream) = pending.pop() {
+            if !seen.insert(stream.0.uid) {
+                continue;
+            }
+            output.push_str(&format!(
+                "  \"{}\" [label={:?}];\n", stream.0.uid, stream.label()));
+            if let Some((_, _, wrappees)) = stream.inspect() {
+                for wrappee in wrappees {
+                    output.push_str(&format!(
+                        "  \"{}\" -> \"{}\";\n",
+                        stream.0.uid, wrappee.0.uid));
+                    pending.push(wrappee);
+                }
+            }
+        }
+        output.push_str("}\n");
+        output
+    }
+
+    fn inspect(&self) -> Option<(&'static str, String, Vec<ByteStream>)> {
+        let body = self.0.body.try_borrow().ok()?;
+        Some((body.kind(), body.summary(), body.wrappees()))
+    }
+
+    fn label(&self) -> String {
+        match self.inspect() {
+            Some((kind, summary, _)) if summary.is_empty() => {
+                format!("{} {}", kind, self.0.uid)
+            }
+            Some((kind, summary, _)) => {
+                format!("{} {} ({})", kind, self.0.uid, summary)
+            }
+            None => {
+                format!("{} (busy)", self.0.uid)
+            }
+        }
+    }
 } // impl ByteStream
 
+// Unregisters the callback of the stream when dropped.
+struct CallbackRegistration<'a>(&'a ByteStream);
+
+impl Drop for CallbackRegistration<'_> {
+    fn drop(&mut self) {
+        self.0.unregister_callback();
+    }
+} // impl Drop for CallbackRegistration
+
 pub trait ByteStreamBody {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
     fn register_callback(&mut self, callback: crate::Action);
     fn unregister_callback(&mut self);
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut]) -> Result<usize> {
+        let mut total = 0;
+        for buf in bufs.iter_mut() {
+            if buf.is_empty() {
+                continue;
+            }
+            match self.read(buf) {
+                Ok(count) => {
+                    total += count;
+                    if count < buf.len() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    if total == 0 {
+                        return Err(err);
+                    }
+                    break;
+                }
+            }
+        }
+        Ok(total)
+    }
+
+    // Lend the stream's pending data without copying it. Ok(None) means
+    // the stream does not lend and must be read; an empty vector means
+    // end of stream. The data stays pending until consumed.
+    fn peek(&mut self) -> Result<Option<Vec<Buffer>>> {
+        Ok(None)
+    }
+
+    fn consume(&mut self, count: usize) {
+        assert_eq!(count, 0);
+    }
+
+    // The file descriptor the stream reads from directly, if any, so
+    // that the data can be moved without a trip through user space.
+    // Whatever is moved must be reported with consume().
+    fn splice_source(&self) -> Option<SpliceSource> {
+        None
+    }
+
+    // For inspection: the type of the stream, the streams it reads from
+    // and a brief account of its state.
+    fn kind(&self) -> &'static str {
+        "stream"
+    }
+
+    fn wrappees(&self) -> Vec<ByteStream> {
+        Vec::new()
+    }
+
+    fn summary(&self) -> String {
+        String::new()
+    }
+
+    // Release the stream's resources and those of its wrappees. Reads
+    // fail with EBADF afterwards.
+    fn close(&mut self) {}
+}
+
+#[derive(Debug)]
+pub struct SpliceSource {
+    pub fd: Fd,
+    pub offset: Option<u64>,    // None: use and update the file offset
+    pub limit: Option<u64>,
+}
+
+// "aten::stream::queue::StreamBody" is known as "queue::Stream".
+#[doc(hidden)]
+pub fn kind_of(type_name: &'static str) -> &'static str {
+    let name = type_name.strip_suffix("Body").unwrap_or(type_name);
+    match name.rfind("::").and_then(|end| name[..end].rfind("::")) {
+        Some(start) => &name[start + 2..],
+        None => name,
+    }
 }
 
 pub trait DebuggableByteStreamBody: ByteStreamBody + std::fmt::Debug {}
@@ -44,6 +255,10 @@ impl Read for ByteStream {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         self.0.body.borrow_mut().read(buf)
     }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut]) -> Result<usize> {
+        self.0.body.borrow_mut().read_vectored(bufs)
+    }
 }
 
 DECLARE_LINKS!(ByteStreamPair, WeakByteStreamPair,
@@ -66,11 +281,33 @@ impl ByteStreamPair {
     pub fn set_egress(&self, egress: ByteStream) {
         self.0.body.borrow_mut().set_egress(egress);
     }
+
+    pub fn poll_egress(&self) -> linger::State {
+        self.0.body.borrow_mut().poll_egress()
+    }
+
+    pub fn register_egress_callback(&self, callback: Action) {
+        self.0.body.borrow_mut().register_egress_callback(callback);
+    }
+
+    pub fn unregister_egress_callback(&self) {
+        self.0.body.borrow_mut().unregister_egress_callback();
+    }
 } // impl ByteStreamPair
 
 pub trait ByteStreamPairBody {
     fn get_ingress(&self) -> Option<ByteStream>;
     fn set_egress(&mut self, egress: ByteStream);
+
+    // A pair that does not keep track of its egress never reports it
+    // done and has nobody to notify.
+    fn poll_egress(&mut self) -> linger::State {
+        linger::State::Busy
+    }
+
+    fn register_egress_callback(&mut self, _callback: Action) {}
+
+    fn unregister_egress_callback(&mut self) {}
 }
 
 pub trait DebuggableByteStreamPairBody: ByteStreamPairBody + std::fmt::Debug {}
@@ -87,17 +324,19 @@ macro_rules! DECLARE_STREAM {
      $ATEN_STREAM_READ_TRIVIAL:ident,
      $ATEN_STREAM_READ:ident,
      $ATEN_STREAM_READ_DUMP:ident,
-     $ATEN_STREAM_READ_FAIL:ident) => {
-        DECLARE_STREAM_DROP!($StreamBody,
-                             $ATEN_STREAM_DROP);
-        DECLARE_STREAM_NO_DROP!($Stream, $WeakStream, $StreamBody,
-                                $ATEN_STREAM_UPPED_MISS,
-                                $ATEN_STREAM_REGISTER_CALLBACK,
-                                $ATEN_STREAM_UNREGISTER_CALLBACK,
-                                $ATEN_STREAM_READ_TRIVIAL,
-                                $ATEN_STREAM_READ,
-                                $ATEN_STREAM_READ_DUMP,
-                                $ATEN_STREAM_READ_FAIL);
+     $ATEN_STREAM_READ_FAIL:ident
+     $(, $StreamHooks:ident)?) => {
+        $crate::DECLARE_STREAM_DROP!($StreamBody,
+                                     $ATEN_STREAM_DROP);
+        $crate::DECLARE_STREAM_NO_DROP!($Stream, $WeakStream, $StreamBody,
+                                        $ATEN_STREAM_UPPED_MISS,
+                                        $ATEN_STREAM_REGISTER_CALLBACK,
+                                        $ATEN_STREAM_UNREGISTER_CALLBACK,
+                                        $ATEN_STREAM_READ_TRIVIAL,
+                                        $ATEN_STREAM_READ,
+                                        $ATEN_STREAM_READ_DUMP,
+                                        $ATEN_STREAM_READ_FAIL
+                                        $(, $StreamHooks)?);
     }
 }
 
@@ -113,6 +352,8 @@ macro_rules! DECLARE_STREAM_DROP {
     }
 }
 
+// A stream body that implements StreamHooks itself says so with a
+// trailing StreamHooks argument; the others get the defaults.
 #[macro_export]
 macro_rules! DECLARE_STREAM_NO_DROP {
     ($Stream:ident,
@@ -125,7 +366,30 @@ macro_rules! DECLARE_STREAM_NO_DROP {
      $ATEN_STREAM_READ:ident,
      $ATEN_STREAM_READ_DUMP:ident,
      $ATEN_STREAM_READ_FAIL:ident) => {
-        $crate::DECLARE_LINKS!($Stream, WeakStream, StreamBody,
+        $crate::DECLARE_STREAM_NO_DROP!($Stream, $WeakStream, $StreamBody,
+                                        $ATEN_STREAM_UPPED_MISS,
+                                        $ATEN_STREAM_REGISTER_CALLBACK,
+                                        $ATEN_STREAM_UNREGISTER_CALLBACK,
+                                        $ATEN_STREAM_READ_TRIVIAL,
+                                        $ATEN_STREAM_READ,
+                                        $ATEN_STREAM_READ_DUMP,
+                                        $ATEN_STREAM_READ_FAIL,
+                                        StreamHooks);
+
+        impl $crate::stream::StreamHooks for $StreamBody {}
+    };
+    ($Stream:ident,
+     $WeakStream:ident,
+     $StreamBody:ident,
+     $ATEN_STREAM_UPPED_MISS:ident,
+     $ATEN_STREAM_REGISTER_CALLBACK:ident,
+     $ATEN_STREAM_UNREGISTER_CALLBACK:ident,
+     $ATEN_STREAM_READ_TRIVIAL:ident,
+     $ATEN_STREAM_READ:ident,
+     $ATEN_STREAM_READ_DUMP:ident,
+     $ATEN_STREAM_READ_FAIL:ident,
+     StreamHooks) => {
+        $crate::DECLARE_LINKS!($Stream, $WeakStream, $StreamBody,
                                $ATEN_STREAM_UPPED_MISS, STREAMD);
 
         impl $crate::stream::ByteStreamBody for $StreamBody {
@@ -142,6 +406,13 @@ macro_rules! DECLARE_STREAM_NO_DROP {
             }
 
             fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+                if self.base.is_closed() {
+                    let err = $crate::error::badf();
+                    TRACE!($ATEN_STREAM_READ_FAIL {
+                        STREAMD: self, WANT: buf.len(), ERR: r3::errsym(&err)
+                    });
+                    return Err(err);
+                }
                 if let Ok(_) = self.base.read(buf) {
                     TRACE!($ATEN_STREAM_READ_TRIVIAL {
                         STREAMD: self, WANT: buf.len()
@@ -166,6 +437,51 @@ macro_rules! DECLARE_STREAM_NO_DROP {
                     }
                 }
             }
+
+            fn peek(&mut self)
+                    -> Result<Option<Vec<$crate::buffer::Buffer>>> {
+                if self.base.is_closed() {
+                    return Err($crate::error::badf());
+                }
+                $crate::stream::StreamHooks::peek_nontrivial(self)
+            }
+
+            fn consume(&mut self, count: usize) {
+                $crate::stream::StreamHooks::consume_nontrivial(
+                    self, count);
+            }
+
+            fn splice_source(&self)
+                             -> Option<$crate::stream::SpliceSource> {
+                if self.base.is_closed() {
+                    return None;
+                }
+                $crate::stream::StreamHooks::splice_source_nontrivial(
+                    self)
+            }
+
+            fn kind(&self) -> &'static str {
+                $crate::stream::kind_of(std::any::type_name::<Self>())
+            }
+
+            fn wrappees(&self) -> Vec<$crate::stream::ByteStream> {
+                $crate::stream::StreamHooks::get_wrappees(self)
+            }
+
+            fn summary(&self) -> String {
+                $crate::stream::StreamHooks::get_summary(self)
+            }
+
+            fn close(&mut self) {
+                if self.base.is_closed() {
+                    return;
+                }
+                self.base.close();
+                for wrappee in self.wrappees() {
+                    wrappee.close();
+                }
+                $crate::stream::StreamHooks::close_nontrivial(self);
+            }
         }
 
         impl std::fmt::Display for $StreamBody {
@@ -192,13 +508,45 @@ macro_rules! DECLARE_STREAM_NO_DROP {
             for $Stream {
                 fn get_link(&self) -> &$crate::Link<$StreamBody> { &self.0 }
             }
-    }
+    };
 }
 
 pub trait BasicStreamBody {
     fn get_base(&self) -> &base::StreamBody;
 }
 
+// Optional parts of a stream body. The defaults suit a leaf stream that
+// neither lends its data nor holds anything beyond its base.
+pub trait StreamHooks {
+    // The lending counterparts of read_nontrivial() for streams that
+    // hold their data in buffers or read directly from a file
+    // descriptor.
+    fn peek_nontrivial(&mut self) -> Result<Option<Vec<Buffer>>> {
+        Ok(None)
+    }
+
+    fn consume_nontrivial(&mut self, count: usize) {
+        assert_eq!(count, 0);
+    }
+
+    fn splice_source_nontrivial(&self) -> Option<SpliceSource> {
+        None
+    }
+
+    // For inspection: the streams read from and a brief account of the
+    // state.
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        Vec::new()
+    }
+
+    fn get_summary(&self) -> String {
+        String::new()
+    }
+
+    // Release whatever the stream holds apart from its wrappees.
+    fn close_nontrivial(&mut self) {}
+}
+
 pub trait BasicStream<W, B>: Downgradable<W> + Sized where
     W: Upgradable<Self> + 'static,
     B: BasicStreamBody + DebuggableByteStreamBody + 'static,
@@ -218,6 +566,14 @@ pub trait BasicStream<W, B>: Downgradable<W> + Sized where
         self.get_link().body.borrow_mut().read(buf)
     }
 
+    fn peek(&self) -> Result<Option<Vec<Buffer>>> {
+        self.get_link().body.borrow_mut().peek()
+    }
+
+    fn consume(&self, count: usize) {
+        self.get_link().body.borrow_mut().consume(count);
+    }
+
     fn register_callback(&self, callback: Action) {
         self.get_link().body.borrow_mut().register_callback(callback);
     }
@@ -226,6 +582,14 @@ pub trait BasicStream<W, B>: Downgradable<W> + Sized where
         self.get_link().body.borrow_mut().unregister_callback();
     }
 
+    fn close(&self) {
+        self.get_link().body.borrow_mut().close();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.get_link().body.borrow().get_base().is_closed()
+    }
+
     fn register_wrappee_callback(&self, wrappee: &ByteStream) {
         let weak_stream = self.downgrade();
         let uid = self.get_link().uid;
@@ -244,17 +608,26 @@ pub trait BasicStream<W, B>: Downgradable<W> + Sized where
 
 pub mod avid;
 pub mod base;
+pub mod base64;
 pub mod blob;
+pub mod chaos;
+pub mod chunked;
+pub mod deflate;
 pub mod dry;
 pub mod empty;
 pub mod farewell;
 pub mod file;
+pub mod hex;
+pub mod mmap;
 pub mod naivedecoder;
 pub mod naiveencoder;
 pub mod nice;
 pub mod pacer;
 pub mod queue;
+pub mod recorder;
+pub mod replay;
 pub mod reservoir;
 pub mod sub;
 pub mod switch;
+pub mod timeout;
 pub mod zero;
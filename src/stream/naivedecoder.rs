@@ -1,9 +1,10 @@
 use std::rc::Rc;
 use std::cell::RefCell;
-use std::io::Result;
+use std::io::{Error, Result};
 
 use crate::{Disk, Link, UID, Downgradable, Upgradable, error};
-use crate::stream::{ByteStream, ByteStreamBody, BasicStream};
+use crate::error::{AtenError, Kind};
+use crate::stream::{ByteStream, ByteStreamBody, BasicStream, StreamHooks};
 use crate::stream::{base, queue, blob};
 use r3::{TRACE, Traceable};
 
@@ -16,7 +17,8 @@ DECLARE_STREAM!(
     ATEN_NAIVEDECODER_READ_TRIVIAL,
     ATEN_NAIVEDECODER_READ,
     ATEN_NAIVEDECODER_READ_DUMP,
-    ATEN_NAIVEDECODER_READ_FAIL);
+    ATEN_NAIVEDECODER_READ_FAIL,
+    StreamHooks);
 
 #[derive(Debug)]
 enum State {
@@ -52,14 +54,14 @@ impl StreamBody {
             }
             if buf[ri] == self.terminator {
                 ri += 1;
-                if ri == buf.len() {
+                if ri == count {
                     self.state = State::Terminated(self.wrappee.clone());
                     return Ok(wi);
                 }
                 if let Some(disk) = self.base.get_weak_disk().upgrade() {
                     let q = queue::Stream::new(&disk, None);
                     q.enqueue(
-                        blob::Stream::new(&disk, buf[ri..].to_vec())
+                        blob::Stream::new(&disk, buf[ri..count].to_vec())
                             .as_bytestream());
                     q.enqueue(self.wrappee.clone());
                     q.terminate();
@@ -83,13 +85,18 @@ impl StreamBody {
         }
     }
 
+    fn unterminated(&self) -> Error {
+        AtenError::new(Kind::Protocol, "input ends before terminator")
+            .at(self.base.get_uid()).into()
+    }
+
     fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
         match self.state {
             State::Reading | State::Escaped => {
                 match self.wrappee.read(buf) {
                     Ok(0) => {
                         self.state = State::Errored;
-                        Err(error::proto())
+                        Err(self.unterminated())
                     }
                     Ok(count) => {
                         self.decode(buf, count)
@@ -103,12 +110,29 @@ impl StreamBody {
                 Ok(0)
             }
             State::Errored => {
-                Err(error::proto())
+                Err(self.unterminated())
             }
         }
     }
 }
 
+impl StreamHooks for StreamBody {
+    // Once terminated, the wrappee belongs to the remainder.
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        match self.state {
+            State::Terminated(_) => Vec::new(),
+            _ => vec![self.wrappee.clone()],
+        }
+    }
+
+    fn get_summary(&self) -> String {
+        match &self.state {
+            State::Terminated(_) => "Terminated".to_string(),
+            state => format!("{:?}", state),
+        }
+    }
+} // impl StreamHooks for StreamBody
+
 impl Stream {
     pub fn new(disk: &Disk,
                wrappee: ByteStream,
@@ -3,7 +3,7 @@ use std::cell::RefCell;
 use std::io::Result;
 
 use crate::{Disk, Link, UID, Downgradable};
-use crate::stream::{ByteStream, BasicStream, base};
+use crate::stream::{ByteStream, BasicStream, StreamHooks, base};
 use r3::{TRACE, Traceable};
 
 DECLARE_STREAM!(
@@ -15,7 +15,8 @@ DECLARE_STREAM!(
     ATEN_NAIVEENCODER_READ_TRIVIAL,
     ATEN_NAIVEENCODER_READ,
     ATEN_NAIVEENCODER_READ_DUMP,
-    ATEN_NAIVEENCODER_READ_FAIL);
+    ATEN_NAIVEENCODER_READ_FAIL,
+    StreamHooks);
 
 #[derive(Debug)]
 enum State {
@@ -109,6 +110,16 @@ impl StreamBody {
     }
 }
 
+impl StreamHooks for StreamBody {
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        vec![self.wrappee.clone()]
+    }
+
+    fn get_summary(&self) -> String {
+        format!("{:?}", self.state)
+    }
+} // impl StreamHooks for StreamBody
+
 impl Stream {
     pub fn new(disk: &Disk,
                wrappee: ByteStream,
@@ -3,7 +3,7 @@ use std::cell::RefCell;
 use std::io::Result;
 
 use crate::{Disk, Link, UID, Downgradable, error};
-use crate::stream::{ByteStream, BasicStream, base};
+use crate::stream::{ByteStream, BasicStream, StreamHooks, base};
 use r3::{TRACE, Traceable};
 
 DECLARE_STREAM!(
@@ -15,7 +15,8 @@ DECLARE_STREAM!(
     ATEN_NICESTREAM_READ_TRIVIAL,
     ATEN_NICESTREAM_READ,
     ATEN_NICESTREAM_READ_DUMP,
-    ATEN_NICESTREAM_READ_FAIL);
+    ATEN_NICESTREAM_READ_FAIL,
+    StreamHooks);
 
 #[derive(Debug)]
 pub struct StreamBody {
@@ -46,6 +47,16 @@ impl StreamBody {
     }
 }
 
+impl StreamHooks for StreamBody {
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        vec![self.wrappee.clone()]
+    }
+
+    fn get_summary(&self) -> String {
+        format!("burst {}/{}", self.cursor, self.max_burst)
+    }
+} // impl StreamHooks for StreamBody
+
 impl Stream {
     pub fn new(disk: &Disk, wrappee: ByteStream, max_burst: usize) -> Stream {
         let uid = UID::new();
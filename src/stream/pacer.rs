@@ -4,19 +4,20 @@ use std::io::Result;
 use std::time::{Instant, Duration};
 
 use crate::{Disk, Link, Action, UID, Timer, Downgradable, Upgradable, error};
-use crate::stream::{ByteStream, BasicStream, base};
+use crate::stream::{ByteStream, BasicStream, StreamHooks, base};
+use crate::misc::RateLimiter;
 use r3::{TRACE, Traceable};
 
-DECLARE_STREAM!(
+DECLARE_STREAM_NO_DROP!(
     Stream, WeakStream, StreamBody,
-    ATEN_PACERSTREAM_DROP,
     ATEN_PACERSTREAM_UPPED_MISS,
     ATEN_PACERSTREAM_REGISTER_CALLBACK,
     ATEN_PACERSTREAM_UNREGISTER_CALLBACK,
     ATEN_PACERSTREAM_READ_TRIVIAL,
     ATEN_PACERSTREAM_READ,
     ATEN_PACERSTREAM_READ_DUMP,
-    ATEN_PACERSTREAM_READ_FAIL);
+    ATEN_PACERSTREAM_READ_FAIL,
+    StreamHooks);
 
 #[derive(Debug)]
 pub struct StreamBody {
@@ -29,11 +30,15 @@ pub struct StreamBody {
     max_burst: f64,
     prev_time: Instant,
     retry_timer: Option<Timer>,
+    limiter: Option<RateLimiter>,
     weak_self: Weak<RefCell<Self>>,
 }
 
 impl StreamBody {
     fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if let Some(limiter) = self.limiter.clone() {
+            return self.read_limited(&limiter, buf);
+        }
         let disk =
             match self.base.get_weak_disk().upgrade() {
                 Some(disk) => disk,
@@ -76,12 +81,66 @@ impl StreamBody {
         }
     }
 
+    // The shared limiter takes the place of the stream's own bucket.
+    fn read_limited(&mut self, limiter: &RateLimiter, buf: &mut [u8])
+                    -> Result<usize> {
+        let uid = self.base.get_uid();
+        let weak_self = self.weak_self.clone();
+        let retry = Action::new(move || {
+            if let Some(body) = weak_self.upgrade() {
+                body.borrow().retry();
+            }
+        });
+        let available = limiter.available(uid, self.min_burst, &retry);
+        if available < 1.0 {
+            TRACE!(ATEN_PACERSTREAM_READ_WAIT {
+                STREAM: self, LIMITER: limiter,
+            });
+            return Err(error::again());
+        }
+        let count = buf.len().min(available.min(self.max_burst) as usize);
+        let result = self.wrappee.read(&mut buf[..count]);
+        limiter.consume(uid, *result.as_ref().unwrap_or(&0));
+        result
+    }
+
     fn retry(&self) {
         TRACE!(ATEN_PACERSTREAM_RETRY { STREAM: self });
         self.base.invoke_callback();
     }
 }
 
+impl StreamHooks for StreamBody {
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        vec![self.wrappee.clone()]
+    }
+
+    fn get_summary(&self) -> String {
+        match &self.limiter {
+            Some(limiter) => format!("limiter {}", limiter),
+            None => format!("quota {:.1}, rate {}", self.quota, self.byterate),
+        }
+    }
+
+    fn close_nontrivial(&mut self) {
+        if let Some(timer) = self.retry_timer.take() {
+            timer.cancel();
+        }
+        if let Some(limiter) = self.limiter.take() {
+            limiter.forget(self.base.get_uid());
+        }
+    }
+} // impl StreamHooks for StreamBody
+
+impl Drop for StreamBody {
+    fn drop(&mut self) {
+        TRACE!(ATEN_PACERSTREAM_DROP { STREAM: self });
+        if let Some(limiter) = &self.limiter {
+            limiter.forget(self.base.get_uid());
+        }
+    }
+} // impl Drop for StreamBody
+
 impl Stream {
     pub fn new(disk: &Disk,
                wrappee: ByteStream,
@@ -107,6 +166,7 @@ impl Stream {
                 max_burst: max_burst as f64,
                 prev_time: disk.now(),
                 retry_timer: None,
+                limiter: None,
                 weak_self: weak_self.clone(),
             }));
         let stream = Stream(Link {
@@ -116,4 +176,21 @@ impl Stream {
         stream.register_wrappee_callback(&wrappee);
         Ok(stream)
     }
+
+    // Pace the stream with a limiter shared with other streams. The
+    // stream waits for at least min_burst bytes of the limiter's quota
+    // and draws at most max_burst bytes at a time.
+    pub fn with_limiter(disk: &Disk,
+                        wrappee: ByteStream,
+                        limiter: &RateLimiter,
+                        min_burst: usize,
+                        max_burst: usize) -> Result<Stream> {
+        let stream =
+            Self::new(disk, wrappee, limiter.rate(), min_burst, max_burst)?;
+        TRACE!(ATEN_PACERSTREAM_SET_LIMITER {
+            STREAM: stream, LIMITER: limiter,
+        });
+        stream.0.body.borrow_mut().limiter = Some(limiter.clone());
+        Ok(stream)
+    }
 } // impl Stream
@@ -1,10 +1,11 @@
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 use std::cell::RefCell;
 use std::collections::LinkedList;
 use std::io::{Result, Error, Write};
 
-use crate::{Disk, Link, UID, Downgradable, Upgradable, error};
-use crate::stream::{ByteStream, BasicStream, base, blob};
+use crate::{Disk, Link, UID, Action, Downgradable, Upgradable, error};
+use crate::buffer::Buffer;
+use crate::stream::{ByteStream, BasicStream, StreamHooks, base, blob};
 use r3::{TRACE, Traceable};
 
 DECLARE_STREAM!(
@@ -16,16 +17,19 @@ DECLARE_STREAM!(
     ATEN_QUEUESTREAM_READ_TRIVIAL,
     ATEN_QUEUESTREAM_READ,
     ATEN_QUEUESTREAM_READ_DUMP,
-    ATEN_QUEUESTREAM_READ_FAIL);
+    ATEN_QUEUESTREAM_READ_FAIL,
+    StreamHooks);
 
 pub struct StreamBody {
     base: base::StreamBody,
     queue: LinkedList<ByteStream>,
     terminated: bool,
     supplier: Option<Rc<RefCell<dyn Supplier>>>,
+    supply_requested: bool,
     exhausted: bool,
     pending_error: Option<Error>,
     notification_expected: bool,
+    weak_self: Weak<RefCell<StreamBody>>,
 }
 
 impl StreamBody {
@@ -34,10 +38,15 @@ impl StreamBody {
             return Err(err);
         }
         let mut cursor = 0;
-        while let Some(head) = self.queue.front_mut() {
-            if cursor >= buf.len() {
-                break;
-            }
+        while cursor < buf.len() {
+            let head =
+                match self.queue.front_mut() {
+                    Some(head) => head,
+                    None => {
+                        self.request_supply();
+                        break;
+                    }
+                };
             match head.read(&mut buf[cursor..]) {
                 Err(err) => {
                     if cursor == 0 {
@@ -68,8 +77,117 @@ impl StreamBody {
             Err(error::again())
         }
     }
+
+    // The supplier may well enqueue() to or terminate() this very queue,
+    // so it is consulted from the main loop rather than under the borrow.
+    fn request_supply(&mut self) {
+        if self.terminated || self.supplier.is_none() || self.supply_requested
+        {
+            return;
+        }
+        self.supply_requested = true;
+        self.base.get_weak_disk().upped(|disk| {
+            let weak_self = self.weak_self.clone();
+            disk.execute(Action::new(move || {
+                if let Some(body) = weak_self.upgrade() {
+                    supply(&body);
+                }
+            }));
+        });
+    }
+
+    fn wake_action(&self) -> Action {
+        let weak_self = self.weak_self.clone();
+        Action::new(move || {
+            if let Some(body) = weak_self.upgrade() {
+                let mut body = body.borrow_mut();
+                TRACE!(ATEN_QUEUESTREAM_SUPPLY_WAKE { STREAM: &*body });
+                body.supply_requested = false;
+                body.base.invoke_callback();
+            }
+        })
+    }
+
+    fn register_wrappee_callback(&self, wrappee: &ByteStream) {
+        let weak_self = self.weak_self.clone();
+        let uid = self.base.get_uid();
+        wrappee.register_callback(Action::new(move || {
+            match weak_self.upgrade() {
+                Some(body) => {
+                    body.borrow().base.invoke_callback();
+                }
+                None => {
+                    TRACE!(ATEN_STREAM_WRAPPEE_UPPED_MISS { STREAM: uid });
+                }
+            }
+        }));
+    }
 }
 
+impl StreamHooks for StreamBody {
+    fn peek_nontrivial(&mut self) -> Result<Option<Vec<Buffer>>> {
+        if let Some(err) = self.pending_error.take() {
+            return Err(err);
+        }
+        loop {
+            let result =
+                match self.queue.front() {
+                    Some(head) => head.peek(),
+                    None => {
+                        self.request_supply();
+                        if self.terminated {
+                            self.exhausted = true;
+                            return Ok(Some(Vec::new()));
+                        }
+                        return Err(error::again());
+                    }
+                };
+            match result {
+                Ok(Some(buffers)) if buffers.is_empty() => {
+                    self.queue.pop_front();
+                }
+                Err(err) => {
+                    if error::is_again(&err) {
+                        self.notification_expected = true;
+                    }
+                    return Err(err);
+                }
+                result => {
+                    return result;
+                }
+            }
+        }
+    }
+
+    fn consume_nontrivial(&mut self, count: usize) {
+        match self.queue.front() {
+            Some(head) => {
+                head.consume(count);
+            }
+            None => {
+                assert_eq!(count, 0);
+            }
+        }
+    }
+
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        self.queue.iter().cloned().collect()
+    }
+
+    fn get_summary(&self) -> String {
+        let mut summary = format!("length {}", self.queue.len());
+        if self.terminated {
+            summary.push_str(", terminated");
+        }
+        summary
+    }
+
+    fn close_nontrivial(&mut self) {
+        self.queue.clear();
+        self.supplier = None;
+    }
+} // impl StreamHooks for StreamBody
+
 impl std::fmt::Debug for StreamBody {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("queue::Stream")
@@ -84,22 +202,73 @@ impl std::fmt::Debug for StreamBody {
     }
 } // impl std::fmt::Debug for StreamBody
 
-pub trait Supplier {}
+fn supply(body: &Rc<RefCell<StreamBody>>) {
+    let (supplier, wake) =
+        match &body.borrow().supplier {
+            Some(supplier) => (supplier.clone(), body.borrow().wake_action()),
+            None => { return; }
+        };
+    let supply = supplier.borrow_mut().supply(&wake);
+    let mut body = body.borrow_mut();
+    match supply {
+        Supply::Stream(wrappee) => {
+            TRACE!(ATEN_QUEUESTREAM_SUPPLY {
+                STREAM: &*body, WRAPPEE: wrappee
+            });
+            body.supply_requested = false;
+            body.register_wrappee_callback(&wrappee);
+            body.queue.push_back(wrappee);
+            body.base.invoke_callback();
+        }
+        Supply::Pending => {
+            TRACE!(ATEN_QUEUESTREAM_SUPPLY_PENDING { STREAM: &*body });
+            // The supplier may have fed the queue directly.
+            if !body.queue.is_empty() || body.terminated {
+                body.supply_requested = false;
+                body.base.invoke_callback();
+            }
+        }
+        Supply::Done => {
+            TRACE!(ATEN_QUEUESTREAM_SUPPLY_DONE { STREAM: &*body });
+            body.supply_requested = false;
+            body.terminated = true;
+            body.supplier = None;
+            body.base.invoke_callback();
+        }
+    }
+}
+
+pub enum Supply {
+    Stream(ByteStream),
+    // Nothing to offer yet; the supplier performs the wake action once it
+    // has.
+    Pending,
+    // No more streams will follow.
+    Done,
+}
+
+// Called when the queue runs dry.
+pub trait Supplier {
+    fn supply(&mut self, wake: &Action) -> Supply;
+}
 
 impl Stream {
     pub fn new(disk: &Disk, supplier: Option<Rc<RefCell<dyn Supplier>>>)
                -> Stream {
         let uid = UID::new();
         TRACE!(ATEN_QUEUESTREAM_CREATE { DISK: disk, STREAM: uid });
-        let body = Rc::new(RefCell::new(StreamBody {
-            base: base::StreamBody::new(disk.downgrade(), uid),
-            queue: LinkedList::new(),
-            supplier: supplier,
-            terminated: false,
-            exhausted: false,
-            pending_error: None,
-            notification_expected: false,
-        }));
+        let body = Rc::new_cyclic(
+            |weak_self| RefCell::new(StreamBody {
+                base: base::StreamBody::new(disk.downgrade(), uid),
+                queue: LinkedList::new(),
+                supplier: supplier,
+                supply_requested: false,
+                terminated: false,
+                exhausted: false,
+                pending_error: None,
+                notification_expected: false,
+                weak_self: weak_self.clone(),
+            }));
         Stream(Link {
             uid: uid,
             body: body.clone(),
@@ -113,6 +282,20 @@ impl Stream {
         self.register_wrappee_callback(&wrappee);
     }
 
+    pub fn enqueue_buffer(&self, buffer: Buffer) -> Result<()> {
+        let weak_disk = self.0.body.borrow().base.get_weak_disk().clone();
+        match weak_disk.upgrade() {
+            Some(disk) => {
+                self.enqueue(
+                    blob::Stream::from_buffer(&disk, buffer).as_bytestream());
+                Ok(())
+            }
+            None => {
+                Err(error::badf())
+            }
+        }
+    }
+
     pub fn push(&self, wrappee: ByteStream) {
         assert!(!self.0.body.borrow().exhausted);
         TRACE!(ATEN_QUEUESTREAM_PUSH { STREAM: self, WRAPPEE: wrappee });
@@ -130,18 +313,8 @@ impl Stream {
 
 impl Write for Stream {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        let weak_disk = self.0.body.borrow().base.get_weak_disk().clone();
-        match weak_disk.upgrade() {
-            Some(disk) => {
-                let count = buf.len();
-                self.enqueue(
-                    blob::Stream::new(&disk, buf.to_vec()).as_bytestream());
-                Ok(count)
-            }
-            None => {
-                Err(error::badf())
-            }
-        }
+        self.enqueue_buffer(Buffer::from(buf.to_vec()))?;
+        Ok(buf.len())
     }
 
     fn flush(&mut self) -> Result<()> {
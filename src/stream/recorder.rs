@@ -0,0 +1,177 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use crate::{Disk, Link, UID, Downgradable, Upgradable, error};
+use crate::stream::{ByteStream, BasicStream, StreamHooks, base};
+use r3::{TRACE, Traceable};
+
+DECLARE_STREAM!(
+    Stream, WeakStream, StreamBody,
+    ATEN_RECORDERSTREAM_DROP,
+    ATEN_RECORDERSTREAM_UPPED_MISS,
+    ATEN_RECORDERSTREAM_REGISTER_CALLBACK,
+    ATEN_RECORDERSTREAM_UNREGISTER_CALLBACK,
+    ATEN_RECORDERSTREAM_READ_TRIVIAL,
+    ATEN_RECORDERSTREAM_READ,
+    ATEN_RECORDERSTREAM_READ_DUMP,
+    ATEN_RECORDERSTREAM_READ_FAIL,
+    StreamHooks);
+
+// A recording starts with MAGIC and is followed by one record per read
+// of the wrappee:
+//
+//     delay tag [length data | errno]
+//
+// The delay is in microseconds since the previous read (or since the
+// creation of the recorder). Integers are LEB128 varints.
+pub(crate) const MAGIC: &[u8] = b"ATENREC\x01";
+pub(crate) const TAG_DATA: u8 = 0;
+pub(crate) const TAG_ERROR: u8 = 1;
+pub(crate) const TAG_EOF: u8 = 2;
+
+pub(crate) fn put_varint(record: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        record.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    record.push(value as u8);
+}
+
+#[derive(Debug)]
+pub struct StreamBody {
+    base: base::StreamBody,
+    wrappee: ByteStream,
+    file: Option<BufWriter<File>>,
+    prev_time: Instant,
+}
+
+impl StreamBody {
+    fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let result = self.wrappee.read(buf);
+        self.record(&result, buf);
+        result
+    }
+
+    // A failure to record is traced and ends the recording but does
+    // not affect the stream.
+    fn record(&mut self, result: &Result<usize>, buf: &[u8]) {
+        let file =
+            match &mut self.file {
+                Some(file) => file,
+                None => { return; }
+            };
+        let now =
+            match self.base.get_weak_disk().upgrade() {
+                Some(disk) => disk.now(),
+                None => Instant::now(),
+            };
+        let delay = now.saturating_duration_since(self.prev_time);
+        self.prev_time = now;
+        let mut record = Vec::new();
+        put_varint(&mut record, delay.as_micros() as u64);
+        match result {
+            Ok(0) => {
+                record.push(TAG_EOF);
+            }
+            Ok(count) => {
+                record.push(TAG_DATA);
+                put_varint(&mut record, *count as u64);
+                record.extend_from_slice(&buf[..*count]);
+            }
+            Err(err) => {
+                record.push(TAG_ERROR);
+                let errno = error::errno(err).unwrap_or(libc::EIO);
+                put_varint(&mut record, errno as u64);
+            }
+        }
+        if let Err(err) = file.write_all(&record) {
+            TRACE!(ATEN_RECORDERSTREAM_RECORD_FAIL {
+                STREAM: self, ERR: r3::errsym(&err),
+            });
+            self.file = None;
+            return;
+        }
+        if let Ok(0) = result {
+            self.finish();
+        }
+    }
+
+    // The recording is buffered; whatever is pending is written out
+    // when it ends.
+    fn finish(&mut self) {
+        if let Some(mut file) = self.file.take() {
+            if let Err(err) = file.flush() {
+                TRACE!(ATEN_RECORDERSTREAM_FLUSH_FAIL {
+                    STREAM: self, ERR: r3::errsym(&err),
+                });
+            }
+        }
+    }
+} // impl StreamBody
+
+impl StreamHooks for StreamBody {
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        vec![self.wrappee.clone()]
+    }
+
+    fn get_summary(&self) -> String {
+        if self.file.is_some() {
+            "recording".to_string()
+        } else {
+            "stopped".to_string()
+        }
+    }
+
+    fn close_nontrivial(&mut self) {
+        self.finish();
+    }
+} // impl StreamHooks for StreamBody
+
+impl Stream {
+    pub fn new(disk: &Disk, wrappee: ByteStream, path: &Path)
+               -> Result<Stream> {
+        let uid = UID::new();
+        let mut file =
+            match File::create(path) {
+                Ok(file) => file,
+                Err(err) => {
+                    TRACE!(ATEN_RECORDERSTREAM_CREATE_FAIL {
+                        DISK: disk, PATH: path.to_string_lossy(),
+                        ERR: r3::errsym(&err),
+                    });
+                    return Err(err);
+                }
+            };
+        file.write_all(MAGIC)?;
+        TRACE!(ATEN_RECORDERSTREAM_CREATE {
+            DISK: disk, STREAM: uid, WRAPPEE: wrappee,
+            PATH: path.to_string_lossy(),
+        });
+        let body = Rc::new(RefCell::new(StreamBody {
+            base: base::StreamBody::new(disk.downgrade(), uid),
+            wrappee: wrappee.clone(),
+            file: Some(BufWriter::new(file)),
+            prev_time: disk.now(),
+        }));
+        let stream = Stream(Link {
+            uid: uid,
+            body: body.clone(),
+        });
+        stream.register_wrappee_callback(&wrappee);
+        Ok(stream)
+    }
+
+    // Stop recording; the stream keeps passing the wrappee through.
+    pub fn stop(&self) {
+        TRACE!(ATEN_RECORDERSTREAM_STOP { STREAM: self });
+        self.0.body.borrow_mut().finish();
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.0.body.borrow().file.is_some()
+    }
+} // impl Stream
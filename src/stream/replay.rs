@@ -0,0 +1,291 @@
+use std::rc::{Rc, Weak};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{Error, Result};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::{Disk, Link, Action, UID, Timer, Downgradable, Upgradable, error};
+use crate::error::{AtenError, Kind};
+use crate::buffer::Buffer;
+use crate::stream::{BasicStream, StreamHooks, base};
+use crate::stream::recorder::{MAGIC, TAG_DATA, TAG_ERROR, TAG_EOF};
+use r3::{TRACE, Traceable};
+
+DECLARE_STREAM_NO_DROP!(
+    Stream, WeakStream, StreamBody,
+    ATEN_REPLAYSTREAM_UPPED_MISS,
+    ATEN_REPLAYSTREAM_REGISTER_CALLBACK,
+    ATEN_REPLAYSTREAM_UNREGISTER_CALLBACK,
+    ATEN_REPLAYSTREAM_READ_TRIVIAL,
+    ATEN_REPLAYSTREAM_READ,
+    ATEN_REPLAYSTREAM_READ_DUMP,
+    ATEN_REPLAYSTREAM_READ_FAIL,
+    StreamHooks);
+
+// In real time, a record is not delivered before its recorded delay has
+// passed. In virtual time, records are delivered without waiting, and
+// elapsed() tells the recorded time instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    Real,
+    Virtual,
+}
+
+#[derive(Debug)]
+enum Outcome {
+    Data(Buffer),
+    Error(i32),
+    Eof,
+}
+
+#[derive(Debug)]
+struct Record {
+    delay: Duration,
+    outcome: Outcome,
+}
+
+fn corrupt(context: &str) -> Error {
+    AtenError::new(Kind::Protocol, context).into()
+}
+
+fn get_varint(data: &[u8], cursor: &mut usize) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*cursor)
+            .ok_or_else(|| corrupt("recording ends in a number"))?;
+        *cursor += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(corrupt("number too long"))
+}
+
+fn parse(data: &[u8]) -> Result<VecDeque<Record>> {
+    if !data.starts_with(MAGIC) {
+        return Err(corrupt("not a recording"));
+    }
+    let mut records = VecDeque::new();
+    let mut cursor = MAGIC.len();
+    while cursor < data.len() {
+        let delay = Duration::from_micros(get_varint(data, &mut cursor)?);
+        let tag = *data.get(cursor)
+            .ok_or_else(|| corrupt("recording ends before a tag"))?;
+        cursor += 1;
+        let outcome =
+            match tag {
+                TAG_DATA => {
+                    let length = get_varint(data, &mut cursor)? as usize;
+                    let end = cursor.checked_add(length)
+                        .filter(|end| *end <= data.len())
+                        .ok_or_else(|| corrupt("recording ends in data"))?;
+                    let chunk = data[cursor..end].to_vec();
+                    cursor = end;
+                    Outcome::Data(Buffer::from(chunk))
+                }
+                TAG_ERROR => {
+                    Outcome::Error(get_varint(data, &mut cursor)? as i32)
+                }
+                TAG_EOF => {
+                    Outcome::Eof
+                }
+                _ => {
+                    return Err(corrupt("unknown tag"));
+                }
+            };
+        records.push_back(Record {
+            delay: delay,
+            outcome: outcome,
+        });
+    }
+    Ok(records)
+}
+
+#[derive(Debug)]
+pub struct StreamBody {
+    base: base::StreamBody,
+    timing: Timing,
+    records: VecDeque<Record>,
+    partial: Option<Buffer>,
+    exhausted: bool,
+    clock: Instant,
+    elapsed: Duration,
+    wake_timer: Option<Timer>,
+    weak_self: Weak<RefCell<Self>>,
+}
+
+impl StreamBody {
+    fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if let Some(chunk) = self.partial.take() {
+            return Ok(self.deliver(buf, chunk));
+        }
+        if self.exhausted {
+            return Ok(0);
+        }
+        // A recording that ends without EOF stalls, like the original.
+        let delay =
+            match self.records.front() {
+                Some(record) => record.delay,
+                None => { return Err(error::again()); }
+            };
+        if self.timing == Timing::Real {
+            let due = self.clock + delay;
+            if self.wake_timer.is_some() {
+                return Err(error::again());
+            }
+            if let Some(disk) = self.base.get_weak_disk().upgrade() {
+                if disk.now() < due {
+                    self.wake_at(&disk, due);
+                    return Err(error::again());
+                }
+            }
+            self.clock = due;
+        }
+        self.elapsed += delay;
+        match self.records.pop_front().unwrap().outcome {
+            Outcome::Data(chunk) => {
+                Ok(self.deliver(buf, chunk))
+            }
+            Outcome::Error(errno) => {
+                if errno == libc::EAGAIN {
+                    self.wake_next();
+                }
+                Err(Error::from_raw_os_error(errno))
+            }
+            Outcome::Eof => {
+                self.exhausted = true;
+                Ok(0)
+            }
+        }
+    }
+
+    // A recorded chunk is never merged with the next one, but it is
+    // split if the buffer is too small.
+    fn deliver(&mut self, buf: &mut [u8], mut chunk: Buffer) -> usize {
+        let count = chunk.len().min(buf.len());
+        buf[..count].copy_from_slice(&chunk[..count]);
+        chunk.advance(count);
+        if !chunk.is_empty() {
+            self.partial = Some(chunk);
+        }
+        count
+    }
+
+    // After a recorded EAGAIN, the callback is invoked when the next
+    // record is due.
+    fn wake_next(&mut self) {
+        let disk =
+            match self.base.get_weak_disk().upgrade() {
+                Some(disk) => disk,
+                None => { return; }
+            };
+        match (self.timing, self.records.front()) {
+            (Timing::Real, Some(record)) => {
+                let due = self.clock + record.delay;
+                self.wake_at(&disk, due);
+            }
+            (Timing::Virtual, Some(_)) => {
+                self.base.invoke_callback();
+            }
+            (_, None) => {}
+        }
+    }
+
+    fn wake_at(&mut self, disk: &Disk, due: Instant) {
+        let delay = due.saturating_duration_since(disk.now());
+        TRACE!(ATEN_REPLAYSTREAM_WAIT {
+            STREAM: self, DELAY: delay.as_secs_f64(),
+        });
+        let weak_self = self.weak_self.clone();
+        self.wake_timer = Some(disk.schedule(
+            due,
+            Action::new(move || {
+                if let Some(body) = weak_self.upgrade() {
+                    let mut body = body.borrow_mut();
+                    body.wake_timer = None;
+                    body.base.invoke_callback();
+                }
+            })));
+    }
+} // impl StreamBody
+
+impl StreamHooks for StreamBody {
+    fn get_summary(&self) -> String {
+        let mut summary = format!("records {}", self.records.len());
+        if self.exhausted {
+            summary.push_str(", exhausted");
+        }
+        summary
+    }
+
+    fn close_nontrivial(&mut self) {
+        if let Some(timer) = self.wake_timer.take() {
+            timer.cancel();
+        }
+        self.records.clear();
+        self.partial = None;
+    }
+} // impl StreamHooks for StreamBody
+
+impl Drop for StreamBody {
+    fn drop(&mut self) {
+        TRACE!(ATEN_REPLAYSTREAM_DROP { STREAM: self });
+        if let Some(timer) = self.wake_timer.take() {
+            timer.cancel();
+        }
+    }
+} // impl Drop for StreamBody
+
+impl Stream {
+    // The recording is loaded and validated in full; a malformed one is
+    // rejected with EPROTO.
+    pub fn new(disk: &Disk, recording: &[u8], timing: Timing)
+               -> Result<Stream> {
+        let records =
+            match parse(recording) {
+                Ok(records) => records,
+                Err(err) => {
+                    TRACE!(ATEN_REPLAYSTREAM_CREATE_FAIL {
+                        DISK: disk, ERR: r3::errsym(&err),
+                    });
+                    return Err(err);
+                }
+            };
+        let uid = UID::new();
+        TRACE!(ATEN_REPLAYSTREAM_CREATE {
+            DISK: disk, STREAM: uid, RECORDS: records.len(),
+            TIMING: format!("{:?}", timing),
+        });
+        let body = Rc::new_cyclic(
+            |weak_self| RefCell::new(StreamBody {
+                base: base::StreamBody::new(disk.downgrade(), uid),
+                timing: timing,
+                records: records,
+                partial: None,
+                exhausted: false,
+                clock: disk.now(),
+                elapsed: Duration::ZERO,
+                wake_timer: None,
+                weak_self: weak_self.clone(),
+            }));
+        Ok(Stream(Link {
+            uid: uid,
+            body: body,
+        }))
+    }
+
+    pub fn open(disk: &Disk, path: &Path, timing: Timing) -> Result<Stream> {
+        Self::new(disk, &std::fs::read(path)?, timing)
+    }
+
+    // The recorded time of the records delivered so far.
+    pub fn elapsed(&self) -> Duration {
+        self.0.body.borrow().elapsed
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.0.body.borrow().records.len()
+    }
+} // impl Stream
@@ -1,9 +1,14 @@
 use std::rc::Rc;
 use std::cell::RefCell;
-use std::io::Result;
+use std::io::{Result, Error};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
 
-use crate::{Disk, Link, UID, Downgradable, Upgradable, error};
-use crate::stream::{ByteStream, BasicStream, base, queue, blob};
+use crate::{Disk, Link, UID, Fd, Downgradable, Upgradable, error};
+use crate::buffer::Buffer;
+use crate::stream::{ByteStream, BasicStream, StreamHooks};
+use crate::stream::{base, queue, blob, file};
 use r3::{TRACE, Traceable};
 
 DECLARE_STREAM!(
@@ -15,7 +20,81 @@ DECLARE_STREAM!(
     ATEN_RESERVOIR_READ_TRIVIAL,
     ATEN_RESERVOIR_READ,
     ATEN_RESERVOIR_READ_DUMP,
-    ATEN_RESERVOIR_READ_FAIL);
+    ATEN_RESERVOIR_READ_FAIL,
+    StreamHooks);
+
+#[derive(Debug)]
+struct Spill {
+    directory: PathBuf,
+    memory_limit: usize,
+    file: Option<Fd>,
+    size: usize,
+}
+
+impl Spill {
+    fn create_file(&mut self) -> Result<Fd> {
+        let path =
+            std::ffi::CString::new(self.directory.as_os_str().as_bytes())
+            .map_err(|_| error::inval())?;
+        let fd = unsafe {
+            libc::open(path.as_ptr(),
+                       libc::O_TMPFILE | libc::O_RDWR | libc::O_CLOEXEC,
+                       0o600)
+        };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(Fd::new(fd))
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let fd =
+            match &self.file {
+                Some(fd) => fd.clone(),
+                None => {
+                    let fd = self.create_file()?;
+                    self.file = Some(fd.clone());
+                    fd
+                }
+            };
+        let mut cursor = 0;
+        while cursor < data.len() {
+            let slice = &data[cursor..];
+            let count = unsafe {
+                libc::write(fd.as_raw_fd(),
+                            slice.as_ptr() as *const libc::c_void,
+                            slice.len())
+            };
+            if count < 0 {
+                let err = Error::last_os_error();
+                if err.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+                continue;
+            }
+            cursor += count as usize;
+        }
+        self.size += data.len();
+        Ok(())
+    }
+
+    fn reopen(&self) -> Result<Option<Fd>> {
+        let fd =
+            match &self.file {
+                Some(fd) => fd,
+                None => { return Ok(None); }
+            };
+        let path = std::ffi::CString::new(
+            format!("/proc/self/fd/{}", fd.as_raw_fd())).unwrap();
+        let fd = unsafe {
+            libc::open(path.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC)
+        };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(Some(Fd::new(fd)))
+    }
+} // impl Spill
 
 #[derive(Debug)]
 pub struct StreamBody {
@@ -24,15 +103,56 @@ pub struct StreamBody {
     capacity: usize,
     amount: usize,
     eof_reached: bool,
-    storage: queue::Stream,
+    chunks: Vec<Buffer>,
+    spill: Option<Spill>,
+    storage: Option<ByteStream>,
 }
 
 impl StreamBody {
-    fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
-        if self.eof_reached {
-            return self.storage.read(buf);
+    fn store(&mut self, data: &[u8]) -> Result<()> {
+        if let Some(spill) = &mut self.spill {
+            let memory = self.amount - spill.size;
+            if spill.file.is_some() ||
+                memory + data.len() > spill.memory_limit {
+                if spill.file.is_none() {
+                    TRACE!(ATEN_RESERVOIR_SPILL {
+                        STREAM: self.base, MEMORY: memory,
+                    });
+                }
+                return spill.write(data);
+            }
+        }
+        self.chunks.push(Buffer::from(data.to_vec()));
+        Ok(())
+    }
+
+    fn replay(&self) -> Result<ByteStream> {
+        let disk =
+            match self.base.get_weak_disk().upgrade() {
+                Some(disk) => disk,
+                None => { return Err(error::badf()); }
+            };
+        let replay = queue::Stream::new(&disk, None);
+        for chunk in &self.chunks {
+            replay.enqueue(
+                blob::Stream::from_buffer(&disk, chunk.clone())
+                    .as_bytestream());
         }
+        if let Some(spill) = &self.spill {
+            if let Some(fd) = spill.reopen()? {
+                replay.enqueue(
+                    file::Stream::new(&disk, &fd, true)?.as_bytestream());
+            }
+        }
+        replay.terminate();
+        Ok(replay.as_bytestream())
+    }
+
+    fn fill(&mut self) -> Result<ByteStream> {
         loop {
+            if let Some(storage) = &self.storage {
+                return Ok(storage.clone());
+            }
             if self.amount > self.capacity {
                 TRACE!(ATEN_RESERVOIR_OVERFLOW { STREAM: self });
                 return Err(error::nospc());
@@ -41,19 +161,18 @@ impl StreamBody {
             match self.wrappee.read(&mut chunk) {
                 Ok(0) => {
                     TRACE!(ATEN_RESERVOIR_FILLED { STREAM: self });
-                    self.storage.terminate();
+                    let storage = self.replay()?;
                     self.eof_reached = true;
-                    return self.storage.read(buf);
+                    self.storage = Some(storage);
                 }
                 Ok(n) => {
-                    self.amount += n;
-                    if let Some(disk) = self.base.get_weak_disk().upgrade() {
-                        self.storage.enqueue(
-                            blob::Stream::new(&disk, chunk[..n].to_vec())
-                                .as_bytestream());
-                    } else {
-                        return Err(error::badf());
+                    if let Err(err) = self.store(&chunk[..n]) {
+                        TRACE!(ATEN_RESERVOIR_SPILL_FAIL {
+                            STREAM: self, ERR: r3::errsym(&err)
+                        });
+                        return Err(err);
                     }
+                    self.amount += n;
                 }
                 Err(err) => {
                     return Err(err);
@@ -61,21 +180,93 @@ impl StreamBody {
             }
         }
     }
+
+    fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.fill()?.read(buf)
+    }
 }
 
+impl StreamHooks for StreamBody {
+    fn peek_nontrivial(&mut self) -> Result<Option<Vec<Buffer>>> {
+        self.fill()?.peek()
+    }
+
+    fn consume_nontrivial(&mut self, count: usize) {
+        match &self.storage {
+            Some(storage) => {
+                storage.consume(count);
+            }
+            None => {
+                assert_eq!(count, 0);
+            }
+        }
+    }
+
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        vec![self.wrappee.clone()]
+    }
+
+    fn get_summary(&self) -> String {
+        let spilled = self.spill.as_ref().map_or(0, |spill| spill.size);
+        let mut summary =
+            format!("amount {}/{}, spilled {}",
+                    self.amount, self.capacity, spilled);
+        if self.eof_reached {
+            summary.push_str(", filled");
+        }
+        summary
+    }
+
+    fn close_nontrivial(&mut self) {
+        self.chunks.clear();
+        self.spill = None;
+        self.storage = None;
+    }
+} // impl StreamHooks for StreamBody
+
 impl Stream {
     pub fn new(disk: &Disk, wrappee: ByteStream, capacity: usize) -> Stream {
         let uid = UID::new();
         TRACE!(ATEN_RESERVOIR_CREATE {
             DISK: disk, STREAM: uid, WRAPPEE: wrappee, CAPACITY: capacity,
         });
+        Self::make(disk, uid, wrappee, capacity, None)
+    }
+
+    pub fn new_spilling(disk: &Disk,
+                        wrappee: ByteStream,
+                        memory_limit: usize,
+                        capacity: usize,
+                        directory: &Path) -> Stream {
+        let uid = UID::new();
+        TRACE!(ATEN_RESERVOIR_CREATE_SPILLING {
+            DISK: disk, STREAM: uid, WRAPPEE: wrappee,
+            MEMORY_LIMIT: memory_limit, CAPACITY: capacity,
+            DIRECTORY: directory.to_string_lossy(),
+        });
+        let spill = Spill {
+            directory: directory.to_path_buf(),
+            memory_limit: memory_limit,
+            file: None,
+            size: 0,
+        };
+        Self::make(disk, uid, wrappee, capacity, Some(spill))
+    }
+
+    fn make(disk: &Disk,
+            uid: UID,
+            wrappee: ByteStream,
+            capacity: usize,
+            spill: Option<Spill>) -> Stream {
         let body = Rc::new(RefCell::new(StreamBody {
             base: base::StreamBody::new(disk.downgrade(), uid),
             wrappee: wrappee.clone(),
             capacity: capacity,
             amount: 0,
             eof_reached: false,
-            storage: queue::Stream::new(&disk, None),
+            chunks: Vec::new(),
+            spill: spill,
+            storage: None,
         }));
         let stream = Stream(Link {
             uid: uid,
@@ -88,4 +279,16 @@ impl Stream {
     pub fn amount(&self) -> usize {
         self.0.body.borrow().amount
     }
+
+    pub fn spilled(&self) -> usize {
+        self.0.body.borrow().spill.as_ref().map_or(0, |spill| spill.size)
+    }
+
+    pub fn replay(&self) -> Result<ByteStream> {
+        let body = self.0.body.borrow();
+        if !body.eof_reached {
+            return Err(error::again());
+        }
+        body.replay()
+    }
 } // impl Stream
@@ -3,7 +3,7 @@ use std::cell::RefCell;
 use std::io::Result;
 
 use crate::{Disk, Link, UID, Downgradable};
-use crate::stream::{ByteStream, BasicStream, base};
+use crate::stream::{ByteStream, BasicStream, StreamHooks, base};
 use r3::{TRACE, Traceable};
 
 DECLARE_STREAM!(
@@ -15,7 +15,8 @@ DECLARE_STREAM!(
     ATEN_SUBSTREAM_READ_TRIVIAL,
     ATEN_SUBSTREAM_READ,
     ATEN_SUBSTREAM_READ_DUMP,
-    ATEN_SUBSTREAM_READ_FAIL);
+    ATEN_SUBSTREAM_READ_FAIL,
+    StreamHooks);
 
 #[derive(Debug)]
 pub struct StreamBody {
@@ -65,6 +66,27 @@ impl StreamBody {
     }
 }
 
+impl StreamHooks for StreamBody {
+    // At the end, the wrappee belongs to the remainder.
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        match self.end {
+            Some(end) if self.cursor >= end => Vec::new(),
+            _ => vec![self.wrappee.clone()],
+        }
+    }
+
+    fn get_summary(&self) -> String {
+        match self.end {
+            Some(end) => {
+                format!("cursor {} in {}..{}", self.cursor, self.begin, end)
+            }
+            None => {
+                format!("cursor {} in {}..", self.cursor, self.begin)
+            }
+        }
+    }
+} // impl StreamHooks for StreamBody
+
 impl Stream {
     pub fn new(disk: &Disk,
                wrappee: ByteStream,
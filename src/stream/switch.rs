@@ -3,7 +3,7 @@ use std::cell::RefCell;
 use std::io::Result;
 
 use crate::{Disk, Link, UID, Downgradable};
-use crate::stream::{ByteStream, BasicStream, base};
+use crate::stream::{ByteStream, BasicStream, StreamHooks, base};
 use r3::{TRACE, Traceable};
 
 DECLARE_STREAM!(
@@ -15,7 +15,8 @@ DECLARE_STREAM!(
     ATEN_SWITCHSTREAM_READ_TRIVIAL,
     ATEN_SWITCHSTREAM_READ,
     ATEN_SWITCHSTREAM_READ_DUMP,
-    ATEN_SWITCHSTREAM_READ_FAIL);
+    ATEN_SWITCHSTREAM_READ_FAIL,
+    StreamHooks);
 
 #[derive(Debug)]
 pub struct StreamBody {
@@ -29,6 +30,12 @@ impl StreamBody {
     }
 }
 
+impl StreamHooks for StreamBody {
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        vec![self.wrappee.clone()]
+    }
+} // impl StreamHooks for StreamBody
+
 impl Stream {
     pub fn new(disk: &Disk, wrappee: ByteStream) -> Stream {
         let uid = UID::new();
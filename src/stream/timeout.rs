@@ -0,0 +1,173 @@
+use std::rc::{Rc, Weak};
+use std::cell::RefCell;
+use std::io::Result;
+use std::time::{Instant, Duration};
+
+use crate::{Disk, Link, Action, UID, Timer, Downgradable, Upgradable, error};
+use crate::stream::{ByteStream, BasicStream, StreamHooks, base};
+use r3::{TRACE, Traceable};
+
+DECLARE_STREAM_NO_DROP!(
+    Stream, WeakStream, StreamBody,
+    ATEN_TIMEOUTSTREAM_UPPED_MISS,
+    ATEN_TIMEOUTSTREAM_REGISTER_CALLBACK,
+    ATEN_TIMEOUTSTREAM_UNREGISTER_CALLBACK,
+    ATEN_TIMEOUTSTREAM_READ_TRIVIAL,
+    ATEN_TIMEOUTSTREAM_READ,
+    ATEN_TIMEOUTSTREAM_READ_DUMP,
+    ATEN_TIMEOUTSTREAM_READ_FAIL,
+    StreamHooks);
+
+#[derive(Debug)]
+enum State {
+    Active,
+    TimedOut,
+    Done,
+}
+
+#[derive(Debug)]
+pub struct StreamBody {
+    base: base::StreamBody,
+    wrappee: ByteStream,
+    max_idle: Option<Duration>,
+    idle_timer: Option<Timer>,
+    deadline_timer: Option<Timer>,
+    state: State,
+    weak_self: Weak<RefCell<Self>>,
+}
+
+impl StreamBody {
+    fn read_nontrivial(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self.state {
+            State::Active => {}
+            State::TimedOut => {
+                return Err(error::timedout());
+            }
+            State::Done => {
+                return self.wrappee.read(buf);
+            }
+        }
+        match self.wrappee.read(buf) {
+            Ok(0) => {
+                self.finish();
+                Ok(0)
+            }
+            Ok(count) => {
+                self.arm_idle_timer();
+                Ok(count)
+            }
+            Err(err) => {
+                if !error::is_again(&err) {
+                    self.finish();
+                }
+                Err(err)
+            }
+        }
+    }
+
+    fn arm_idle_timer(&mut self) {
+        if let Some(timer) = self.idle_timer.take() {
+            timer.cancel();
+        }
+        if let Some(max_idle) = self.max_idle {
+            if let Some(disk) = self.base.get_weak_disk().upgrade() {
+                let weak_self = self.weak_self.clone();
+                self.idle_timer = Some(disk.schedule(
+                    disk.now() + max_idle,
+                    Action::new(move || {
+                        if let Some(body) = weak_self.upgrade() {
+                            body.borrow_mut().expire();
+                        }
+                    })));
+            }
+        }
+    }
+
+    fn cancel_timers(&mut self) {
+        if let Some(timer) = self.idle_timer.take() {
+            timer.cancel();
+        }
+        if let Some(timer) = self.deadline_timer.take() {
+            timer.cancel();
+        }
+    }
+
+    fn finish(&mut self) {
+        self.cancel_timers();
+        self.state = State::Done;
+    }
+
+    fn expire(&mut self) {
+        if !matches!(self.state, State::Active) {
+            TRACE!(ATEN_TIMEOUTSTREAM_EXPIRE_SPURIOUS { STREAM: self });
+            return;
+        }
+        TRACE!(ATEN_TIMEOUTSTREAM_EXPIRE { STREAM: self });
+        self.cancel_timers();
+        self.state = State::TimedOut;
+        self.base.invoke_callback();
+    }
+} // impl StreamBody
+
+impl StreamHooks for StreamBody {
+    fn get_wrappees(&self) -> Vec<ByteStream> {
+        vec![self.wrappee.clone()]
+    }
+
+    fn get_summary(&self) -> String {
+        format!("{:?}", self.state)
+    }
+
+    fn close_nontrivial(&mut self) {
+        self.cancel_timers();
+    }
+} // impl StreamHooks for StreamBody
+
+impl Drop for StreamBody {
+    fn drop(&mut self) {
+        TRACE!(ATEN_TIMEOUTSTREAM_DROP { STREAM: self });
+        self.cancel_timers();
+    }
+} // impl Drop for StreamBody
+
+impl Stream {
+    pub fn new(disk: &Disk,
+               wrappee: ByteStream,
+               max_idle: Option<Duration>,
+               deadline: Option<Instant>) -> Stream {
+        let uid = UID::new();
+        TRACE!(ATEN_TIMEOUTSTREAM_CREATE {
+            DISK: disk, STREAM: uid, WRAPPEE: wrappee,
+            MAX_IDLE: r3::option(&max_idle.map(|d| d.as_secs_f64())),
+            DEADLINE: r3::option(&deadline.map(
+                |t| t.saturating_duration_since(disk.now()).as_secs_f64())),
+        });
+        let body = Rc::new_cyclic(
+            |weak_self| RefCell::new(StreamBody {
+                base: base::StreamBody::new(disk.downgrade(), uid),
+                wrappee: wrappee.clone(),
+                max_idle: max_idle,
+                idle_timer: None,
+                deadline_timer: None,
+                state: State::Active,
+                weak_self: weak_self.clone(),
+            }));
+        if let Some(deadline) = deadline {
+            let weak_self = Rc::downgrade(&body);
+            body.borrow_mut().deadline_timer = Some(disk.schedule(
+                deadline,
+                Action::new(move || {
+                    if let Some(body) = weak_self.upgrade() {
+                        body.borrow_mut().expire();
+                    }
+                })));
+        }
+        body.borrow_mut().arm_idle_timer();
+        let stream = Stream(Link {
+            uid: uid,
+            body: body,
+        });
+        stream.register_wrappee_callback(&wrappee);
+        stream
+    }
+} // impl Stream
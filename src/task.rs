@@ -0,0 +1,371 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::io::{Error, Result};
+use std::os::unix::io::AsRawFd;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::ThreadId;
+use std::time::Instant;
+
+use crate::{Disk, WeakDisk, Action, UID, Timer, Registration, Fd};
+use crate::{Downgradable, Upgradable};
+use r3::{TRACE, Traceable};
+
+type TaskId = u64;
+
+static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(1);
+
+// Wakes coming from other threads are queued here and signaled through
+// a pipe registered with the disk.
+#[derive(Debug)]
+struct Remote {
+    queue: Mutex<Vec<TaskId>>,
+    write_fd: Fd,
+}
+
+#[derive(Debug)]
+struct WakeHandle {
+    thread: ThreadId,
+    task_id: TaskId,
+    remote: Arc<Remote>,
+}
+
+impl Wake for WakeHandle {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if std::thread::current().id() == self.thread {
+            schedule(self.task_id);
+            return;
+        }
+        self.remote.queue.lock().unwrap().push(self.task_id);
+        let dummy_byte = &0u8 as *const _ as *const libc::c_void;
+        unsafe {
+            libc::write(self.remote.write_fd.as_raw_fd(), dummy_byte, 1)
+        };
+    }
+} // impl Wake for WakeHandle
+
+struct Task {
+    weak_disk: WeakDisk,
+    future: Option<Pin<Box<dyn Future<Output = ()>>>>,
+    waker: Waker,
+    scheduled: bool,
+}
+
+struct Executor {
+    remote: Arc<Remote>,
+    registration: Registration,
+    task_count: usize,
+}
+
+thread_local! {
+    static TASKS: RefCell<HashMap<TaskId, Task>> =
+        RefCell::new(HashMap::new());
+    static EXECUTORS: RefCell<BTreeMap<UID, Executor>> =
+        const { RefCell::new(BTreeMap::new()) };
+}
+
+fn drain_remote(remote: &Remote, read_fd: &Fd) {
+    let mut buffer = [0u8; 64];
+    while unsafe {
+        libc::read(read_fd.as_raw_fd(),
+                   buffer.as_mut_ptr() as *mut libc::c_void, buffer.len())
+    } > 0 {}
+    let task_ids = std::mem::take(&mut *remote.queue.lock().unwrap());
+    for task_id in task_ids {
+        schedule(task_id);
+    }
+}
+
+fn executor_remote(disk: &Disk) -> Result<Arc<Remote>> {
+    let uid = disk.0.uid;
+    if let Some(remote) = EXECUTORS.with(|executors| {
+        executors.borrow_mut().get_mut(&uid).map(|executor| {
+            executor.task_count += 1;
+            executor.remote.clone()
+        })
+    }) {
+        return Ok(remote);
+    }
+    let mut pipe_fds = [0 as libc::c_int; 2];
+    let status = unsafe {
+        libc::pipe2(pipe_fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK)
+    };
+    if status < 0 {
+        return Err(Error::last_os_error());
+    }
+    let read_fd = Fd::new(pipe_fds[0]);
+    let remote = Arc::new(Remote {
+        queue: Mutex::new(Vec::new()),
+        write_fd: Fd::new(pipe_fds[1]),
+    });
+    let weak_remote = Arc::downgrade(&remote);
+    let drain_fd = read_fd.clone();
+    let registration = disk.register(&read_fd, Action::new(move || {
+        if let Some(remote) = weak_remote.upgrade() {
+            drain_remote(&remote, &drain_fd);
+        }
+    }))?;
+    TRACE!(ATEN_TASK_EXECUTOR_CREATE { DISK: disk, FD: read_fd });
+    EXECUTORS.with(|executors| {
+        executors.borrow_mut().insert(uid, Executor {
+            remote: remote.clone(),
+            registration: registration,
+            task_count: 1,
+        });
+    });
+    Ok(remote)
+}
+
+fn release_executor(uid: UID) {
+    let executor = EXECUTORS.with(|executors| {
+        let mut executors = executors.borrow_mut();
+        let executor = executors.get_mut(&uid)?;
+        executor.task_count -= 1;
+        if executor.task_count > 0 {
+            return None;
+        }
+        executors.remove(&uid)
+    });
+    drop(executor);
+}
+
+fn schedule(task_id: TaskId) {
+    let disk = TASKS.with(|tasks| {
+        let mut tasks = tasks.borrow_mut();
+        let task = tasks.get_mut(&task_id)?;
+        if task.scheduled {
+            return None;
+        }
+        task.scheduled = true;
+        task.weak_disk.upgrade()
+    });
+    if let Some(disk) = disk {
+        TRACE!(ATEN_TASK_SCHEDULE { DISK: disk, TASK: task_id });
+        disk.execute(Action::new(move || { run(task_id); }));
+    }
+}
+
+fn run(task_id: TaskId) {
+    let taken = TASKS.with(|tasks| {
+        let mut tasks = tasks.borrow_mut();
+        let task = tasks.get_mut(&task_id)?;
+        task.scheduled = false;
+        Some((task.future.take()?, task.waker.clone()))
+    });
+    let (mut future, waker) =
+        match taken {
+            Some(taken) => taken,
+            None => { return; }
+        };
+    TRACE!(ATEN_TASK_POLL { TASK: task_id });
+    let mut context = Context::from_waker(&waker);
+    match future.as_mut().poll(&mut context) {
+        Poll::Pending => {
+            TASKS.with(|tasks| {
+                if let Some(task) = tasks.borrow_mut().get_mut(&task_id) {
+                    task.future = Some(future);
+                }
+            });
+        }
+        Poll::Ready(()) => {
+            TRACE!(ATEN_TASK_DONE { TASK: task_id });
+            retire(task_id);
+        }
+    }
+}
+
+// Forget the task, dropping its future if it has not completed.
+fn retire(task_id: TaskId) {
+    let task = TASKS.with(|tasks| {
+        tasks.borrow_mut().remove(&task_id)
+    });
+    if let Some(task) = task {
+        if let Some(disk) = task.weak_disk.upgrade() {
+            release_executor(disk.0.uid);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct JoinState<T> {
+    output: Option<T>,
+    waker: Option<Waker>,
+}
+
+// Resolves to the output of a spawned future. Dropping the handle
+// detaches the task.
+#[derive(Debug)]
+pub struct JoinHandle<T> {
+    task_id: TaskId,
+    state: Rc<RefCell<JoinState<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn is_finished(&self) -> bool {
+        self.state.borrow().output.is_some()
+    }
+} // impl JoinHandle<T>
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<T> {
+        let mut state = self.state.borrow_mut();
+        match state.output.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                state.waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+} // impl<T> Future for JoinHandle<T>
+
+#[derive(Debug, Default)]
+struct TriggerState {
+    fired: bool,
+    waker: Option<Waker>,
+}
+
+// Bridges callbacks to futures: the action of the trigger wakes up
+// whichever task last parked on it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Trigger(Rc<RefCell<TriggerState>>);
+
+impl Trigger {
+    pub(crate) fn new() -> Trigger {
+        Trigger::default()
+    }
+
+    pub(crate) fn action(&self) -> Action {
+        let state = self.0.clone();
+        Action::new(move || {
+            let waker = {
+                let mut state = state.borrow_mut();
+                state.fired = true;
+                state.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        })
+    }
+
+    pub(crate) fn fired(&self) -> bool {
+        self.0.borrow().fired
+    }
+
+    pub(crate) fn park(&self, context: &Context) {
+        self.0.borrow_mut().waker = Some(context.waker().clone());
+    }
+} // impl Trigger
+
+// Dropping the future cancels the timer.
+#[derive(Debug)]
+pub struct Sleep {
+    timer: Timer,
+    trigger: Trigger,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        if self.trigger.fired() {
+            return Poll::Ready(());
+        }
+        self.trigger.park(context);
+        Poll::Pending
+    }
+} // impl Future for Sleep
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.timer.cancel();
+    }
+} // impl Drop for Sleep
+
+impl Disk {
+    pub fn sleep_until(&self, expires: Instant) -> Sleep {
+        let trigger = Trigger::new();
+        Sleep {
+            timer: self.schedule(expires, trigger.action()),
+            trigger: trigger,
+        }
+    }
+
+    // Run the future as a task of the main loop. Tasks stay on the
+    // thread that spawned them, but their wakers may be used anywhere.
+    pub fn spawn_local<F>(&self, future: F) -> Result<JoinHandle<F::Output>>
+    where F: Future + 'static {
+        let remote = executor_remote(self)?;
+        let task_id = NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed);
+        let state = Rc::new(RefCell::new(JoinState {
+            output: None,
+            waker: None,
+        }));
+        let task_state = state.clone();
+        let wrapper = async move {
+            let output = future.await;
+            let mut state = task_state.borrow_mut();
+            state.output = Some(output);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        };
+        let waker = Waker::from(Arc::new(WakeHandle {
+            thread: std::thread::current().id(),
+            task_id: task_id,
+            remote: remote,
+        }));
+        TRACE!(ATEN_TASK_SPAWN { DISK: self, TASK: task_id });
+        TASKS.with(|tasks| {
+            tasks.borrow_mut().insert(task_id, Task {
+                weak_disk: self.downgrade(),
+                future: Some(Box::pin(wrapper)),
+                waker: waker,
+                scheduled: false,
+            });
+        });
+        schedule(task_id);
+        Ok(JoinHandle {
+            task_id: task_id,
+            state: state,
+        })
+    }
+
+    // Run the main loop until the future completes. If the loop is quit
+    // before that, ECANCELED is returned.
+    pub fn block_on<F>(&self, future: F) -> Result<F::Output>
+    where F: Future + 'static {
+        let weak_disk = self.downgrade();
+        let handle = self.spawn_local(async move {
+            let output = future.await;
+            weak_disk.upped(|disk| { disk.quit(); });
+            output
+        })?;
+        TRACE!(ATEN_TASK_BLOCK_ON { DISK: self, TASK: handle.task_id });
+        let result = self.main_loop();
+        self.mut_body().quit = false;
+        result?;
+        let output = handle.state.borrow_mut().output.take();
+        match output {
+            Some(output) => Ok(output),
+            None => {
+                TRACE!(ATEN_TASK_BLOCK_ON_CANCELED {
+                    DISK: self, TASK: handle.task_id,
+                });
+                retire(handle.task_id);
+                Err(Error::from_raw_os_error(libc::ECANCELED))
+            }
+        }
+    }
+} // impl Disk
@@ -0,0 +1,88 @@
+mod common;
+
+use std::time::Duration;
+
+use aten::Disk;
+use aten::stream::{BasicStream, blob, chaos};
+use common::payload;
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Data(usize),
+    Fault(usize, i32),
+}
+
+// Read through a chaos stream to the end, recording every read and
+// every injected fault along with the offset it was hit at.
+fn run(data: &[u8], config: chaos::Config) -> (Vec<u8>, Vec<Event>) {
+    let disk = Disk::new().unwrap();
+    let wrappee = blob::Stream::new(&disk, data.to_vec()).as_bytestream();
+    let stream = chaos::Stream::new(&disk, wrappee, config).as_bytestream();
+    disk.block_on(async move {
+        let mut received = Vec::new();
+        let mut events = Vec::new();
+        let mut buf = [0u8; 1000];
+        loop {
+            match stream.read_async(&mut buf).await {
+                Ok(0) => return (received, events),
+                Ok(count) => {
+                    received.extend_from_slice(&buf[..count]);
+                    events.push(Event::Data(count));
+                }
+                Err(err) => {
+                    let errno = err.raw_os_error().unwrap();
+                    events.push(Event::Fault(received.len(), errno));
+                }
+            }
+        }
+    }).unwrap()
+}
+
+fn config(seed: u64) -> chaos::Config {
+    chaos::Config {
+        seed: seed,
+        short_read_probability: 0.5,
+        again_probability: 0.2,
+        delay_probability: 0.1,
+        max_delay: Duration::from_millis(2),
+        faults: vec![(7000, libc::ECONNRESET), (1234, libc::EIO)],
+    }
+}
+
+fn faults(events: &[Event]) -> Vec<&Event> {
+    events.iter().filter(|event| matches!(event, Event::Fault(..))).collect()
+}
+
+#[test]
+fn chaos_faults_at_configured_offsets() {
+    let data = payload(10000);
+    let (received, events) = run(&data, config(1));
+    assert_eq!(received, data);
+    assert_eq!(faults(&events), vec![&Event::Fault(1234, libc::EIO),
+                                     &Event::Fault(7000, libc::ECONNRESET)]);
+}
+
+#[test]
+fn chaos_is_deterministic_for_seed() {
+    let data = payload(10000);
+    for seed in [0, 1, 42, u64::MAX] {
+        let (_, first) = run(&data, config(seed));
+        let (_, second) = run(&data, config(seed));
+        assert_eq!(first, second);
+        assert_eq!(faults(&first).len(), 2);
+    }
+    let (_, first) = run(&data, config(1));
+    let (_, other) = run(&data, config(2));
+    assert_ne!(first, other);
+}
+
+#[test]
+fn chaos_fault_past_end_is_not_hit() {
+    let data = payload(100);
+    let (received, events) = run(&data, chaos::Config {
+        faults: vec![(100, libc::EIO), (5000, libc::EPIPE)],
+        ..Default::default()
+    });
+    assert_eq!(received, data);
+    assert_eq!(faults(&events), vec![&Event::Fault(100, libc::EIO)]);
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use aten::{Disk, Action, Fd, error};
+use aten::stream::{ByteStream, BasicStream, blob, file, nice, pacer, queue};
+use aten::stream::{chunked, naivedecoder, sub};
+use common::drain;
+
+#[test]
+fn close_propagates_to_wrappees() {
+    let disk = Disk::new().unwrap();
+    let first = blob::Stream::new(&disk, b"first".to_vec());
+    let second = blob::Stream::new(&disk, b"second".to_vec());
+    let q = queue::Stream::new(&disk, None);
+    q.enqueue(first.as_bytestream());
+    q.enqueue(second.as_bytestream());
+    let n = nice::Stream::new(&disk, q.as_bytestream(), 100);
+    let stream = n.as_bytestream();
+    stream.close();
+    assert!(n.is_closed());
+    assert!(q.is_closed());
+    assert!(first.is_closed() && second.is_closed());
+    assert!(stream.wrappees()[0].wrappees().is_empty());
+    let mut buf = [0u8; 10];
+    let err = stream.read(&mut buf).unwrap_err();
+    assert_eq!(error::errno(&err), Some(libc::EBADF));
+    assert_eq!(error::errno(&first.read(&mut buf).unwrap_err()),
+               Some(libc::EBADF));
+    stream.close();
+}
+
+#[test]
+fn close_wakes_up_pending_reader() {
+    let disk = Disk::new().unwrap();
+    let p = pacer::Stream::new(
+        &disk, blob::Stream::new(&disk, b"slow".to_vec()).as_bytestream(),
+        1.0, 4, 4).unwrap();
+    let stream = p.as_bytestream();
+    let closer = stream.clone();
+    disk.schedule(disk.in_millis(10), Action::new(move || {
+        closer.close();
+    }));
+    let start = Instant::now();
+    let reader = stream.clone();
+    let result = disk.block_on(async move {
+        let mut buf = [0u8; 10];
+        reader.read_async(&mut buf).await
+    }).unwrap();
+    assert_eq!(error::errno(&result.unwrap_err()), Some(libc::EBADF));
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn close_releases_registration() {
+    let disk = Disk::new().unwrap();
+    let mut pair = [0i32, 0i32];
+    assert_eq!(unsafe { libc::pipe(&mut pair[0]) }, 0);
+    let read_fd = Fd::new(pair[0]);
+    let _write_fd = Fd::new(pair[1]);
+    let stream = file::Stream::new(&disk, &read_fd, false).unwrap();
+    assert!(disk.register(&read_fd, Action::noop()).is_err());
+    stream.close();
+    assert!(disk.register(&read_fd, Action::noop()).is_ok());
+}
+
+// A queue that still holds the given tail once the head has been read.
+fn head_and_tail(disk: &Disk, head: &[u8], tail: &[u8]) -> ByteStream {
+    let q = queue::Stream::new(disk, None);
+    q.enqueue(blob::Stream::new(disk, head.to_vec()).as_bytestream());
+    q.enqueue(blob::Stream::new(disk, tail.to_vec()).as_bytestream());
+    q.terminate();
+    q.as_bytestream()
+}
+
+#[test]
+fn close_leaves_remainder_open() {
+    let disk = Disk::new().unwrap();
+    let decoder = naivedecoder::Stream::new(
+        &disk, head_and_tail(&disk, b"head\n", b"tail"), b'\n', None);
+    assert_eq!(drain(&disk, decoder.as_bytestream()).unwrap(), b"head");
+    decoder.close();
+    assert_eq!(drain(&disk, decoder.remainder().unwrap()).unwrap(), b"tail");
+    let decoder = chunked::Decoder::new(
+        &disk, head_and_tail(&disk, b"3\r\nabc\r\n0\r\n\r\n", b"next"));
+    assert_eq!(drain(&disk, decoder.as_bytestream()).unwrap(), b"abc");
+    decoder.close();
+    assert_eq!(drain(&disk, decoder.remainder().unwrap()).unwrap(), b"next");
+    let s = sub::Stream::new(
+        &disk, head_and_tail(&disk, b"head", b"tail"), 0, Some(4));
+    assert_eq!(drain(&disk, s.as_bytestream()).unwrap(), b"head");
+    s.close();
+    assert_eq!(drain(&disk, s.remainder().unwrap()).unwrap(), b"tail");
+}
@@ -0,0 +1,330 @@
+mod common;
+
+use aten::{Disk, error};
+use aten::stream::{ByteStream, BasicStream, blob, queue};
+use aten::stream::{base64, chunked, deflate, hex};
+use common::{drain, drain_with};
+use proptest::prelude::*;
+
+fn blob_stream(disk: &Disk, data: &[u8]) -> ByteStream {
+    blob::Stream::new(disk, data.to_vec()).as_bytestream()
+}
+
+// The data delivered in pieces of the given size.
+fn piecewise_stream(disk: &Disk, data: &[u8], piece: usize) -> ByteStream {
+    let q = queue::Stream::new(disk, None);
+    for chunk in data.chunks(piece) {
+        q.enqueue(blob_stream(disk, chunk));
+    }
+    q.terminate();
+    q.as_bytestream()
+}
+
+// Read until the stream fails, which it is expected to.
+fn read_to_error(stream: &ByteStream) -> (Vec<u8>, std::io::Error) {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 100];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => panic!("no error after {:?}", data),
+            Ok(count) => data.extend_from_slice(&buf[..count]),
+            Err(err) => return (data, err),
+        }
+    }
+}
+
+fn is_proto(err: &std::io::Error) -> bool {
+    error::errno(err) == Some(libc::EPROTO)
+}
+
+fn base64_encode(disk: &Disk, data: &[u8], alphabet: base64::Alphabet,
+                 padding: bool, line_length: Option<usize>) -> ByteStream {
+    base64::Encoder::new(disk, blob_stream(disk, data), alphabet, padding,
+                         line_length).unwrap().as_bytestream()
+}
+
+fn base64_decode(disk: &Disk, text: &[u8]) -> std::io::Result<Vec<u8>> {
+    let decoder = base64::Decoder::new(
+        disk, blob_stream(disk, text), base64::Alphabet::Standard);
+    drain(disk, decoder.as_bytestream())
+}
+
+proptest! {
+    #[test]
+    fn base64_decoder_inverts_encoder(
+        data in prop::collection::vec(any::<u8>(), 0..3000),
+        url_safe in any::<bool>(),
+        padding in any::<bool>(),
+        line_length in prop::option::of(1usize..100),
+        chunk in 1usize..500) {
+        let disk = Disk::new().unwrap();
+        let alphabet =
+            if url_safe {
+                base64::Alphabet::UrlSafe
+            } else {
+                base64::Alphabet::Standard
+            };
+        let encoded =
+            base64_encode(&disk, &data, alphabet, padding, line_length);
+        let decoder = base64::Decoder::new(&disk, encoded, alphabet);
+        let decoded =
+            drain_with(&disk, decoder.as_bytestream(), chunk).unwrap();
+        prop_assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn hex_decoder_inverts_encoder(
+        data in prop::collection::vec(any::<u8>(), 0..3000),
+        uppercase in any::<bool>(),
+        chunk in 1usize..500) {
+        let disk = Disk::new().unwrap();
+        let encoder = hex::Encoder::new(
+            &disk, blob_stream(&disk, &data), uppercase);
+        let decoder = hex::Decoder::new(&disk, encoder.as_bytestream());
+        let decoded =
+            drain_with(&disk, decoder.as_bytestream(), chunk).unwrap();
+        prop_assert_eq!(decoded, data);
+    }
+}
+
+#[test]
+fn base64_encodes_rfc4648_vectors() {
+    let disk = Disk::new().unwrap();
+    let vectors: [(&[u8], &[u8]); 7] = [
+        (b"", b""),
+        (b"f", b"Zg=="),
+        (b"fo", b"Zm8="),
+        (b"foo", b"Zm9v"),
+        (b"foob", b"Zm9vYg=="),
+        (b"fooba", b"Zm9vYmE="),
+        (b"foobar", b"Zm9vYmFy"),
+    ];
+    for (plain, encoded) in vectors {
+        let stream = base64_encode(
+            &disk, plain, base64::Alphabet::Standard, true, None);
+        assert_eq!(drain(&disk, stream).unwrap(), encoded);
+        assert_eq!(base64_decode(&disk, encoded).unwrap(), plain);
+    }
+}
+
+#[test]
+fn base64_rejects_malformed_input() {
+    let disk = Disk::new().unwrap();
+    for text in [&b"Zm9v!"[..], b"Z", b"Zg=", b"Zg===", b"=Zg", b"Zm8=v"] {
+        let err = base64_decode(&disk, text).unwrap_err();
+        assert!(is_proto(&err), "{:?}: {}", text, err);
+    }
+}
+
+#[test]
+fn base64_delivers_prefix_of_malformed_input() {
+    let disk = Disk::new().unwrap();
+    for (text, prefix) in [(&b"QUJDREVG!"[..], &b"ABCDEF"[..]),
+                           (b"QUJD\r\nRE!", b"ABC"),
+                           (b"!QUJD", b"")] {
+        for piece in 1..=text.len() {
+            let decoder = base64::Decoder::new(
+                &disk, piecewise_stream(&disk, text, piece),
+                base64::Alphabet::Standard);
+            let (data, err) = read_to_error(&decoder.as_bytestream());
+            assert_eq!(data, prefix, "{:?} in pieces of {}", text, piece);
+            assert!(is_proto(&err));
+            let mut buf = [0u8; 10];
+            assert!(is_proto(&decoder.read(&mut buf).unwrap_err()));
+        }
+    }
+}
+
+#[test]
+fn hex_rejects_malformed_input() {
+    let disk = Disk::new().unwrap();
+    for text in [&b"0g"[..], b"abc", b"12 3"] {
+        let decoder = hex::Decoder::new(&disk, blob_stream(&disk, text));
+        let err = drain(&disk, decoder.as_bytestream()).unwrap_err();
+        assert!(is_proto(&err), "{:?}: {}", text, err);
+    }
+}
+
+#[test]
+fn hex_skips_whitespace() {
+    let disk = Disk::new().unwrap();
+    let decoder = hex::Decoder::new(
+        &disk, blob_stream(&disk, b" 4142\r\n43\t4 4\n"));
+    assert_eq!(drain(&disk, decoder.as_bytestream()).unwrap(), b"ABCD");
+}
+
+#[test]
+fn hex_delivers_prefix_of_malformed_input() {
+    let disk = Disk::new().unwrap();
+    for (text, prefix) in [(&b"41 42\n43g"[..], &b"ABC"[..]),
+                           (b"4142 4x", b"AB"),
+                           (b"x41", b"")] {
+        for piece in 1..=text.len() {
+            let decoder = hex::Decoder::new(
+                &disk, piecewise_stream(&disk, text, piece));
+            let (data, err) = read_to_error(&decoder.as_bytestream());
+            assert_eq!(data, prefix, "{:?} in pieces of {}", text, piece);
+            assert!(is_proto(&err));
+            let mut buf = [0u8; 10];
+            assert!(is_proto(&decoder.read(&mut buf).unwrap_err()));
+        }
+    }
+}
+
+// "hello, world\n" as compressed by gzip(1) with no name or timestamp.
+const HELLO_GZ: [u8; 33] = [
+    0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48,
+    0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0x28, 0xcf, 0x2f, 0xca, 0x49, 0xe1, 0x02,
+    0x00, 0x53, 0x74, 0x24, 0xf4, 0x0d, 0x00, 0x00, 0x00,
+];
+
+fn inflate(disk: &Disk, data: &[u8], format: deflate::Format, chunk: usize)
+           -> std::io::Result<Vec<u8>> {
+    let decoder = deflate::Decoder::new(disk, blob_stream(disk, data), format);
+    drain_with(disk, decoder.as_bytestream(), chunk)
+}
+
+fn gunzip(disk: &Disk, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    inflate(disk, data, deflate::Format::Gzip, 4096)
+}
+
+proptest! {
+    #[test]
+    fn deflate_decoder_inverts_encoder(
+        data in prop::collection::vec(any::<u8>(), 0..20000),
+        format in prop_oneof![Just(deflate::Format::Raw),
+                              Just(deflate::Format::Zlib),
+                              Just(deflate::Format::Gzip)],
+        level in 0..=deflate::MAX_LEVEL,
+        chunk in 1usize..2000) {
+        let disk = Disk::new().unwrap();
+        let encoder = deflate::Encoder::new(
+            &disk, blob_stream(&disk, &data), format, level).unwrap();
+        let compressed = drain(&disk, encoder.as_bytestream()).unwrap();
+        prop_assert_eq!(inflate(&disk, &compressed, format, chunk).unwrap(),
+                        data);
+    }
+}
+
+#[test]
+fn gzip_decodes_reference_member() {
+    let disk = Disk::new().unwrap();
+    assert_eq!(gunzip(&disk, &HELLO_GZ).unwrap(), b"hello, world\n");
+}
+
+#[test]
+fn gzip_decodes_concatenated_members() {
+    let disk = Disk::new().unwrap();
+    let mut data = HELLO_GZ.to_vec();
+    data.extend_from_slice(&HELLO_GZ);
+    assert_eq!(gunzip(&disk, &data).unwrap(),
+               b"hello, world\nhello, world\n");
+}
+
+#[test]
+fn gzip_rejects_bad_crc() {
+    let disk = Disk::new().unwrap();
+    let mut data = HELLO_GZ.to_vec();
+    data[25] ^= 1;
+    assert!(is_proto(&gunzip(&disk, &data).unwrap_err()));
+}
+
+#[test]
+fn gzip_rejects_bad_size() {
+    let disk = Disk::new().unwrap();
+    let mut data = HELLO_GZ.to_vec();
+    data[29] += 1;
+    assert!(is_proto(&gunzip(&disk, &data).unwrap_err()));
+}
+
+#[test]
+fn gzip_rejects_truncated_trailer() {
+    let disk = Disk::new().unwrap();
+    for length in [HELLO_GZ.len() - 1, HELLO_GZ.len() - 8, 5] {
+        let err = gunzip(&disk, &HELLO_GZ[..length]).unwrap_err();
+        assert!(is_proto(&err), "length {}: {}", length, err);
+    }
+}
+
+#[test]
+fn gzip_rejects_trailing_garbage() {
+    let disk = Disk::new().unwrap();
+    let mut data = HELLO_GZ.to_vec();
+    data.extend_from_slice(b"garbage!!!");
+    assert!(is_proto(&gunzip(&disk, &data).unwrap_err()));
+}
+
+fn dechunk(disk: &Disk, data: &[u8])
+           -> (chunked::Decoder, std::io::Result<Vec<u8>>) {
+    let decoder = chunked::Decoder::new(disk, blob_stream(disk, data));
+    let result = drain(disk, decoder.as_bytestream());
+    (decoder, result)
+}
+
+fn trailer() -> impl Strategy<Value = (String, String)> {
+    ("[A-Za-z][A-Za-z0-9-]{0,20}", "[ -~]{0,30}")
+        .prop_map(|(name, value)| (name, value.trim().to_string()))
+}
+
+proptest! {
+    #[test]
+    fn chunked_decoder_inverts_encoder(
+        data in prop::collection::vec(any::<u8>(), 0..10000),
+        trailers in prop::collection::vec(trailer(), 0..4),
+        chunk in 1usize..3000) {
+        let disk = Disk::new().unwrap();
+        let encoder = chunked::Encoder::new(&disk, blob_stream(&disk, &data));
+        encoder.set_trailers(trailers.clone()).unwrap();
+        let decoder = chunked::Decoder::new(&disk, encoder.as_bytestream());
+        prop_assert!(decoder.trailers().is_none());
+        let decoded =
+            drain_with(&disk, decoder.as_bytestream(), chunk).unwrap();
+        prop_assert_eq!(decoded, data);
+        prop_assert_eq!(decoder.trailers().unwrap(), trailers);
+    }
+}
+
+#[test]
+fn chunked_decodes_extensions_and_trailers() {
+    let disk = Disk::new().unwrap();
+    let (decoder, result) = dechunk(
+        &disk,
+        b"4\r\nWiki\r\n5;ext=1\r\npedia\r\n0\r\nX-Foo:  bar \r\n\r\nnext");
+    assert_eq!(result.unwrap(), b"Wikipedia");
+    assert_eq!(decoder.trailers().unwrap(),
+               vec![("X-Foo".to_string(), "bar".to_string())]);
+    assert_eq!(drain(&disk, decoder.remainder().unwrap()).unwrap(), b"next");
+}
+
+#[test]
+fn chunked_rejects_malformed_input() {
+    let disk = Disk::new().unwrap();
+    let cases: [&[u8]; 7] = [
+        b"zz\r\n",                       // bad size
+        b"\r\n",                         // empty size
+        b"3\r\nabcd\r\n0\r\n\r\n",         // chunk overrun
+        b"3\r\nab",                      // input ends in chunk
+        b"3\r\nabc\r\n",                 // input ends in framing
+        b"0\r\nno colon\r\n\r\n",          // bad trailer
+        b"0\r\n: empty name\r\n\r\n",      // bad trailer
+    ];
+    for text in cases {
+        let (decoder, result) = dechunk(&disk, text);
+        let err = result.unwrap_err();
+        assert!(is_proto(&err), "{:?}: {}", text, err);
+        assert!(decoder.trailers().is_none());
+        assert!(aten::error::details(&err).is_some());
+    }
+}
+
+#[test]
+fn chunked_encoder_rejects_bad_trailers() {
+    let disk = Disk::new().unwrap();
+    let encoder = chunked::Encoder::new(&disk, blob_stream(&disk, b""));
+    let bad = vec![("X-Foo".to_string(), "a\r\nb".to_string())];
+    assert!(encoder.set_trailers(bad).is_err());
+    let bad = vec![("X Foo".to_string(), "ab".to_string())];
+    assert!(encoder.set_trailers(bad).is_err());
+    assert_eq!(drain(&disk, encoder.as_bytestream()).unwrap(),
+               b"0\r\n\r\n");
+}
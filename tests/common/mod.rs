@@ -0,0 +1,35 @@
+#![allow(dead_code)]
+
+use std::io::Result;
+
+use aten::Disk;
+use aten::stream::ByteStream;
+
+// Read the stream to the end under the main loop.
+pub fn drain(disk: &Disk, stream: ByteStream) -> Result<Vec<u8>> {
+    drain_with(disk, stream, 4096)
+}
+
+pub fn drain_with(disk: &Disk, stream: ByteStream, chunk: usize)
+                  -> Result<Vec<u8>> {
+    disk.block_on(async move {
+        let mut data = Vec::new();
+        let mut buf = vec![0u8; chunk];
+        loop {
+            let count = stream.read_async(&mut buf).await?;
+            if count == 0 {
+                return Ok(data);
+            }
+            data.extend_from_slice(&buf[..count]);
+        }
+    })?
+}
+
+// Run whatever is due by now.
+pub fn settle(disk: &Disk) {
+    disk.block_on(async {}).unwrap();
+}
+
+pub fn payload(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i * 7 % 251) as u8).collect()
+}
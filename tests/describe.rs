@@ -0,0 +1,88 @@
+mod common;
+
+use aten::Disk;
+use aten::stream::{ByteStream, BasicStream};
+use aten::stream::{blob, naivedecoder, nice, pacer, queue, reservoir};
+use common::drain;
+
+fn pipeline(disk: &Disk) -> (queue::Stream, ByteStream) {
+    let q = queue::Stream::new(disk, None);
+    q.enqueue(blob::Stream::new(disk, b"hello".to_vec()).as_bytestream());
+    q.enqueue(blob::Stream::new(disk, b"world".to_vec()).as_bytestream());
+    let n = nice::Stream::new(disk, q.as_bytestream(), 100);
+    let p = pacer::Stream::new(
+        disk, n.as_bytestream(), 1000.0, 10, 100).unwrap();
+    (q, p.as_bytestream())
+}
+
+#[test]
+fn wrappees_are_reported() {
+    let disk = Disk::new().unwrap();
+    let (q, stream) = pipeline(&disk);
+    let wrappees = stream.wrappees();
+    assert_eq!(wrappees.len(), 1);
+    assert_eq!(wrappees[0].kind(), "nice::Stream");
+    let queued = q.as_bytestream().wrappees();
+    assert_eq!(queued.len(), 2);
+    assert!(queued.iter().all(|stream| stream.kind() == "blob::Stream"));
+    assert!(queued[0].wrappees().is_empty());
+}
+
+#[test]
+fn describe_renders_tree() {
+    let disk = Disk::new().unwrap();
+    let (q, stream) = pipeline(&disk);
+    let description = stream.describe();
+    let lines: Vec<&str> = description.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert!(lines[0].starts_with("pacer::Stream "));
+    assert!(lines[0].contains("(quota 0.0, rate 1000)"));
+    assert!(lines[1].starts_with("  nice::Stream "));
+    assert!(lines[2].starts_with("    queue::Stream "));
+    assert!(lines[2].ends_with("(length 2)"));
+    assert!(lines[3].starts_with("      blob::Stream "));
+    assert!(lines[3].ends_with("(remaining 5)"));
+    q.terminate();
+    assert!(stream.describe().lines().nth(2).unwrap()
+            .ends_with("(length 2, terminated)"));
+}
+
+#[test]
+fn describe_dot_links_wrappers_to_wrappees() {
+    let disk = Disk::new().unwrap();
+    let (_q, stream) = pipeline(&disk);
+    let dot = stream.describe_dot();
+    assert!(dot.starts_with("digraph {\n"));
+    assert!(dot.ends_with("}\n"));
+    assert_eq!(dot.matches(" [label=").count(), 5);
+    assert_eq!(dot.matches(" -> ").count(), 4);
+}
+
+#[test]
+fn shared_wrappees_are_shown_once() {
+    let disk = Disk::new().unwrap();
+    let shared = blob::Stream::new(&disk, b"x".to_vec()).as_bytestream();
+    let q = queue::Stream::new(&disk, None);
+    q.enqueue(shared.clone());
+    q.enqueue(shared);
+    let description = q.as_bytestream().describe();
+    assert_eq!(description.lines().count(), 3);
+    assert!(description.lines().nth(2).unwrap().ends_with(" (see above)"));
+    assert_eq!(q.as_bytestream().describe_dot().matches(" [label=").count(),
+               2);
+}
+
+#[test]
+fn summaries_track_state() {
+    let disk = Disk::new().unwrap();
+    let data = b"framed\0trailer".to_vec();
+    let r = reservoir::Stream::new(
+        &disk, blob::Stream::new(&disk, data.clone()).as_bytestream(), 100);
+    let decoder = naivedecoder::Stream::new(&disk, r.as_bytestream(), 0, None);
+    assert_eq!(decoder.as_bytestream().summary(), "Reading");
+    assert_eq!(r.as_bytestream().summary(), "amount 0/100, spilled 0");
+    assert_eq!(drain(&disk, decoder.as_bytestream()).unwrap(), b"framed");
+    assert_eq!(decoder.as_bytestream().summary(), "Terminated");
+    assert_eq!(r.as_bytestream().summary(),
+               "amount 14/100, spilled 0, filled");
+}
@@ -0,0 +1,150 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use aten::{Disk, Action, Downgradable, Upgradable};
+
+type Log = Rc<RefCell<Vec<&'static str>>>;
+
+fn note(log: &Log, what: &'static str) -> Action {
+    let log = log.clone();
+    Action::new(move || { log.borrow_mut().push(what); })
+}
+
+fn quit(disk: &Disk) -> Action {
+    let weak_disk = disk.downgrade();
+    Action::new(move || {
+        if let Some(disk) = weak_disk.upgrade() {
+            disk.quit();
+        }
+    })
+}
+
+#[test]
+fn timers_expire_in_order() {
+    let disk = Disk::new().unwrap();
+    let log = Log::default();
+    disk.schedule(disk.in_millis(30), note(&log, "c"));
+    disk.schedule(disk.in_millis(10), note(&log, "a"));
+    disk.schedule(disk.in_millis(20), note(&log, "b"));
+    disk.schedule(disk.in_millis(40), quit(&disk));
+    let start = disk.now();
+    disk.main_loop().unwrap();
+    assert_eq!(*log.borrow(), ["a", "b", "c"]);
+    assert!(disk.now() - start >= Duration::from_millis(40));
+}
+
+#[test]
+fn timers_with_equal_expiry_keep_scheduling_order() {
+    let disk = Disk::new().unwrap();
+    let log = Log::default();
+    let expires = disk.in_millis(5);
+    disk.schedule(expires, note(&log, "first"));
+    disk.schedule(expires, note(&log, "second"));
+    disk.schedule(expires, quit(&disk));
+    disk.main_loop().unwrap();
+    assert_eq!(*log.borrow(), ["first", "second"]);
+}
+
+#[test]
+fn immediate_actions_interleave_with_due_timers() {
+    let disk = Disk::new().unwrap();
+    let log = Log::default();
+    disk.schedule(disk.in_millis(5), note(&log, "later"));
+    disk.schedule(disk.now(), note(&log, "due"));
+    disk.execute(note(&log, "executed 1"));
+    disk.execute(note(&log, "executed 2"));
+    disk.schedule(disk.in_millis(10), quit(&disk));
+    disk.main_loop().unwrap();
+    assert_eq!(*log.borrow(), ["due", "executed 1", "executed 2", "later"]);
+}
+
+#[test]
+fn canceled_timers_do_not_fire() {
+    let disk = Disk::new().unwrap();
+    let log = Log::default();
+    let scheduled = disk.schedule(disk.in_millis(5), note(&log, "scheduled"));
+    let executed = disk.execute(note(&log, "executed"));
+    disk.schedule(disk.in_millis(10), note(&log, "kept"));
+    disk.schedule(disk.in_millis(20), quit(&disk));
+    scheduled.cancel();
+    executed.cancel();
+    disk.main_loop().unwrap();
+    assert_eq!(*log.borrow(), ["kept"]);
+}
+
+#[test]
+fn timer_can_be_canceled_from_another_action() {
+    let disk = Disk::new().unwrap();
+    let log = Log::default();
+    let victim = disk.schedule(disk.in_millis(10), note(&log, "victim"));
+    disk.schedule(disk.in_millis(5), Action::new(move || { victim.cancel(); }));
+    disk.schedule(disk.in_millis(20), quit(&disk));
+    disk.main_loop().unwrap();
+    assert!(log.borrow().is_empty());
+}
+
+#[test]
+fn canceling_an_expired_timer_is_harmless() {
+    let disk = Disk::new().unwrap();
+    let log = Log::default();
+    let timer = disk.execute(note(&log, "done"));
+    disk.execute(quit(&disk));
+    disk.main_loop().unwrap();
+    timer.cancel();
+    assert_eq!(*log.borrow(), ["done"]);
+}
+
+#[test]
+fn event_fires_once_per_trigger_round() {
+    let disk = Disk::new().unwrap();
+    let log = Log::default();
+    let event = disk.make_event(note(&log, "event"));
+    event.trigger();
+    event.trigger();
+    disk.execute(quit(&disk));
+    disk.main_loop().unwrap();
+    assert_eq!(*log.borrow(), ["event"]);
+    event.trigger();
+    disk.execute(quit(&disk));
+    disk.main_loop().unwrap();
+    assert_eq!(*log.borrow(), ["event", "event"]);
+}
+
+#[test]
+fn canceled_event_does_not_fire() {
+    let disk = Disk::new().unwrap();
+    let log = Log::default();
+    let event = disk.make_event(note(&log, "event"));
+    event.cancel();
+    event.trigger();
+    event.cancel();
+    disk.execute(quit(&disk));
+    disk.main_loop().unwrap();
+    assert!(log.borrow().is_empty());
+}
+
+#[test]
+fn retriggered_event_fires_after_cancel() {
+    let disk = Disk::new().unwrap();
+    let log = Log::default();
+    let event = disk.make_event(note(&log, "event"));
+    event.trigger();
+    event.cancel();
+    event.trigger();
+    disk.execute(quit(&disk));
+    disk.main_loop().unwrap();
+    assert_eq!(*log.borrow(), ["event"]);
+}
+
+#[test]
+fn dropped_event_does_not_fire() {
+    let disk = Disk::new().unwrap();
+    let log = Log::default();
+    let event = disk.make_event(note(&log, "event"));
+    event.trigger();
+    drop(event);
+    disk.execute(quit(&disk));
+    disk.main_loop().unwrap();
+    assert!(log.borrow().is_empty());
+}
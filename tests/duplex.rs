@@ -0,0 +1,216 @@
+mod common;
+
+use std::cell::RefCell;
+use std::io::Result;
+use std::net::Shutdown;
+use std::os::unix::io::AsRawFd;
+use std::rc::Rc;
+
+use aten::{Disk, Fd, Action, UID, Downgradable, Upgradable};
+use aten::misc::Duplex;
+use aten::misc::linger::State;
+use aten::misc::unix_connect::socket_pair;
+use aten::stream::{ByteStream, BasicStream, blob};
+use aten::stream::{ByteStreamPair, ByteStreamPairBody};
+use aten::stream::DebuggableByteStreamPairBody;
+use common::{drain, payload, settle};
+
+// Read exactly the given number of bytes under the main loop.
+fn read_exact(disk: &Disk, stream: ByteStream, size: usize)
+              -> Result<Vec<u8>> {
+    disk.block_on(async move {
+        let mut data = vec![0u8; size];
+        let mut cursor = 0;
+        while cursor < size {
+            let count = stream.read_async(&mut data[cursor..]).await?;
+            if count == 0 {
+                break;
+            }
+            cursor += count;
+        }
+        data.truncate(cursor);
+        Ok(data)
+    })?
+}
+
+fn shut_down_writing(fd: &Fd) {
+    assert_eq!(unsafe { libc::shutdown(fd.as_raw_fd(), libc::SHUT_WR) }, 0);
+}
+
+#[test]
+fn socket_pair_carries_traffic_both_ways() {
+    let disk = Disk::new().unwrap();
+    let ((left, _left_fd), (right, _right_fd)) = socket_pair(&disk).unwrap();
+    let to_right = payload(300000);
+    let to_left: Vec<u8> = payload(200000).into_iter().rev().collect();
+    left.set_egress(blob::Stream::new(&disk, to_right.clone()).as_bytestream());
+    right.set_egress(blob::Stream::new(&disk, to_left.clone()).as_bytestream());
+    let right_ingress = right.get_ingress().unwrap();
+    let left_ingress = left.get_ingress().unwrap();
+    assert_eq!(read_exact(&disk, right_ingress, to_right.len()).unwrap(),
+               to_right);
+    assert_eq!(read_exact(&disk, left_ingress, to_left.len()).unwrap(),
+               to_left);
+    disk.block_on(async {}).unwrap();
+    assert!(matches!(left.poll_egress(), State::Final(Ok(()))));
+    assert!(matches!(right.poll_egress(), State::Final(Ok(()))));
+}
+
+#[test]
+fn socket_pair_delivers_eof_after_shutdown() {
+    let disk = Disk::new().unwrap();
+    let ((left, left_fd), (right, _right_fd)) = socket_pair(&disk).unwrap();
+    left.set_egress(blob::Stream::new(&disk, b"last words".to_vec())
+                    .as_bytestream());
+    let ingress = right.get_ingress().unwrap();
+    assert_eq!(read_exact(&disk, ingress.clone(), 10).unwrap(),
+               b"last words");
+    shut_down_writing(&left_fd);
+    assert_eq!(drain(&disk, ingress).unwrap(), b"");
+}
+
+#[test]
+fn socket_pair_echo() {
+    let disk = Disk::new().unwrap();
+    let ((left, left_fd), (right, right_fd)) = socket_pair(&disk).unwrap();
+    right.set_egress(right.get_ingress().unwrap());
+    let data = payload(100000);
+    left.set_egress(blob::Stream::new(&disk, data.clone()).as_bytestream());
+    let ingress = left.get_ingress().unwrap();
+    assert_eq!(read_exact(&disk, ingress.clone(), data.len()).unwrap(), data);
+    // Closing one direction ends the echo.
+    let weak_disk = disk.downgrade();
+    right.register_egress_callback(Action::new(move || {
+        weak_disk.upped(|disk| { disk.quit(); });
+    }));
+    shut_down_writing(&left_fd);
+    disk.main_loop().unwrap();
+    assert!(matches!(right.poll_egress(), State::Final(Ok(()))));
+    shut_down_writing(&right_fd);
+    assert_eq!(drain(&disk, ingress).unwrap(), b"");
+}
+
+// A duplex on one end of a socket pair and the bare fd of the other.
+fn duplex_and_peer(disk: &Disk) -> (Duplex, Fd) {
+    let mut pair = [0i32, 0i32];
+    assert_eq!(unsafe {
+        libc::socketpair(libc::PF_UNIX, libc::SOCK_STREAM, 0, &mut pair[0])
+    }, 0);
+    let fd = Fd::new(pair[0]);
+    (Duplex::new(disk, &fd).unwrap(), Fd::new(pair[1]))
+}
+
+// Blocking read of everything up to EOF.
+fn read_to_eof(fd: &Fd) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let count = unsafe {
+            libc::read(fd.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void,
+                       buf.len())
+        };
+        assert!(count >= 0);
+        if count == 0 {
+            return data;
+        }
+        data.extend_from_slice(&buf[..count as usize]);
+    }
+}
+
+fn write_all(fd: &Fd, data: &[u8]) {
+    assert_eq!(unsafe {
+        libc::write(fd.as_raw_fd(), data.as_ptr() as *const libc::c_void,
+                    data.len())
+    }, data.len() as isize);
+}
+
+#[test]
+fn duplex_shutdown_reaches_peer() {
+    let disk = Disk::new().unwrap();
+    let (duplex, peer) = duplex_and_peer(&disk);
+    duplex.shutdown(Shutdown::Write).unwrap();
+    assert_eq!(read_to_eof(&peer), b"");
+    // The other direction still works.
+    let data = b"still listening";
+    write_all(&peer, data);
+    shut_down_writing(&peer);
+    assert_eq!(drain(&disk, duplex.get_ingress().unwrap()).unwrap(), data);
+}
+
+#[test]
+fn duplex_detects_peer_half_close_and_hang_up() {
+    let disk = Disk::new().unwrap();
+    let (duplex, peer) = duplex_and_peer(&disk);
+    assert!(duplex.peer_shutdown().is_none());
+    shut_down_writing(&peer);
+    assert_eq!(drain(&disk, duplex.get_ingress().unwrap()).unwrap(), b"");
+    assert_eq!(duplex.peer_shutdown(), Some(Shutdown::Write));
+    drop(peer);
+    settle(&disk);
+    assert_eq!(duplex.peer_shutdown(), Some(Shutdown::Both));
+}
+
+#[test]
+fn duplex_shuts_down_after_egress() {
+    let disk = Disk::new().unwrap();
+    let (duplex, peer) = duplex_and_peer(&disk);
+    duplex.set_shutdown_on_egress_eof(true);
+    let data = payload(50000);
+    let weak_disk = disk.downgrade();
+    duplex.register_egress_callback(Action::new(move || {
+        weak_disk.upped(|disk| { disk.quit(); });
+    }));
+    duplex.set_egress(blob::Stream::new(&disk, data.clone()).as_bytestream());
+    let reader = std::thread::spawn(move || read_to_eof(&peer));
+    disk.main_loop().unwrap();
+    assert!(matches!(duplex.poll_egress(), State::Final(Ok(()))));
+    assert_eq!(reader.join().unwrap(), data);
+}
+
+#[test]
+fn duplex_echoes_its_own_ingress() {
+    let disk = Disk::new().unwrap();
+    let (duplex, peer) = duplex_and_peer(&disk);
+    duplex.set_shutdown_on_egress_eof(true);
+    duplex.set_egress(duplex.get_ingress().unwrap());
+    let weak_disk = disk.downgrade();
+    duplex.register_egress_callback(Action::new(move || {
+        weak_disk.upped(|disk| { disk.quit(); });
+    }));
+    let data = payload(20000);
+    write_all(&peer, &data);
+    shut_down_writing(&peer);
+    disk.main_loop().unwrap();
+    assert!(matches!(duplex.poll_egress(), State::Final(Ok(()))));
+    assert_eq!(read_to_eof(&peer), data);
+}
+
+// Hands back whatever it is given; does not track the egress.
+#[derive(Debug, Default)]
+struct Mirror {
+    egress: Option<ByteStream>,
+}
+
+impl ByteStreamPairBody for Mirror {
+    fn get_ingress(&self) -> Option<ByteStream> {
+        self.egress.clone()
+    }
+
+    fn set_egress(&mut self, egress: ByteStream) {
+        self.egress = Some(egress);
+    }
+}
+
+impl DebuggableByteStreamPairBody for Mirror {}
+
+#[test]
+fn pair_without_egress_tracking_stays_busy() {
+    let disk = Disk::new().unwrap();
+    let pair = ByteStreamPair::new(UID::new(),
+                                   Rc::new(RefCell::new(Mirror::default())));
+    pair.set_egress(blob::Stream::new(&disk, b"hi".to_vec()).as_bytestream());
+    pair.register_egress_callback(Action::noop());
+    assert!(matches!(pair.poll_egress(), State::Busy));
+    pair.unregister_egress_callback();
+    assert_eq!(drain(&disk, pair.get_ingress().unwrap()).unwrap(), b"hi");
+}
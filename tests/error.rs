@@ -0,0 +1,101 @@
+mod common;
+
+use std::io::{Error, ErrorKind};
+
+use aten::{Disk, UID, error};
+use aten::error::{AtenError, Kind};
+use aten::stream::{ByteStream, BasicStream, blob, chunked, naivedecoder};
+use aten::stream::{base64, deflate, hex, replay};
+use common::drain;
+
+#[test]
+fn aten_error_converts_into_io_error() {
+    let err: Error = AtenError::new(Kind::Again, "nothing yet").into();
+    assert!(error::is_again(&err));
+    assert_eq!(err.raw_os_error(), None);
+    assert_eq!(error::errno(&err), Some(libc::EAGAIN));
+    let details = error::details(&err).unwrap();
+    assert_eq!(details.kind(), Kind::Again);
+    assert_eq!(details.context(), "nothing yet");
+    assert_eq!(details.origin(), None);
+    let err: Error = AtenError::from_errno(libc::ECONNRESET, "peer").into();
+    assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+    assert_eq!(error::details(&err).unwrap().kind(), Kind::Other);
+    assert_eq!(error::errno(&error::proto()), Some(libc::EPROTO));
+    assert!(error::details(&error::proto()).is_none());
+}
+
+#[test]
+fn decoder_errors_name_their_origin() {
+    let disk = Disk::new().unwrap();
+    let decoder = naivedecoder::Stream::new(
+        &disk, blob::Stream::new(&disk, b"open".to_vec()).as_bytestream(),
+        0, None);
+    let err = drain(&disk, decoder.as_bytestream()).unwrap_err();
+    let details = error::details(&err).unwrap();
+    assert_eq!(details.kind(), Kind::Protocol);
+    assert_eq!(details.errno(), libc::EPROTO);
+    assert_eq!(details.origin(), Some(decoder.get_link().uid));
+    assert!(err.to_string().starts_with(
+        &format!("{}: input ends before terminator", decoder.get_link().uid)));
+}
+
+#[test]
+fn chunked_decoder_explains_malformed_input() {
+    let disk = Disk::new().unwrap();
+    let decoder = chunked::Decoder::new(
+        &disk, blob::Stream::new(&disk, b"zz\r\n".to_vec()).as_bytestream());
+    let err = drain(&disk, decoder.as_bytestream()).unwrap_err();
+    assert_eq!(error::details(&err).unwrap().context(), "bad chunk size");
+    assert_eq!(error::errno(&err), Some(libc::EPROTO));
+}
+
+fn blob_stream(disk: &Disk, data: &[u8]) -> ByteStream {
+    blob::Stream::new(disk, data.to_vec()).as_bytestream()
+}
+
+fn origin_and_context(err: &Error) -> (Option<UID>, String) {
+    let details = error::details(err).unwrap();
+    assert_eq!(details.kind(), Kind::Protocol);
+    assert_eq!(error::errno(err), Some(libc::EPROTO));
+    (details.origin(), details.context().to_string())
+}
+
+#[test]
+fn codec_errors_name_their_origin() {
+    let disk = Disk::new().unwrap();
+    let decoder = base64::Decoder::new(
+        &disk, blob_stream(&disk, b"Zm9v!"), base64::Alphabet::Standard);
+    let err = drain(&disk, decoder.as_bytestream()).unwrap_err();
+    assert_eq!(origin_and_context(&err),
+               (Some(decoder.get_link().uid), "invalid symbol".to_string()));
+    let decoder = hex::Decoder::new(&disk, blob_stream(&disk, b"abc"));
+    let err = drain(&disk, decoder.as_bytestream()).unwrap_err();
+    assert_eq!(origin_and_context(&err),
+               (Some(decoder.get_link().uid),
+                "input ends in a byte".to_string()));
+    let decoder = deflate::Decoder::new(
+        &disk, blob_stream(&disk, b"not gzip at all"), deflate::Format::Gzip);
+    let err = drain(&disk, decoder.as_bytestream()).unwrap_err();
+    assert_eq!(origin_and_context(&err),
+               (Some(decoder.get_link().uid), "bad gzip header".to_string()));
+    let err = replay::Stream::new(
+        &disk, b"ATENREC\x01\x00\x07", replay::Timing::Virtual)
+        .unwrap_err();
+    assert_eq!(origin_and_context(&err), (None, "unknown tag".to_string()));
+}
+
+#[test]
+fn errors_keep_their_own_details() {
+    let disk = Disk::new().unwrap();
+    let first = chunked::Decoder::new(&disk, blob_stream(&disk, b"zz\r\n"));
+    let first = drain(&disk, first.as_bytestream()).unwrap_err();
+    let second = naivedecoder::Stream::new(
+        &disk, blob_stream(&disk, b"open"), 0, None);
+    let second = drain(&disk, second.as_bytestream()).unwrap_err();
+    assert_eq!(error::details(&first).unwrap().context(), "bad chunk size");
+    assert_eq!(error::details(&second).unwrap().context(),
+               "input ends before terminator");
+    // A plain error of the same errno has no story to tell.
+    assert!(error::details(&error::proto()).is_none());
+}
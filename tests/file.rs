@@ -0,0 +1,136 @@
+mod common;
+
+use std::ffi::CString;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+use aten::Disk;
+use aten::stream::{BasicStream, file, mmap};
+use common::{drain, drain_with, payload};
+
+fn scratch_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(
+        format!("aten-{}-{}", name, std::process::id()))
+}
+
+#[test]
+fn file_reads_range_of_regular_file() {
+    let disk = Disk::new().unwrap();
+    let path = scratch_path("range");
+    let data = payload(10000);
+    std::fs::write(&path, &data).unwrap();
+    let stream = file::Stream::open(&disk, &path, 1000, Some(5000)).unwrap();
+    assert_eq!(drain(&disk, stream.as_bytestream()).unwrap(),
+               &data[1000..6000]);
+    assert_eq!(stream.position(), Some(6000));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_opens_fifo_without_writer() {
+    let disk = Disk::new().unwrap();
+    let path = scratch_path("fifo");
+    let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+    let stream = file::Stream::open(&disk, &path, 0, None).unwrap();
+    assert_eq!(stream.position(), None);
+    let mut writer =
+        std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    writer.write_all(b"through the pipe").unwrap();
+    drop(writer);
+    assert_eq!(drain(&disk, stream.as_bytestream()).unwrap(),
+               b"through the pipe");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_reads_to_eof_without_length() {
+    let disk = Disk::new().unwrap();
+    let path = scratch_path("tail");
+    let data = payload(10000);
+    std::fs::write(&path, &data).unwrap();
+    let stream = file::Stream::open(&disk, &path, 9000, None).unwrap();
+    stream.advise(file::Advice::Sequential).unwrap();
+    assert_eq!(drain_with(&disk, stream.as_bytestream(), 100).unwrap(),
+               &data[9000..]);
+    assert_eq!(stream.position(), Some(10000));
+    let past_end = file::Stream::open(&disk, &path, 20000, None).unwrap();
+    assert_eq!(drain(&disk, past_end.as_bytestream()).unwrap(), b"");
+    let empty = file::Stream::open(&disk, &path, 0, Some(0)).unwrap();
+    assert_eq!(drain(&disk, empty.as_bytestream()).unwrap(), b"");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_open_reports_errors() {
+    let disk = Disk::new().unwrap();
+    let err = file::Stream::open(
+        &disk, &scratch_path("missing"), 0, None).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+    let path = scratch_path("unseekable");
+    let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+    let err = file::Stream::open(&disk, &path, 10, None).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ESPIPE));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn closed_file_stream_fails_reads() {
+    let disk = Disk::new().unwrap();
+    let path = scratch_path("closed");
+    std::fs::write(&path, b"contents").unwrap();
+    let stream = file::Stream::open(&disk, &path, 0, None).unwrap();
+    stream.close();
+    let mut buf = [0u8; 10];
+    assert_eq!(stream.read(&mut buf).unwrap_err().raw_os_error(),
+               Some(libc::EBADF));
+    assert!(stream.as_bytestream().splice_source().is_none());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn mmap_maps_unaligned_range() {
+    let disk = Disk::new().unwrap();
+    let path = scratch_path("mmap");
+    let data = payload(20000);
+    std::fs::write(&path, &data).unwrap();
+    let stream = mmap::Stream::open(&disk, &path, 5001, Some(7000)).unwrap();
+    // The mapping outlives the file's name.
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(stream.remaining(), 7000);
+    stream.advise_sequential().unwrap();
+    let lent = stream.peek().unwrap().unwrap();
+    assert_eq!(lent.len(), 1);
+    assert_eq!(&lent[0][..], &data[5001..12001]);
+    stream.consume(1000);
+    assert_eq!(stream.remaining(), 6000);
+    assert_eq!(drain_with(&disk, stream.as_bytestream(), 999).unwrap(),
+               &data[6001..12001]);
+    assert_eq!(stream.peek().unwrap().unwrap().len(), 0);
+}
+
+#[test]
+fn mmap_cuts_range_at_eof() {
+    let disk = Disk::new().unwrap();
+    let path = scratch_path("mmap-eof");
+    let data = payload(10000);
+    std::fs::write(&path, &data).unwrap();
+    let stream = mmap::Stream::open(&disk, &path, 9000, Some(5000)).unwrap();
+    assert_eq!(drain(&disk, stream.as_bytestream()).unwrap(), &data[9000..]);
+    let at_end = mmap::Stream::open(&disk, &path, 10000, None).unwrap();
+    assert_eq!(at_end.remaining(), 0);
+    assert_eq!(drain(&disk, at_end.as_bytestream()).unwrap(), b"");
+    let err = mmap::Stream::open(&disk, &path, 10001, None).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn mmap_rejects_irregular_files() {
+    let disk = Disk::new().unwrap();
+    let err = mmap::Stream::open(
+        &disk, &std::env::temp_dir(), 0, None).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+}
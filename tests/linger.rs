@@ -0,0 +1,164 @@
+mod common;
+
+use std::cell::Cell;
+use std::os::unix::io::AsRawFd;
+use std::rc::Rc;
+
+use aten::{Disk, Action, Fd, error};
+use aten::misc::{Linger, pipe};
+use aten::misc::linger::State;
+use aten::stream::{BasicStream, blob, file, queue};
+use common::{drain, payload};
+
+#[test]
+fn linger_copies_to_fd() {
+    let disk = Disk::new().unwrap();
+    let (read_stream, write_fd) = pipe(&disk).unwrap();
+    let data = payload(100000);
+    let linger = Linger::new(
+        &disk, blob::Stream::new(&disk, data.clone()).as_bytestream(),
+        &write_fd, false).unwrap();
+    drop(write_fd);
+    assert!(matches!(linger.poll(), State::Busy));
+    linger.prod();
+    assert_eq!(drain(&disk, read_stream).unwrap(), data);
+    assert!(matches!(linger.poll(), State::Final(Ok(()))));
+    assert!(matches!(linger.poll(), State::Stale));
+}
+
+#[test]
+fn linger_calls_back_when_done() {
+    let disk = Disk::new().unwrap();
+    let (read_stream, write_fd) = pipe(&disk).unwrap();
+    let linger = Linger::new(
+        &disk, blob::Stream::new(&disk, b"done".to_vec()).as_bytestream(),
+        &write_fd, false).unwrap();
+    drop(write_fd);
+    let called = Rc::new(Cell::new(false));
+    let flag = called.clone();
+    linger.register_callback(Action::new(move || { flag.set(true); }));
+    linger.prod();
+    assert_eq!(drain(&disk, read_stream).unwrap(), b"done");
+    disk.block_on(async {}).unwrap();
+    assert!(called.get());
+}
+
+#[test]
+fn drifting_linger_finishes_on_its_own() {
+    let disk = Disk::new().unwrap();
+    let (read_stream, write_fd) = pipe(&disk).unwrap();
+    let data = payload(100000);
+    let linger = Linger::new(
+        &disk, blob::Stream::new(&disk, data.clone()).as_bytestream(),
+        &write_fd, false).unwrap();
+    drop(write_fd);
+    let called = Rc::new(Cell::new(false));
+    let flag = called.clone();
+    linger.register_callback(Action::new(move || { flag.set(true); }));
+    linger.prod();
+    linger.drift();
+    assert!(matches!(linger.poll(), State::Drifting));
+    drop(linger);
+    assert_eq!(drain(&disk, read_stream).unwrap(), data);
+    disk.block_on(async {}).unwrap();
+    assert!(!called.get());
+}
+
+#[test]
+fn drifting_a_finished_linger_releases_it() {
+    let disk = Disk::new().unwrap();
+    let (read_stream, write_fd) = pipe(&disk).unwrap();
+    let linger = Linger::new(
+        &disk, blob::Stream::new(&disk, b"x".to_vec()).as_bytestream(),
+        &write_fd, false).unwrap();
+    drop(write_fd);
+    linger.prod();
+    assert_eq!(drain(&disk, read_stream).unwrap(), b"x");
+    linger.drift();
+    assert!(matches!(linger.poll(), State::Stale));
+}
+
+#[test]
+fn aborted_linger_stops_copying() {
+    let disk = Disk::new().unwrap();
+    let (read_stream, write_fd) = pipe(&disk).unwrap();
+    let source = queue::Stream::new(&disk, None);
+    source.enqueue(blob::Stream::new(&disk, b"early".to_vec()).as_bytestream());
+    let linger = Linger::new(
+        &disk, source.as_bytestream(), &write_fd, false).unwrap();
+    drop(write_fd);
+    linger.prod();
+    let ingress = read_stream.clone();
+    let early = disk.block_on(async move {
+        let mut buf = [0u8; 100];
+        let count = ingress.read_async(&mut buf).await.unwrap();
+        buf[..count].to_vec()
+    }).unwrap();
+    assert_eq!(early, b"early");
+    assert!(matches!(linger.abort(), State::Busy));
+    assert!(matches!(linger.poll(), State::Stale));
+    source.enqueue(blob::Stream::new(&disk, b"late".to_vec()).as_bytestream());
+    source.terminate();
+    drop(linger);
+    // The write end is gone with the linger, and nothing more was copied.
+    assert_eq!(drain(&disk, read_stream).unwrap(), b"");
+}
+
+#[test]
+fn linger_reports_source_errors() {
+    let disk = Disk::new().unwrap();
+    let (_read_stream, write_fd) = pipe(&disk).unwrap();
+    let decoder = aten::stream::naivedecoder::Stream::new(
+        &disk, blob::Stream::new(&disk, b"no end".to_vec()).as_bytestream(),
+        0, None);
+    let linger = Linger::new(
+        &disk, decoder.as_bytestream(), &write_fd, false).unwrap();
+    linger.prod();
+    let result = disk.block_on(async move { linger.completion().await });
+    let err = result.unwrap().unwrap_err();
+    assert_eq!(error::errno(&err), Some(libc::EPROTO));
+}
+
+fn write_all(fd: &Fd, data: &[u8]) {
+    let count = unsafe {
+        libc::write(fd.as_raw_fd(), data.as_ptr() as *const libc::c_void,
+                    data.len())
+    };
+    assert_eq!(count, data.len() as isize);
+}
+
+#[test]
+fn linger_sends_file_range_to_pipe() {
+    let disk = Disk::new().unwrap();
+    let path = std::env::temp_dir().join(
+        format!("aten-sendfile-{}", std::process::id()));
+    let data = payload(3 << 20);
+    std::fs::write(&path, &data).unwrap();
+    let source =
+        file::Stream::open(&disk, &path, 12345, Some(2 << 20)).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let (read_stream, write_fd) = pipe(&disk).unwrap();
+    let linger = Linger::new(
+        &disk, source.as_bytestream(), &write_fd, false).unwrap();
+    drop(write_fd);
+    linger.prod();
+    assert_eq!(drain(&disk, read_stream).unwrap(),
+               &data[12345..12345 + (2 << 20)]);
+    assert!(matches!(linger.poll(), State::Final(Ok(()))));
+    assert_eq!(source.position(), Some(12345 + (2 << 20)));
+}
+
+#[test]
+fn linger_splices_pipe_into_pipe() {
+    let disk = Disk::new().unwrap();
+    let (source, source_fd) = pipe(&disk).unwrap();
+    let data = payload(30000);
+    write_all(&source_fd, &data);
+    drop(source_fd);
+    let (read_stream, write_fd) = pipe(&disk).unwrap();
+    let linger = Linger::new(&disk, source, &write_fd, false).unwrap();
+    drop(write_fd);
+    linger.prod();
+    assert_eq!(drain(&disk, read_stream).unwrap(), data);
+    assert!(matches!(linger.poll(), State::Final(Ok(()))));
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use aten::{Disk, Action, Downgradable, Upgradable};
+use aten::misc::Loopback;
+use aten::misc::linger::State;
+use aten::misc::loopback::Config;
+use aten::stream::{ByteStream, BasicStream, blob, queue};
+use common::{drain, drain_with, payload};
+
+fn blob_stream(disk: &Disk, data: &[u8]) -> ByteStream {
+    blob::Stream::new(disk, data.to_vec()).as_bytestream()
+}
+
+// Run the main loop until the egress of the loopback is done.
+// The final state is reported only once.
+fn await_egress(disk: &Disk, loopback: &Loopback) -> State {
+    let weak_disk = disk.downgrade();
+    loopback.register_egress_callback(Action::new(move || {
+        weak_disk.upped(|disk| { disk.quit(); });
+    }));
+    let mut state = loopback.poll_egress();
+    while matches!(state, State::Busy) {
+        disk.main_loop().unwrap();
+        state = loopback.poll_egress();
+    }
+    loopback.unregister_egress_callback();
+    state
+}
+
+#[test]
+fn loopback_half_close() {
+    let disk = Disk::new().unwrap();
+    let (client, server) = Loopback::pair(&disk, Config::default());
+    client.set_egress(blob_stream(&disk, b"request"));
+    assert_eq!(drain(&disk, server.get_ingress().unwrap()).unwrap(),
+               b"request");
+    assert!(matches!(await_egress(&disk, &client), State::Final(Ok(()))));
+    // The client is done talking but still listens.
+    let response = queue::Stream::new(&disk, None);
+    server.set_egress(response.as_bytestream());
+    response.enqueue(blob_stream(&disk, b"response"));
+    response.terminate();
+    assert_eq!(drain(&disk, client.get_ingress().unwrap()).unwrap(),
+               b"response");
+    assert!(matches!(await_egress(&disk, &server), State::Final(Ok(()))));
+}
+
+#[test]
+fn loopback_echo_through_own_ingress() {
+    let disk = Disk::new().unwrap();
+    let (client, server) = Loopback::pair(&disk, Config {
+        capacity: 1000,
+        ..Default::default()
+    });
+    server.set_egress(server.get_ingress().unwrap());
+    let data = payload(20000);
+    client.set_egress(blob_stream(&disk, &data));
+    assert_eq!(drain(&disk, client.get_ingress().unwrap()).unwrap(), data);
+}
+
+#[test]
+fn loopback_delivers_chunks_separately() {
+    let disk = Disk::new().unwrap();
+    let (client, server) = Loopback::pair(&disk, Config {
+        max_chunk: Some(100),
+        ..Default::default()
+    });
+    let data = payload(1000);
+    client.set_egress(blob_stream(&disk, &data));
+    let ingress = server.get_ingress().unwrap();
+    let received = disk.block_on(async move {
+        let mut sizes = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            match ingress.read_async(&mut buf).await.unwrap() {
+                0 => return sizes,
+                count => sizes.push(count),
+            }
+        }
+    }).unwrap();
+    assert_eq!(received, vec![100; 10]);
+}
+
+#[test]
+fn loopback_applies_latency() {
+    let disk = Disk::new().unwrap();
+    let (client, server) = Loopback::pair(&disk, Config {
+        latency: Duration::from_millis(30),
+        ..Default::default()
+    });
+    let start = Instant::now();
+    client.set_egress(blob_stream(&disk, b"slow"));
+    assert_eq!(drain_with(&disk, server.get_ingress().unwrap(), 2).unwrap(),
+               b"slow");
+    assert!(start.elapsed() >= Duration::from_millis(30));
+}
+
+#[test]
+fn closed_ingress_fails_peer_egress() {
+    let disk = Disk::new().unwrap();
+    let (client, server) = Loopback::pair(&disk, Config {
+        capacity: 100,
+        ..Default::default()
+    });
+    server.get_ingress().unwrap().close();
+    client.set_egress(blob_stream(&disk, &payload(1000)));
+    match await_egress(&disk, &client) {
+        State::Final(Err(err)) => {
+            assert_eq!(err.raw_os_error(), Some(libc::EPIPE));
+        }
+        state => panic!("unexpected {:?}", state),
+    }
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use aten::Disk;
+use aten::stream::{ByteStream, BasicStream, blob, queue};
+use aten::stream::{naivedecoder, naiveencoder};
+use common::{drain, drain_with};
+use proptest::prelude::*;
+
+// A terminator and an optional escape distinct from it, with a payload
+// that must not contain the terminator when there is no escape.
+fn framing() -> impl Strategy<Value = (u8, Option<u8>, Vec<u8>)> {
+    (any::<u8>(), any::<Option<u8>>())
+        .prop_filter("escape equals terminator",
+                     |(terminator, escape)| *escape != Some(*terminator))
+        .prop_flat_map(|(terminator, escape)| {
+            let byte = any::<u8>().prop_filter(
+                "unescaped terminator",
+                move |byte| escape.is_some() || *byte != terminator);
+            (Just(terminator), Just(escape),
+             prop::collection::vec(byte, 0..5000))
+        })
+}
+
+fn encode(disk: &Disk, data: &[u8], terminator: u8, escape: Option<u8>)
+          -> ByteStream {
+    naiveencoder::Stream::new(
+        disk, blob::Stream::new(disk, data.to_vec()).as_bytestream(),
+        terminator, escape).as_bytestream()
+}
+
+proptest! {
+    #[test]
+    fn decoder_inverts_encoder((terminator, escape, data) in framing(),
+                               chunk in 1usize..3000) {
+        let disk = Disk::new().unwrap();
+        let encoded = encode(&disk, &data, terminator, escape);
+        let decoder = naivedecoder::Stream::new(
+            &disk, encoded, terminator, escape);
+        let decoded =
+            drain_with(&disk, decoder.as_bytestream(), chunk).unwrap();
+        prop_assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn encoding_ends_with_single_terminator(
+        (terminator, escape, data) in framing()) {
+        let disk = Disk::new().unwrap();
+        let encoded =
+            drain(&disk, encode(&disk, &data, terminator, escape)).unwrap();
+        prop_assert_eq!(encoded.last(), Some(&terminator));
+        let mut escaped = false;
+        for &byte in &encoded[..encoded.len() - 1] {
+            prop_assert!(escaped || byte != terminator);
+            escaped = !escaped && Some(byte) == escape;
+        }
+        prop_assert!(!escaped);
+    }
+
+    #[test]
+    fn decoder_leaves_trailing_data(
+        (terminator, escape, data) in framing(),
+        trailer in prop::collection::vec(any::<u8>(), 0..1000),
+        chunk in 1usize..3000) {
+        let disk = Disk::new().unwrap();
+        let q = queue::Stream::new(&disk, None);
+        q.enqueue(encode(&disk, &data, terminator, escape));
+        q.enqueue(blob::Stream::new(&disk, trailer.clone()).as_bytestream());
+        q.terminate();
+        let decoder = naivedecoder::Stream::new(
+            &disk, q.as_bytestream(), terminator, escape);
+        let decoded =
+            drain_with(&disk, decoder.as_bytestream(), chunk).unwrap();
+        prop_assert_eq!(decoded, data);
+        let remainder = decoder.remainder().unwrap();
+        prop_assert_eq!(drain(&disk, remainder).unwrap(), trailer);
+    }
+}
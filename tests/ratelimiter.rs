@@ -0,0 +1,168 @@
+mod common;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::task::{Poll, Waker};
+use std::time::{Duration, Instant};
+
+use aten::{Disk, Action, UID, error};
+use aten::misc::RateLimiter;
+use aten::stream::{ByteStream, BasicStream, blob, pacer};
+use common::payload;
+
+// Read all streams concurrently, noting when each one ends.
+fn drain_all(disk: &Disk, streams: Vec<ByteStream>)
+             -> Vec<(Vec<u8>, Instant)> {
+    let waker: Rc<Cell<Option<Waker>>> = Default::default();
+    for stream in &streams {
+        let slot = waker.clone();
+        stream.register_callback(Action::new(move || {
+            if let Some(waker) = slot.take() {
+                waker.wake();
+            }
+        }));
+    }
+    let mut results: Vec<Option<(Vec<u8>, Instant)>> =
+        streams.iter().map(|_| None).collect();
+    let mut data: Vec<Vec<u8>> = streams.iter().map(|_| Vec::new()).collect();
+    disk.block_on(std::future::poll_fn(move |context| {
+        waker.set(Some(context.waker().clone()));
+        let mut buf = [0u8; 5000];
+        for (i, stream) in streams.iter().enumerate() {
+            while results[i].is_none() {
+                match stream.read(&mut buf) {
+                    Ok(0) => {
+                        results[i] = Some((std::mem::take(&mut data[i]),
+                                           Instant::now()));
+                    }
+                    Ok(count) => {
+                        data[i].extend_from_slice(&buf[..count]);
+                    }
+                    Err(err) if error::is_again(&err) => {
+                        break;
+                    }
+                    Err(err) => {
+                        panic!("{}", err);
+                    }
+                }
+            }
+        }
+        if results.iter().all(Option::is_some) {
+            Poll::Ready(results.drain(..).map(Option::unwrap).collect())
+        } else {
+            Poll::Pending
+        }
+    })).unwrap()
+}
+
+fn paced(disk: &Disk, limiter: &RateLimiter, data: &[u8]) -> ByteStream {
+    let wrappee = blob::Stream::new(disk, data.to_vec()).as_bytestream();
+    pacer::Stream::with_limiter(disk, wrappee, limiter, 100, 1000)
+        .unwrap().as_bytestream()
+}
+
+#[test]
+fn limiter_caps_aggregate_rate_fairly() {
+    let disk = Disk::new().unwrap();
+    let limiter = RateLimiter::new(&disk, 100000.0, 1000).unwrap();
+    let data = payload(10000);
+    let streams =
+        (0..3).map(|_| paced(&disk, &limiter, &data)).collect();
+    let start = Instant::now();
+    let results = drain_all(&disk, streams);
+    let total = start.elapsed();
+    assert!(total >= Duration::from_millis(280));
+    for (received, finish) in results {
+        assert_eq!(received, data);
+        // Served in turn, no stream gets far ahead of the others.
+        assert!(finish - start >= total * 3 / 4);
+    }
+}
+
+#[test]
+fn nested_limiter_enforces_both_limits() {
+    let disk = Disk::new().unwrap();
+    let global = RateLimiter::new(&disk, 1000000.0, 1000).unwrap();
+    let client =
+        RateLimiter::with_parent(&disk, &global, 20000.0, 1000).unwrap();
+    let data = payload(6000);
+    let start = Instant::now();
+    let results = drain_all(&disk, vec![paced(&disk, &client, &data),
+                                        paced(&disk, &global, &data)]);
+    assert_eq!(results[0].0, data);
+    assert_eq!(results[1].0, data);
+    assert!(results[0].1 - start >= Duration::from_millis(280));
+    assert!(results[1].1 < results[0].1);
+}
+
+#[test]
+fn limiter_rate_changes_take_effect() {
+    let disk = Disk::new().unwrap();
+    let limiter = RateLimiter::new(&disk, 1000.0, 1000).unwrap();
+    assert_eq!(error::errno(&limiter.set_rate(0.0).unwrap_err()),
+               Some(libc::EINVAL));
+    let data = payload(5000);
+    let stream = paced(&disk, &limiter, &data);
+    let booster = limiter.clone();
+    disk.schedule(disk.in_millis(20), Action::new(move || {
+        booster.set_rate(1000000.0).unwrap();
+    }));
+    let start = Instant::now();
+    let results = drain_all(&disk, vec![stream]);
+    assert_eq!(results[0].0, data);
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(limiter.rate(), 1000000.0);
+}
+
+#[test]
+fn stalled_consumer_does_not_hold_up_others() {
+    let disk = Disk::new().unwrap();
+    let limiter = RateLimiter::new(&disk, 50000.0, 1000).unwrap();
+    let data = payload(3000);
+    // Read once to get in line, and never again.
+    let stalled = paced(&disk, &limiter, &data);
+    let mut buf = [0u8; 10];
+    assert!(error::is_again(&stalled.read(&mut buf).unwrap_err()));
+    let active = paced(&disk, &limiter, &data);
+    let start = Instant::now();
+    let results = drain_all(&disk, vec![active]);
+    assert_eq!(results[0].0, data);
+    assert!(start.elapsed() < Duration::from_secs(1));
+    drop(stalled);
+}
+
+// Ask for 10 bytes on behalf of the consumer and, once they are
+// granted, consume them and note the consumer's name.
+fn ask(limiter: &RateLimiter, uid: UID, name: &'static str,
+       log: &Rc<RefCell<Vec<&'static str>>>) {
+    if log.borrow().contains(&name) {
+        return;
+    }
+    let retry = {
+        let limiter = limiter.clone();
+        let log = log.clone();
+        Action::new(move || { ask(&limiter, uid, name, &log); })
+    };
+    if limiter.available(uid, 10.0, &retry) >= 10.0 {
+        limiter.consume(uid, 10);
+        log.borrow_mut().push(name);
+    }
+}
+
+#[test]
+fn nested_limiter_keeps_its_place_for_waiting_consumers() {
+    let disk = Disk::new().unwrap();
+    let parent = RateLimiter::new(&disk, 10000.0, 10).unwrap();
+    let child = RateLimiter::with_parent(&disk, &parent, 1e6, 100).unwrap();
+    let sleep = disk.sleep_until(disk.now() + Duration::from_millis(20));
+    disk.block_on(sleep).unwrap();
+    let log = Rc::new(RefCell::new(Vec::new()));
+    // The first consumer empties the parent; the others have to wait.
+    ask(&child, UID::new(), "first", &log);
+    ask(&child, UID::new(), "second", &log);
+    ask(&parent, UID::new(), "outsider", &log);
+    ask(&child, UID::new(), "third", &log);
+    let sleep = disk.sleep_until(disk.now() + Duration::from_millis(60));
+    disk.block_on(sleep).unwrap();
+    assert_eq!(*log.borrow(), ["first", "second", "third", "outsider"]);
+}
@@ -0,0 +1,158 @@
+mod common;
+
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::task::{Poll, Waker};
+use std::time::{Duration, Instant};
+
+use aten::{Disk, Action, error};
+use aten::stream::{ByteStream, BasicStream, blob, queue, recorder, replay};
+use aten::stream::replay::Timing;
+use common::drain;
+
+fn scratch_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(
+        format!("aten-{}-{}.rec", name, std::process::id()))
+}
+
+// Read one chunk per wakeup, noting EAGAIN, until EOF or an error.
+fn reads(disk: &Disk, stream: ByteStream) -> Vec<Result<Vec<u8>, i32>> {
+    let waker: Rc<RefCell<Option<Waker>>> = Default::default();
+    let slot = waker.clone();
+    stream.register_callback(Action::new(move || {
+        if let Some(waker) = slot.borrow_mut().take() {
+            waker.wake();
+        }
+    }));
+    let mut log = Vec::new();
+    disk.block_on(std::future::poll_fn(move |context| {
+        let mut buf = [0u8; 1000];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => {
+                    return Poll::Ready(std::mem::take(&mut log));
+                }
+                Ok(count) => {
+                    log.push(Ok(buf[..count].to_vec()));
+                }
+                Err(err) if error::is_again(&err) => {
+                    log.push(Err(libc::EAGAIN));
+                    *waker.borrow_mut() = Some(context.waker().clone());
+                    return Poll::Pending;
+                }
+                Err(err) => {
+                    log.push(Err(err.raw_os_error().unwrap()));
+                    return Poll::Ready(std::mem::take(&mut log));
+                }
+            }
+        }
+    })).unwrap()
+}
+
+fn record_session(disk: &Disk, path: &Path)
+                  -> Vec<Result<Vec<u8>, i32>> {
+    let q = queue::Stream::new(disk, None);
+    q.enqueue(blob::Stream::new(disk, b"first".to_vec()).as_bytestream());
+    let recorder = recorder::Stream::new(disk, q.as_bytestream(), path)
+        .unwrap();
+    let producer = disk.clone();
+    disk.schedule(disk.in_millis(20), Action::new(move || {
+        q.enqueue(blob::Stream::new(&producer, b"second".to_vec())
+                  .as_bytestream());
+        q.terminate();
+    }));
+    reads(disk, recorder.as_bytestream())
+}
+
+#[test]
+fn replay_reproduces_reads() {
+    let disk = Disk::new().unwrap();
+    let path = scratch_path("reads");
+    let original = record_session(&disk, &path);
+    assert!(original.contains(&Err(libc::EAGAIN)));
+    let replayed = replay::Stream::open(&disk, &path, Timing::Virtual)
+        .unwrap();
+    assert_eq!(reads(&disk, replayed.as_bytestream()), original);
+    assert!(replayed.elapsed() >= Duration::from_millis(20));
+    assert_eq!(replayed.remaining(), 0);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn replay_in_real_time_waits() {
+    let disk = Disk::new().unwrap();
+    let path = scratch_path("real");
+    record_session(&disk, &path);
+    let replayed = replay::Stream::open(&disk, &path, Timing::Real).unwrap();
+    let start = Instant::now();
+    assert_eq!(drain(&disk, replayed.as_bytestream()).unwrap(),
+               b"firstsecond");
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn replay_splits_chunks_for_small_buffers() {
+    let disk = Disk::new().unwrap();
+    let path = scratch_path("split");
+    let data = common::payload(5000);
+    let recorder = recorder::Stream::new(
+        &disk, blob::Stream::new(&disk, data.clone()).as_bytestream(),
+        &path).unwrap();
+    assert_eq!(drain(&disk, recorder.as_bytestream()).unwrap(), data);
+    let replayed = replay::Stream::open(&disk, &path, Timing::Virtual)
+        .unwrap();
+    let mut buf = [0u8; 3000];
+    assert_eq!(replayed.read(&mut buf).unwrap(), 3000);
+    assert_eq!(replayed.read(&mut buf).unwrap(), 1096);
+    assert_eq!(&buf[..1096], &data[3000..4096]);
+    assert_eq!(drain(&disk, replayed.as_bytestream()).unwrap(),
+               &data[4096..]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn replay_reports_recorded_errors() {
+    let disk = Disk::new().unwrap();
+    let mut recording = b"ATENREC\x01".to_vec();
+    recording.extend_from_slice(&[0, 0, 2, b'o', b'k']);
+    recording.extend_from_slice(&[0, 1, libc::ECONNRESET as u8]);
+    let replayed = replay::Stream::new(&disk, &recording, Timing::Virtual)
+        .unwrap();
+    assert_eq!(reads(&disk, replayed.as_bytestream()),
+               [Ok(b"ok".to_vec()), Err(libc::ECONNRESET)]);
+}
+
+#[test]
+fn replay_rejects_malformed_recordings() {
+    let disk = Disk::new().unwrap();
+    for recording in [&b"not a recording"[..],
+                      &b"ATENREC\x01\x00\x00\x05ab"[..],
+                      &b"ATENREC\x01\x00\x07"[..]] {
+        let err = replay::Stream::new(&disk, recording, Timing::Virtual)
+            .unwrap_err();
+        assert_eq!(error::errno(&err), Some(libc::EPROTO));
+    }
+}
+
+#[test]
+fn stopped_recording_is_complete() {
+    let disk = Disk::new().unwrap();
+    let path = scratch_path("stopped");
+    let q = queue::Stream::new(&disk, None);
+    q.enqueue(blob::Stream::new(&disk, b"first".to_vec()).as_bytestream());
+    let recorder = recorder::Stream::new(&disk, q.as_bytestream(), &path)
+        .unwrap();
+    let mut buf = [0u8; 100];
+    assert_eq!(recorder.read(&mut buf).unwrap(), 5);
+    recorder.stop();
+    assert!(!recorder.is_recording());
+    let replayed = replay::Stream::open(&disk, &path, Timing::Virtual)
+        .unwrap();
+    assert_eq!(replayed.read(&mut buf).unwrap(), 5);
+    assert_eq!(&buf[..5], b"first");
+    assert_eq!(replayed.remaining(), 0);
+    drop(recorder);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,194 @@
+mod common;
+
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+use aten::{Disk, Action, Downgradable, Upgradable};
+use aten::misc::signal;
+use aten::sink::{BasicSink, rotating, transform, vec};
+use common::settle;
+
+// A fresh directory for the log files of one test.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(
+        format!("aten-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir(&dir).unwrap();
+    dir
+}
+
+fn contents(path: &Path) -> Option<Vec<u8>> {
+    std::fs::read(path).ok()
+}
+
+fn count_rotations(sink: &rotating::Sink) -> Rc<Cell<usize>> {
+    let count = Rc::new(Cell::new(0));
+    let counter = count.clone();
+    sink.register_rotation_callback(Action::new(move || {
+        counter.set(counter.get() + 1);
+    }));
+    count
+}
+
+#[test]
+fn transform_passes_data_through() {
+    let disk = Disk::new().unwrap();
+    let downstream = vec::Sink::new(&disk, None);
+    let sink = transform::Sink::new(
+        &disk, downstream.as_bytesink(), |stream| stream);
+    assert_eq!(sink.write(b"hello").unwrap(), 5);
+    settle(&disk);
+    assert_eq!(downstream.contents(), b"hello");
+}
+
+#[test]
+fn dropped_transform_releases_downstream() {
+    let disk = Disk::new().unwrap();
+    let downstream = vec::Sink::new(&disk, None);
+    let weak_downstream = downstream.downgrade();
+    let sink = transform::Sink::new(
+        &disk, downstream.as_bytesink(), |stream| stream);
+    drop(downstream);
+    sink.write(b"never closed").unwrap();
+    settle(&disk);
+    drop(sink);
+    settle(&disk);
+    assert!(weak_downstream.upgrade().is_none());
+}
+
+#[test]
+fn rotating_sink_rotates_by_size() {
+    let disk = Disk::new().unwrap();
+    let dir = scratch_dir("rotate-size");
+    let path = dir.join("log");
+    let config = rotating::Config {
+        max_size: Some(100),
+        keep: 2,
+        ..Default::default()
+    };
+    let sink = rotating::Sink::open(&disk, &path, config).unwrap();
+    let rotations = count_rotations(&sink);
+    sink.write(&[b'a'; 60]).unwrap();
+    assert_eq!(sink.size(), 60);
+    assert!(contents(&dir.join("log.1")).is_none());
+    sink.write(&[b'b'; 60]).unwrap();
+    assert_eq!(sink.size(), 0);
+    let mut first = vec![b'a'; 60];
+    first.extend_from_slice(&[b'b'; 60]);
+    assert_eq!(contents(&dir.join("log.1")).unwrap(), first);
+    sink.write(&[b'c'; 100]).unwrap();
+    sink.write(&[b'd'; 100]).unwrap();
+    assert_eq!(contents(&path).unwrap(), b"");
+    assert_eq!(contents(&dir.join("log.1")).unwrap(), [b'd'; 100]);
+    assert_eq!(contents(&dir.join("log.2")).unwrap(), [b'c'; 100]);
+    assert!(contents(&dir.join("log.3")).is_none());
+    settle(&disk);
+    assert_eq!(rotations.get(), 3);
+    sink.close().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn rotating_sink_rotates_by_age() {
+    let disk = Disk::new().unwrap();
+    let dir = scratch_dir("rotate-age");
+    let path = dir.join("log");
+    let config = rotating::Config {
+        max_age: Some(Duration::from_millis(50)),
+        ..Default::default()
+    };
+    let sink = rotating::Sink::open(&disk, &path, config).unwrap();
+    let rotations = count_rotations(&sink);
+    sink.write(b"old news").unwrap();
+    let weak_disk = disk.downgrade();
+    disk.schedule(disk.now() + Duration::from_millis(130),
+                  Action::new(move || {
+                      weak_disk.upped(|disk| { disk.quit(); });
+                  }));
+    disk.main_loop().unwrap();
+    // An empty file is not rotated.
+    assert_eq!(rotations.get(), 1);
+    assert_eq!(contents(&dir.join("log.1")).unwrap(), b"old news");
+    assert_eq!(contents(&path).unwrap(), b"");
+    assert!(contents(&dir.join("log.2")).is_none());
+    sink.close().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn rotating_sink_without_keep_discards() {
+    let disk = Disk::new().unwrap();
+    let dir = scratch_dir("rotate-discard");
+    let path = dir.join("log");
+    std::fs::write(&path, b"existing ").unwrap();
+    let config = rotating::Config {
+        max_size: Some(20),
+        keep: 0,
+        ..Default::default()
+    };
+    let sink = rotating::Sink::open(&disk, &path, config).unwrap();
+    assert_eq!(sink.size(), 9);
+    sink.write(b"appended").unwrap();
+    assert_eq!(contents(&path).unwrap(), b"existing appended");
+    sink.write(b"!!!").unwrap();
+    assert_eq!(contents(&path).unwrap(), b"");
+    assert!(contents(&dir.join("log.1")).is_none());
+    sink.close().unwrap();
+    assert_eq!(sink.write(b"late").unwrap_err().raw_os_error(),
+               Some(libc::EBADF));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn rotating_sink_reports_failed_rotation() {
+    let disk = Disk::new().unwrap();
+    let dir = scratch_dir("rotate-fail");
+    let path = dir.join("log");
+    // A nonempty directory in the way cannot be replaced by the file.
+    std::fs::create_dir(dir.join("log.1")).unwrap();
+    std::fs::write(dir.join("log.1").join("x"), b"").unwrap();
+    let config = rotating::Config {
+        max_size: Some(10),
+        keep: 1,
+        ..Default::default()
+    };
+    let sink = rotating::Sink::open(&disk, &path, config).unwrap();
+    let rotations = count_rotations(&sink);
+    assert_eq!(sink.write(&[b'a'; 20]).unwrap(), 20);
+    assert_eq!(sink.write(&[b'b'; 20]).unwrap(), 20);
+    settle(&disk);
+    assert_eq!(rotations.get(), 1);
+    assert!(sink.take_rotation_error().is_some());
+    assert!(sink.take_rotation_error().is_none());
+    std::fs::remove_dir_all(dir.join("log.1")).unwrap();
+    sink.write(b"c").unwrap();
+    assert!(sink.take_rotation_error().is_none());
+    assert_eq!(sink.size(), 0);
+    assert_eq!(contents(&dir.join("log.1")).unwrap().len(), 41);
+    sink.close().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn signal_watch_restores_disposition() {
+    let disk = Disk::new().unwrap();
+    let disposition = || {
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        unsafe {
+            libc::sigaction(libc::SIGUSR2, std::ptr::null(), &mut action)
+        };
+        action.sa_sigaction
+    };
+    unsafe { libc::signal(libc::SIGUSR2, libc::SIG_IGN) };
+    let first = signal::Watch::new(&disk, libc::SIGUSR2, Action::noop())
+        .unwrap();
+    let second = signal::Watch::new(&disk, libc::SIGUSR2, Action::noop())
+        .unwrap();
+    assert_ne!(disposition(), libc::SIG_IGN);
+    drop(first);
+    assert_ne!(disposition(), libc::SIG_IGN);
+    drop(second);
+    assert_eq!(disposition(), libc::SIG_IGN);
+}
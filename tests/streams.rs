@@ -0,0 +1,393 @@
+mod common;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Duration;
+
+use aten::{Disk, Action, Timer, error};
+use aten::stream::{ByteStream, BasicStream};
+use aten::stream::{avid, blob, farewell, naivedecoder, naiveencoder, nice};
+use aten::stream::{pacer, queue, reservoir, sub, switch};
+use common::{drain, drain_with, payload};
+
+fn blob_stream(disk: &Disk, data: &[u8]) -> ByteStream {
+    blob::Stream::new(disk, data.to_vec()).as_bytestream()
+}
+
+#[test]
+fn queue_concatenates_in_order() {
+    let disk = Disk::new().unwrap();
+    let q = queue::Stream::new(&disk, None);
+    q.enqueue(blob_stream(&disk, b"world"));
+    q.push(blob_stream(&disk, b"hello, "));
+    q.enqueue_buffer(b"!".to_vec().into()).unwrap();
+    q.terminate();
+    assert_eq!(drain(&disk, q.as_bytestream()).unwrap(), b"hello, world!");
+}
+
+#[test]
+fn queue_blocks_until_terminated() {
+    let disk = Disk::new().unwrap();
+    let q = queue::Stream::new(&disk, None);
+    q.enqueue(blob_stream(&disk, b"abc"));
+    let mut buf = [0u8; 10];
+    assert_eq!(q.read(&mut buf).unwrap(), 3);
+    assert!(error::is_again(&q.read(&mut buf).unwrap_err()));
+    q.terminate();
+    assert_eq!(q.read(&mut buf).unwrap(), 0);
+}
+
+struct Countdown {
+    disk: Disk,
+    remaining: usize,
+}
+
+impl queue::Supplier for Countdown {
+    fn supply(&mut self, _wake: &Action) -> queue::Supply {
+        if self.remaining == 0 {
+            return queue::Supply::Done;
+        }
+        self.remaining -= 1;
+        queue::Supply::Stream(
+            blob_stream(&self.disk, &[b'0' + self.remaining as u8]))
+    }
+}
+
+#[test]
+fn queue_asks_supplier_when_dry() {
+    let disk = Disk::new().unwrap();
+    let supplier = Rc::new(RefCell::new(Countdown {
+        disk: disk.clone(),
+        remaining: 3,
+    }));
+    let q = queue::Stream::new(&disk, Some(supplier));
+    assert_eq!(drain(&disk, q.as_bytestream()).unwrap(), b"210");
+}
+
+// Has nothing to offer at first; comes back with a stream a little later.
+struct Latecomer {
+    disk: Disk,
+    ready: Rc<Cell<bool>>,
+    timer: Option<Timer>,
+    delivered: bool,
+}
+
+impl queue::Supplier for Latecomer {
+    fn supply(&mut self, wake: &Action) -> queue::Supply {
+        if self.delivered {
+            return queue::Supply::Done;
+        }
+        if self.ready.get() {
+            self.delivered = true;
+            return queue::Supply::Stream(blob_stream(&self.disk, b"late"));
+        }
+        if self.timer.is_none() {
+            let ready = self.ready.clone();
+            let wake = wake.clone();
+            let when = self.disk.now() + Duration::from_millis(20);
+            self.timer = Some(self.disk.schedule(when, Action::new(move || {
+                ready.set(true);
+                wake.perform();
+            })));
+        }
+        queue::Supply::Pending
+    }
+}
+
+#[test]
+fn queue_waits_for_pending_supplier() {
+    let disk = Disk::new().unwrap();
+    let supplier = Rc::new(RefCell::new(Latecomer {
+        disk: disk.clone(),
+        ready: Rc::new(Cell::new(false)),
+        timer: None,
+        delivered: false,
+    }));
+    let q = queue::Stream::new(&disk, Some(supplier));
+    let mut buf = [0u8; 10];
+    assert!(error::is_again(&q.read(&mut buf).unwrap_err()));
+    assert_eq!(drain(&disk, q.as_bytestream()).unwrap(), b"late");
+}
+
+// Feeds the queue it supplies directly instead of returning streams.
+struct Feeder {
+    disk: Disk,
+    queue: Option<queue::Stream>,
+    remaining: usize,
+}
+
+impl queue::Supplier for Feeder {
+    fn supply(&mut self, _wake: &Action) -> queue::Supply {
+        let q = self.queue.as_ref().unwrap();
+        if self.remaining == 0 {
+            q.terminate();
+            return queue::Supply::Pending;
+        }
+        self.remaining -= 1;
+        q.enqueue(blob_stream(&self.disk, b"ab"));
+        q.enqueue(blob_stream(&self.disk, b"c"));
+        queue::Supply::Pending
+    }
+}
+
+#[test]
+fn supplier_may_enqueue_and_terminate() {
+    let disk = Disk::new().unwrap();
+    let supplier = Rc::new(RefCell::new(Feeder {
+        disk: disk.clone(),
+        queue: None,
+        remaining: 1,
+    }));
+    let q = queue::Stream::new(&disk, Some(supplier.clone()));
+    supplier.borrow_mut().queue = Some(q.clone());
+    assert_eq!(drain(&disk, q.as_bytestream()).unwrap(), b"abc");
+    supplier.borrow_mut().queue = None;
+}
+
+#[test]
+fn sub_selects_range_and_leaves_remainder() {
+    let disk = Disk::new().unwrap();
+    let data = payload(100);
+    let s = sub::Stream::new(&disk, blob_stream(&disk, &data), 10, Some(30));
+    assert!(s.remainder().is_none());
+    assert_eq!(drain_with(&disk, s.as_bytestream(), 7).unwrap(),
+               &data[10..30]);
+    let remainder = s.remainder().unwrap();
+    assert_eq!(drain(&disk, remainder).unwrap(), &data[30..]);
+}
+
+#[test]
+fn sub_without_end_runs_to_eof() {
+    let disk = Disk::new().unwrap();
+    let data = payload(100);
+    let s = sub::Stream::new(&disk, blob_stream(&disk, &data), 90, None);
+    assert_eq!(drain(&disk, s.as_bytestream()).unwrap(), &data[90..]);
+}
+
+#[test]
+fn pacer_rejects_bad_parameters() {
+    let disk = Disk::new().unwrap();
+    let wrappee = blob_stream(&disk, b"");
+    assert!(pacer::Stream::new(&disk, wrappee.clone(), 0.0, 1, 1).is_err());
+    assert!(pacer::Stream::new(&disk, wrappee.clone(), 1.0, 0, 1).is_err());
+    assert!(pacer::Stream::new(&disk, wrappee, 1.0, 2, 1).is_err());
+}
+
+#[test]
+fn pacer_limits_rate() {
+    let disk = Disk::new().unwrap();
+    let data = payload(20000);
+    let p = pacer::Stream::new(
+        &disk, blob_stream(&disk, &data), 200000.0, 1000, 2000).unwrap();
+    let start = std::time::Instant::now();
+    assert_eq!(drain(&disk, p.as_bytestream()).unwrap(), data);
+    // Everything beyond the initial burst is paced.
+    assert!(start.elapsed().as_secs_f64() >= 18000.0 / 200000.0 * 0.9);
+}
+
+#[test]
+fn reservoir_holds_everything_before_release() {
+    let disk = Disk::new().unwrap();
+    let data = payload(5000);
+    let r = reservoir::Stream::new(&disk, blob_stream(&disk, &data), 10000);
+    assert!(error::is_again(&r.replay().unwrap_err()));
+    assert_eq!(drain(&disk, r.as_bytestream()).unwrap(), data);
+    assert_eq!(r.amount(), data.len());
+    assert_eq!(drain(&disk, r.replay().unwrap()).unwrap(), data);
+}
+
+#[test]
+fn reservoir_overflows() {
+    let disk = Disk::new().unwrap();
+    let data = payload(20000);
+    let r = reservoir::Stream::new(&disk, blob_stream(&disk, &data), 10000);
+    let err = drain(&disk, r.as_bytestream()).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOSPC));
+}
+
+#[test]
+fn reservoir_spills_to_disk() {
+    let disk = Disk::new().unwrap();
+    let data = payload(50000);
+    let r = reservoir::Stream::new_spilling(
+        &disk, blob_stream(&disk, &data), 4000, 100000,
+        &std::env::temp_dir());
+    assert_eq!(drain(&disk, r.as_bytestream()).unwrap(), data);
+    assert!(r.spilled() > 0);
+    assert_eq!(drain(&disk, r.replay().unwrap()).unwrap(), data);
+}
+
+#[test]
+fn reservoir_spills_only_past_memory_limit() {
+    let disk = Disk::new().unwrap();
+    let data = payload(3000);
+    let r = reservoir::Stream::new_spilling(
+        &disk, blob_stream(&disk, &data), 4000, 100000,
+        &std::env::temp_dir());
+    assert_eq!(drain(&disk, r.as_bytestream()).unwrap(), data);
+    assert_eq!(r.spilled(), 0);
+    let data = payload(50000);
+    let r = reservoir::Stream::new_spilling(
+        &disk, blob_stream(&disk, &data), 4000, 100000,
+        &std::env::temp_dir());
+    assert_eq!(drain_with(&disk, r.as_bytestream(), 777).unwrap(), data);
+    assert_eq!(r.amount(), data.len());
+    // Whatever did not fit in memory went to the file.
+    assert!(r.spilled() >= data.len() - 4000);
+    assert!(r.spilled() <= data.len());
+    for _ in 0..2 {
+        assert_eq!(drain_with(&disk, r.replay().unwrap(), 333).unwrap(),
+                   data);
+    }
+}
+
+#[test]
+fn reservoir_spilling_respects_capacity() {
+    let disk = Disk::new().unwrap();
+    let data = payload(10000);
+    let r = reservoir::Stream::new_spilling(
+        &disk, blob_stream(&disk, &data), 1000, 10000,
+        &std::env::temp_dir());
+    assert_eq!(drain(&disk, r.as_bytestream()).unwrap(), data);
+    let data = payload(10001);
+    let r = reservoir::Stream::new_spilling(
+        &disk, blob_stream(&disk, &data), 1000, 10000,
+        &std::env::temp_dir());
+    let err = drain(&disk, r.as_bytestream()).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOSPC));
+}
+
+#[test]
+fn reservoir_reports_failure_to_spill() {
+    let disk = Disk::new().unwrap();
+    let r = reservoir::Stream::new_spilling(
+        &disk, blob_stream(&disk, &payload(5000)), 1000, 10000,
+        std::path::Path::new("/nonexistent/aten"));
+    let err = drain(&disk, r.as_bytestream()).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+}
+
+#[test]
+fn naive_round_trip() {
+    let disk = Disk::new().unwrap();
+    let data = b"a\0b\x1bc\0\0\x1b\x1b".to_vec();
+    let encoder = naiveencoder::Stream::new(
+        &disk, blob_stream(&disk, &data), 0, Some(0x1b));
+    let decoder = naivedecoder::Stream::new(
+        &disk, encoder.as_bytestream(), 0, Some(0x1b));
+    assert_eq!(drain(&disk, decoder.as_bytestream()).unwrap(), data);
+}
+
+#[test]
+fn naive_decoder_leaves_remainder() {
+    let disk = Disk::new().unwrap();
+    let encoder = naiveencoder::Stream::new(
+        &disk, blob_stream(&disk, b"head"), b'\n', None);
+    let q = queue::Stream::new(&disk, None);
+    q.enqueue(encoder.as_bytestream());
+    q.enqueue(blob_stream(&disk, b"tail"));
+    q.terminate();
+    let decoder = naivedecoder::Stream::new(
+        &disk, q.as_bytestream(), b'\n', None);
+    assert_eq!(drain(&disk, decoder.as_bytestream()).unwrap(), b"head");
+    let remainder = decoder.remainder().unwrap();
+    assert_eq!(drain(&disk, remainder).unwrap(), b"tail");
+}
+
+#[test]
+fn naive_decoder_requires_terminator() {
+    let disk = Disk::new().unwrap();
+    let decoder = naivedecoder::Stream::new(
+        &disk, blob_stream(&disk, b"unterminated"), 0, None);
+    let err = drain(&disk, decoder.as_bytestream()).unwrap_err();
+    assert_eq!(error::errno(&err), Some(libc::EPROTO));
+}
+
+#[test]
+fn nice_backs_off_after_burst() {
+    let disk = Disk::new().unwrap();
+    let data = payload(1000);
+    let n = nice::Stream::new(&disk, blob_stream(&disk, &data), 100);
+    let mut buf = [0u8; 64];
+    assert_eq!(n.read(&mut buf).unwrap(), 64);
+    assert_eq!(n.read(&mut buf).unwrap(), 64);
+    assert!(error::is_again(&n.read(&mut buf).unwrap_err()));
+    let mut rest = drain_with(&disk, n.as_bytestream(), 64).unwrap();
+    let mut got = data[..128].to_vec();
+    got.append(&mut rest);
+    assert_eq!(got, data);
+}
+
+#[test]
+fn avid_fills_the_buffer() {
+    let disk = Disk::new().unwrap();
+    let q = queue::Stream::new(&disk, None);
+    q.enqueue(blob_stream(&disk, b"0123456789"));
+    q.enqueue(blob_stream(&disk, b"abcdefghij"));
+    let a = avid::Stream::new(&disk, q.as_bytestream());
+    let mut buf = [0u8; 15];
+    assert_eq!(a.read(&mut buf).unwrap(), 15);
+    assert_eq!(&buf, b"0123456789abcde");
+    // Whatever is available is returned before running dry.
+    assert_eq!(a.read(&mut buf).unwrap(), 5);
+    assert!(error::is_again(&a.read(&mut buf).unwrap_err()));
+    q.terminate();
+    assert_eq!(a.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn switch_changes_wrappee() {
+    let disk = Disk::new().unwrap();
+    let s = switch::Stream::new(&disk, blob_stream(&disk, b"first"));
+    let mut buf = [0u8; 3];
+    assert_eq!(s.read(&mut buf).unwrap(), 3);
+    assert_eq!(&buf, b"fir");
+    s.switch(blob_stream(&disk, b"second"));
+    assert_eq!(drain(&disk, s.as_bytestream()).unwrap(), b"second");
+}
+
+#[test]
+fn switch_notifies_on_new_wrappee() {
+    let disk = Disk::new().unwrap();
+    let q = queue::Stream::new(&disk, None);
+    let s = switch::Stream::new(&disk, blob_stream(&disk, b""));
+    s.switch(q.as_bytestream());
+    let stream = s.as_bytestream();
+    let d = disk.clone();
+    let reader = disk.spawn_local(async move {
+        let mut buf = [0u8; 10];
+        let count = stream.read_async(&mut buf).await.unwrap();
+        d.quit();
+        buf[..count].to_vec()
+    }).unwrap();
+    q.enqueue(blob_stream(&disk, b"late"));
+    disk.main_loop().unwrap();
+    assert!(reader.is_finished());
+}
+
+#[test]
+fn farewell_fires_on_drop() {
+    let disk = Disk::new().unwrap();
+    let said = Rc::new(Cell::new(false));
+    let f = farewell::Stream::new(&disk, blob_stream(&disk, b"bye"));
+    let flag = said.clone();
+    f.register_farewell_callback(Action::new(move || { flag.set(true); }));
+    assert_eq!(drain(&disk, f.as_bytestream()).unwrap(), b"bye");
+    assert!(!said.get());
+    drop(f);
+    disk.block_on(async {}).unwrap();
+    assert!(said.get());
+}
+
+#[test]
+fn farewell_can_be_unregistered() {
+    let disk = Disk::new().unwrap();
+    let said = Rc::new(Cell::new(false));
+    let f = farewell::Stream::new(&disk, blob_stream(&disk, b""));
+    let flag = said.clone();
+    f.register_farewell_callback(Action::new(move || { flag.set(true); }));
+    f.unregister_farewell_callback();
+    drop(f);
+    disk.block_on(async {}).unwrap();
+    assert!(!said.get());
+}
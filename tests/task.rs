@@ -0,0 +1,187 @@
+mod common;
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::io::Write;
+use std::net::TcpListener;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::{Duration, Instant};
+
+use aten::{Disk, Action, Downgradable, Upgradable};
+use aten::misc::TcpProgress;
+use aten::stream::{BasicStream, queue};
+use common::drain;
+
+// Pending until a flag is raised by whoever holds the other end.
+#[derive(Clone, Default)]
+struct Flag(Arc<Mutex<(bool, Option<Waker>)>>);
+
+impl Flag {
+    fn raise(&self) {
+        let waker = {
+            let mut state = self.0.lock().unwrap();
+            state.0 = true;
+            state.1.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Future for Flag {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        let mut state = self.0.lock().unwrap();
+        if state.0 {
+            return Poll::Ready(());
+        }
+        state.1 = Some(context.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[test]
+fn block_on_joins_spawned_tasks() {
+    let disk = Disk::new().unwrap();
+    let handles: Vec<_> = (0..5u32).map(|i| {
+        disk.spawn_local(async move { i * i }).unwrap()
+    }).collect();
+    let outputs = disk.block_on(async move {
+        let mut outputs = Vec::new();
+        for handle in handles {
+            outputs.push(handle.await);
+        }
+        outputs
+    }).unwrap();
+    assert_eq!(outputs, vec![0, 1, 4, 9, 16]);
+}
+
+#[test]
+fn task_wakes_from_another_thread() {
+    let disk = Disk::new().unwrap();
+    let flag = Flag::default();
+    let remote = flag.clone();
+    let thread = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(20));
+        remote.raise();
+    });
+    disk.block_on(flag).unwrap();
+    thread.join().unwrap();
+}
+
+#[test]
+fn quitting_cancels_block_on() {
+    let disk = Disk::new().unwrap();
+    let weak_disk = disk.downgrade();
+    disk.schedule(disk.now() + Duration::from_millis(10),
+                  Action::new(move || {
+                      weak_disk.upped(|disk| { disk.quit(); });
+                  }));
+    let guard = Rc::new(());
+    let held = guard.clone();
+    let err = disk.block_on(async move {
+        let _held = held;
+        Flag::default().await;
+    }).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ECANCELED));
+    // The canceled task is gone along with whatever it held.
+    assert_eq!(Rc::strong_count(&guard), 1);
+    assert_eq!(disk.block_on(async { 7 }).unwrap(), 7);
+}
+
+#[test]
+fn sleepers_wake_in_order() {
+    let disk = Disk::new().unwrap();
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let start = Instant::now();
+    for (name, delay) in [("slow", 40), ("fast", 10), ("middle", 25)] {
+        let sleep = disk.sleep_until(
+            disk.now() + Duration::from_millis(delay));
+        let log = log.clone();
+        disk.spawn_local(async move {
+            sleep.await;
+            log.borrow_mut().push(name);
+        }).unwrap();
+    }
+    let sleep = disk.sleep_until(disk.now() + Duration::from_millis(60));
+    disk.block_on(sleep).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(60));
+    assert_eq!(*log.borrow(), vec!["fast", "middle", "slow"]);
+}
+
+#[test]
+fn detached_task_runs_to_completion() {
+    let disk = Disk::new().unwrap();
+    let done = Rc::new(RefCell::new(false));
+    let flag = done.clone();
+    let sleep = disk.sleep_until(disk.now() + Duration::from_millis(10));
+    let handle = disk.spawn_local(async move {
+        sleep.await;
+        *flag.borrow_mut() = true;
+    }).unwrap();
+    assert!(!handle.is_finished());
+    drop(handle);
+    let sleep = disk.sleep_until(disk.now() + Duration::from_millis(30));
+    disk.block_on(sleep).unwrap();
+    assert!(*done.borrow());
+}
+
+// A waker that does nothing but can be counted.
+struct Counter;
+
+impl Wake for Counter {
+    fn wake(self: Arc<Self>) {}
+}
+
+#[test]
+fn dropped_read_async_unregisters_its_callback() {
+    let disk = Disk::new().unwrap();
+    let q = queue::Stream::new(&disk, None);
+    let stream = q.as_bytestream();
+    let counter = Arc::new(Counter);
+    let waker = Waker::from(counter.clone());
+    let mut context = Context::from_waker(&waker);
+    let mut buf = [0u8; 16];
+    let mut read = Box::pin(stream.read_async(&mut buf));
+    assert!(read.as_mut().poll(&mut context).is_pending());
+    // Let the main loop run the kick queued by the registration.
+    let sleep = disk.sleep_until(disk.now() + Duration::from_millis(1));
+    disk.block_on(sleep).unwrap();
+    assert!(read.as_mut().poll(&mut context).is_pending());
+    drop(read);
+    drop(waker);
+    assert_eq!(Arc::strong_count(&counter), 1);
+}
+
+#[test]
+fn tcp_connect_reaches_a_listener() {
+    let disk = Disk::new().unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let pair = disk.block_on({
+        let disk = disk.clone();
+        async move { TcpProgress::connect(&disk, &address).await }
+    }).unwrap().unwrap();
+    let (mut peer, _) = listener.accept().unwrap();
+    peer.write_all(b"hello").unwrap();
+    drop(peer);
+    let ingress = pair.get_ingress().unwrap();
+    assert_eq!(drain(&disk, ingress).unwrap(), b"hello");
+}
+
+#[test]
+fn tcp_connect_reports_refusal() {
+    let disk = Disk::new().unwrap();
+    let address = TcpListener::bind("127.0.0.1:0").unwrap()
+        .local_addr().unwrap();
+    let err = disk.block_on({
+        let disk = disk.clone();
+        async move { TcpProgress::connect(&disk, &address).await }
+    }).unwrap().unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ECONNREFUSED));
+}
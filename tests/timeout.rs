@@ -0,0 +1,81 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use aten::{Disk, Action, Downgradable, Upgradable};
+use aten::stream::{BasicStream, blob, queue, timeout};
+use common::{drain, settle};
+
+// Enqueue a byte every interval, count times, then terminate.
+fn trickle(disk: &Disk, interval: Duration, count: u32) -> queue::Stream {
+    let q = queue::Stream::new(disk, None);
+    let start = disk.now();
+    for i in 1..=count {
+        let q = q.clone();
+        let weak_disk = disk.downgrade();
+        disk.schedule(start + interval * i, Action::new(move || {
+            weak_disk.upped(|disk| {
+                q.enqueue(blob::Stream::new(disk, vec![b'.']).as_bytestream());
+            });
+            if i == count {
+                q.terminate();
+            }
+        }));
+    }
+    q
+}
+
+fn is_timedout(err: &std::io::Error) -> bool {
+    err.raw_os_error() == Some(libc::ETIMEDOUT)
+}
+
+#[test]
+fn timeout_tolerates_steady_traffic() {
+    let disk = Disk::new().unwrap();
+    let q = trickle(&disk, Duration::from_millis(20), 8);
+    let stream = timeout::Stream::new(
+        &disk, q.as_bytestream(), Some(Duration::from_millis(100)), None);
+    assert_eq!(drain(&disk, stream.as_bytestream()).unwrap(), b"........");
+}
+
+#[test]
+fn timeout_expires_when_idle() {
+    let disk = Disk::new().unwrap();
+    let q = queue::Stream::new(&disk, None);
+    let start = Instant::now();
+    let stream = timeout::Stream::new(
+        &disk, q.as_bytestream(), Some(Duration::from_millis(50)), None);
+    let err = drain(&disk, stream.as_bytestream()).unwrap_err();
+    assert!(is_timedout(&err));
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[test]
+fn deadline_expires_despite_traffic() {
+    let disk = Disk::new().unwrap();
+    let q = trickle(&disk, Duration::from_millis(20), 20);
+    let start = Instant::now();
+    let deadline = disk.now() + Duration::from_millis(100);
+    let stream = timeout::Stream::new(
+        &disk, q.as_bytestream(), Some(Duration::from_millis(50)),
+        Some(deadline));
+    let err = drain(&disk, stream.as_bytestream()).unwrap_err();
+    assert!(is_timedout(&err));
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(100));
+    assert!(elapsed < Duration::from_millis(400));
+}
+
+#[test]
+fn finished_stream_does_not_time_out() {
+    let disk = Disk::new().unwrap();
+    let stream = timeout::Stream::new(
+        &disk, blob::Stream::new(&disk, b"quick".to_vec()).as_bytestream(),
+        Some(Duration::from_millis(10)),
+        Some(disk.now() + Duration::from_millis(10)));
+    assert_eq!(drain(&disk, stream.as_bytestream()).unwrap(), b"quick");
+    std::thread::sleep(Duration::from_millis(20));
+    settle(&disk);
+    let mut buf = [0u8; 10];
+    assert_eq!(stream.read(&mut buf).unwrap(), 0);
+}